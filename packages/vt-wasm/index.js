@@ -38,7 +38,7 @@ export async function initVt() {
  *
  * @param {number} cols - Terminal width in columns
  * @param {number} rows - Terminal height in rows
- * @param {number} [scrollbackLimit] - Maximum scrollback lines (optional, 0 = unlimited)
+ * @param {number} [scrollbackLimit] - Maximum scrollback lines (optional, default 0 = no scrollback)
  * @returns {object} VT instance
  */
 export function createVt(cols, rows, scrollbackLimit) {
@@ -50,7 +50,11 @@ export function createVt(cols, rows, scrollbackLimit) {
 
   return {
     feed(data) {
-      return wasmInstance.feed(data);
+      const result = wasmInstance.feed(data);
+      if ('error' in result) {
+        throw new Error(`vt-wasm feed failed: ${result.error}`);
+      }
+      return result.ok;
     },
 
     getView() {
@@ -6,7 +6,7 @@
  * once the Rust wrapper is complete.
  */
 
-import type { TerminalSnapshot, CursorPosition, TerminalSize } from './types.js';
+import type { TerminalSnapshot, CursorPosition, TerminalSize, FeedResult } from './types.js';
 
 // WASM module interface (will be provided by wasm-pack build output)
 interface VtWasmModule {
@@ -14,7 +14,7 @@ interface VtWasmModule {
 }
 
 interface WasmVtInstance {
-  feed(data: string): number[];
+  feed(data: string): FeedResult<number[]>;
   get_view(): TerminalSnapshot;
   get_all_lines(): TerminalSnapshot;
   get_cursor(): [number, number] | null; // WASM returns [col, row] array
@@ -58,6 +58,7 @@ export async function initVt(): Promise<void> {
 export interface VtInstance {
   /**
    * Feed terminal output data. Returns array of changed row indices.
+   * Throws the error message if the terminal rejects the input (e.g. avt panics).
    */
   feed(data: string): number[];
 
@@ -99,7 +100,7 @@ export interface VtInstance {
  *
  * @param cols - Terminal width in columns
  * @param rows - Terminal height in rows
- * @param scrollbackLimit - Maximum scrollback lines to retain (optional, default: no limit)
+ * @param scrollbackLimit - Maximum scrollback lines to retain (optional, default: 0, no scrollback)
  * @returns VT instance
  */
 export function createVt(cols: number, rows: number, scrollbackLimit?: number): VtInstance {
@@ -111,13 +112,17 @@ export function createVt(cols: number, rows: number, scrollbackLimit?: number):
   const wasmInstance = wasmModule.create(
     cols,
     rows,
-    scrollbackLimit ?? 0, // 0 means unlimited in avt
+    scrollbackLimit ?? 0, // the CreateOptions default: no scrollback
   );
 
   // Return typed wrapper
   return {
     feed(data: string): number[] {
-      return wasmInstance.feed(data);
+      const result = wasmInstance.feed(data);
+      if ('error' in result) {
+        throw new Error(`vt-wasm feed failed: ${result.error}`);
+      }
+      return result.ok;
     },
 
     getView(): TerminalSnapshot {
@@ -0,0 +1,147 @@
+//! Re-serialization of a terminal snapshot back into SGR-styled ANSI text.
+
+use crate::palette::{nearest_base, parse_hex};
+use crate::{ColorValue, SnapshotLine, SnapshotSpan, TerminalSnapshot};
+
+/// Render a snapshot as ANSI text. Each styled span is preceded by a reset plus its
+/// own attributes, and every line that carried styling ends with a reset.
+/// Lines are separated by CRLF so the output can be fed straight into a terminal.
+pub(crate) fn snapshot_to_ansi(snapshot: &TerminalSnapshot, trailing_newline: bool) -> String {
+    render(snapshot, trailing_newline, false)
+}
+
+/// Render a snapshot as ANSI text like `snapshot_to_ansi`, with every color first mapped
+/// to the nearest of the first `count` xterm base colors (8 or 16) and written as a basic
+/// SGR color (`31`, `44`, `94`, ...). `rgb` resolves a color to compare.
+pub(crate) fn snapshot_to_quantized_ansi(
+    mut snapshot: TerminalSnapshot,
+    trailing_newline: bool,
+    count: usize,
+    rgb: impl Fn(&ColorValue) -> Option<(u8, u8, u8)>,
+) -> String {
+    let quantize = |color: &mut Option<ColorValue>| {
+        let index = match color {
+            // Colors the reduced palette has already are kept as they are
+            Some(ColorValue::Indexed(n)) if (*n as usize) < count => return,
+            Some(color) => rgb(color).map(|rgb| nearest_base(rgb, count)),
+            None => return,
+        };
+        if let Some(index) = index {
+            *color = Some(ColorValue::Indexed(index));
+        }
+    };
+
+    for line in &mut snapshot.lines {
+        for span in &mut line.spans {
+            quantize(&mut span.fg);
+            quantize(&mut span.bg);
+        }
+    }
+
+    render(&snapshot, trailing_newline, true)
+}
+
+/// Render the lines CRLF-separated, with indices below 16 as basic SGR colors if `basic`
+fn render(snapshot: &TerminalSnapshot, trailing_newline: bool, basic: bool) -> String {
+    let mut out = String::new();
+    let last = snapshot.lines.len().saturating_sub(1);
+
+    for (i, line) in snapshot.lines.iter().enumerate() {
+        push_spans(&mut out, line, basic);
+
+        if i < last || trailing_newline {
+            out.push_str("\r\n");
+        }
+    }
+
+    out
+}
+
+/// Append one line's spans as ANSI text, ending with a reset if it carried styling
+pub(crate) fn push_line(out: &mut String, line: &SnapshotLine) {
+    push_spans(out, line, false);
+}
+
+fn push_spans(out: &mut String, line: &SnapshotLine, basic: bool) {
+    let mut styled = false;
+
+    for span in &line.spans {
+        let params = sgr_params(span, basic);
+        if !params.is_empty() {
+            out.push_str("\x1b[0;");
+            out.push_str(&params.join(";"));
+            out.push('m');
+            styled = true;
+        } else if styled {
+            out.push_str("\x1b[0m");
+            styled = false;
+        }
+        out.push_str(&span.text);
+    }
+
+    if styled {
+        out.push_str("\x1b[0m");
+    }
+}
+
+/// SGR parameters that reproduce a span's attributes and colors (empty for the default pen)
+fn sgr_params(span: &SnapshotSpan, basic: bool) -> Vec<String> {
+    let mut params: Vec<String> = [
+        (span.bold, "1"),
+        (span.faint, "2"),
+        (span.italic, "3"),
+        (span.underline, "4"),
+        (span.blink, "5"),
+        (span.rapid_blink, "6"),
+        (span.inverse, "7"),
+        (span.strikethrough, "9"),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, code)| code.to_string())
+    .collect();
+
+    if let Some(fg) = &span.fg {
+        params.extend(color_params(fg, 38, basic));
+    }
+    if let Some(bg) = &span.bg {
+        params.extend(color_params(bg, 48, basic));
+    }
+    if let Some(style) = span.underline_style {
+        // The plain `4` is in place already; the style goes in the colon form
+        if let Some(underline) = params.iter_mut().find(|param| *param == "4") {
+            *underline = format!("4:{}", style.sgr());
+        }
+    }
+    if let Some(color) = &span.underline_color {
+        params.extend(underline_color_params(color));
+    }
+
+    params
+}
+
+/// Underline color parameters in the colon form (`58:5:n` / `58:2::r:g:b`), which
+/// terminals that don't know SGR 58 skip as a whole
+fn underline_color_params(color: &ColorValue) -> Option<String> {
+    match color {
+        ColorValue::Indexed(n) => Some(format!("58:5:{}", n)),
+        ColorValue::Rgb(hex) => {
+            let (r, g, b) = parse_hex(hex)?;
+            Some(format!("58:2::{}:{}:{}", r, g, b))
+        }
+    }
+}
+
+/// Extended color parameters (`38;5;n` / `38;2;r;g;b` and their background variants),
+/// or with `basic` the 8/16-color ones (`3n` / `9n` and `4n` / `10n`) for indices below 16
+fn color_params(color: &ColorValue, base: u8, basic: bool) -> Option<String> {
+    match color {
+        ColorValue::Indexed(n @ 0..=7) if basic => Some((base - 8 + n).to_string()),
+        ColorValue::Indexed(n @ 8..=15) if basic => Some((base + 52 + n - 8).to_string()),
+        ColorValue::Indexed(n) => Some(format!("{};5;{}", base, n)),
+        ColorValue::Rgb(hex) => {
+            let (r, g, b) = parse_hex(hex)?;
+            Some(format!("{};2;{};{};{}", base, r, g, b))
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! asciinema v2 recording of the input fed to a terminal, and reading casts back.
+
+/// Fed input and size changes since `start_recording`, with timestamps
+#[derive(Clone)]
+pub(crate) struct Recording {
+    /// Wall-clock start in seconds
+    start: f64,
+    size: (usize, usize),
+    events: Vec<Event>,
+}
+
+#[derive(Clone)]
+struct Event {
+    /// Seconds since the recording started
+    time: f64,
+    /// `"o"` for output, `"r"` for a resize to `COLSxROWS`
+    code: &'static str,
+    data: String,
+}
+
+impl Recording {
+    /// Start recording a terminal of `size`, marking the initial size as the first event
+    pub fn new(size: (usize, usize)) -> Self {
+        let mut recording = Recording {
+            start: now(),
+            size,
+            events: Vec::new(),
+        };
+        recording.resize(size.0, size.1);
+        recording
+    }
+
+    pub fn output(&mut self, data: &str) {
+        self.push("o", data.to_string());
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        self.push("r", format!("{}x{}", cols, rows));
+    }
+
+    /// The recording as a cast file: the header line followed by one JSON array per event
+    pub fn to_cast(&self) -> String {
+        let (width, height) = self.size;
+        let mut cast = format!(
+            "{{\"version\": 2, \"width\": {}, \"height\": {}}}\n",
+            width, height
+        );
+
+        for event in &self.events {
+            cast.push_str(&format!(
+                "[{:.6}, \"{}\", {}]\n",
+                event.time,
+                event.code,
+                quote(&event.data)
+            ));
+        }
+
+        cast
+    }
+
+    fn push(&mut self, code: &'static str, data: String) {
+        // The wall clock can step backwards; events must not
+        let last = self.events.last().map_or(0.0, |event| event.time);
+        let time = (now() - self.start).max(last);
+
+        self.events.push(Event { time, code, data });
+    }
+}
+
+/// Something that happened at a point in a cast
+#[derive(Debug, PartialEq)]
+pub(crate) enum CastEvent {
+    Output(String),
+    Resize(usize, usize),
+}
+
+/// A parsed cast: the initial size and its events in file order
+#[derive(Debug, PartialEq)]
+pub(crate) struct Cast {
+    pub size: (usize, usize),
+    /// Events with their times. A missing or invalid time repeats the previous one, and
+    /// times never decrease, so an event recorded out of order plays with the one before it.
+    pub events: Vec<(f64, CastEvent)>,
+}
+
+/// Parse an asciinema v2 cast. Returns None without a valid header; malformed event
+/// lines and unknown event codes are skipped.
+pub(crate) fn parse_cast(cast: &str) -> Option<Cast> {
+    let mut lines = cast.lines().filter(|line| !line.trim().is_empty());
+    let header = Json::parse(lines.next()?)?;
+    let dimension = |key| match header.get(key)? {
+        Json::Number(n) if *n >= 1.0 => Some(*n as usize),
+        _ => None,
+    };
+    let size = (dimension("width")?, dimension("height")?);
+
+    let mut events = Vec::new();
+    let mut time: f64 = 0.0;
+
+    for line in lines {
+        let Some(Json::Array(items)) = Json::parse(line) else {
+            continue;
+        };
+
+        if let Some(Json::Number(t)) = items.first() {
+            if t.is_finite() {
+                time = time.max(*t);
+            }
+        }
+
+        let event = match (items.get(1), items.get(2)) {
+            (Some(Json::String(code)), Some(Json::String(data))) => match code.as_str() {
+                "o" => CastEvent::Output(data.clone()),
+                "r" | "size" => match parse_size(data) {
+                    Some((cols, rows)) => CastEvent::Resize(cols, rows),
+                    None => continue,
+                },
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        events.push((time, event));
+    }
+
+    Some(Cast { size, events })
+}
+
+/// Parse a `COLSxROWS` resize payload
+fn parse_size(data: &str) -> Option<(usize, usize)> {
+    let (cols, rows) = data.split_once('x')?;
+    let cols = cols.trim().parse().ok().filter(|&n| n > 0)?;
+    let rows = rows.trim().parse().ok().filter(|&n| n > 0)?;
+    Some((cols, rows))
+}
+
+/// Just enough JSON to read casts
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parse a complete JSON document
+    fn parse(text: &str) -> Option<Json> {
+        let mut reader = Reader {
+            chars: text.chars().peekable(),
+        };
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        reader.chars.peek().is_none().then_some(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Reader<'_> {
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+
+        match *self.chars.peek()? {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => self.string().map(Json::String),
+            't' => self.literal("true", Json::Bool(true)),
+            'f' => self.literal("false", Json::Bool(false)),
+            'n' => self.literal("null", Json::Null),
+            _ => self.number(),
+        }
+    }
+
+    fn object(&mut self) -> Option<Json> {
+        self.chars.next();
+        let mut entries = Vec::new();
+
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Some(Json::Object(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.chars.next_if_eq(&':')?;
+            entries.push((key, self.value()?));
+            self.skip_whitespace();
+
+            match self.chars.next()? {
+                ',' => continue,
+                '}' => return Some(Json::Object(entries)),
+                _ => return None,
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Json> {
+        self.chars.next();
+        let mut items = Vec::new();
+
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&']').is_some() {
+            return Some(Json::Array(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+
+            match self.chars.next()? {
+                ',' => continue,
+                ']' => return Some(Json::Array(items)),
+                _ => return None,
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.chars.next_if_eq(&'"')?;
+        let mut out = String::new();
+
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(out),
+                '\\' => match self.chars.next()? {
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'u' => out.push(self.unicode_escape()?),
+                    c => out.push(c),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// The character of a `\uXXXX` escape (the `\u` already read), joining surrogate pairs
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+
+        if (0xD800..0xDC00).contains(&high) {
+            self.chars.next_if_eq(&'\\')?;
+            self.chars.next_if_eq(&'u')?;
+            let low = self.hex4()?;
+            let code = 0x10000 + ((high - 0xD800) << 10) + (low.checked_sub(0xDC00)? & 0x3FF);
+            return char::from_u32(code);
+        }
+
+        // A lone surrogate can't be represented; keep the text readable
+        Some(char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        (0..4).try_fold(0, |code, _| {
+            Some(code * 16 + self.chars.next()?.to_digit(16)?)
+        })
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let mut text = String::new();
+
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            text.push(c);
+        }
+
+        text.parse().ok().map(Json::Number)
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Option<Json> {
+        for expected in word.chars() {
+            self.chars.next_if_eq(&expected)?;
+        }
+        Some(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+}
+
+/// Encode `s` as a JSON string literal
+pub(crate) fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Current wall-clock time in seconds
+#[cfg(target_arch = "wasm32")]
+fn now() -> f64 {
+    js_now() / 1000.0
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn js_now() -> f64;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
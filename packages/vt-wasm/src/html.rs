@@ -0,0 +1,97 @@
+//! Standalone HTML rendering of a terminal snapshot with inline styles.
+
+use crate::palette::{rgb_hex, xterm_rgb};
+use crate::{ColorValue, SnapshotSpan, TerminalSnapshot};
+
+/// Render a snapshot as a self-contained HTML fragment.
+/// Each line becomes a `<div class="line">`, each span a `<span style="...">`.
+pub(crate) fn snapshot_to_html(snapshot: &TerminalSnapshot) -> String {
+    let mut html =
+        String::from("<div class=\"terminal\" style=\"font-family:monospace;white-space:pre\">\n");
+
+    for line in &snapshot.lines {
+        html.push_str("<div class=\"line\">");
+
+        for span in &line.spans {
+            let style = span_style(span);
+            if style.is_empty() {
+                html.push_str("<span>");
+            } else {
+                html.push_str("<span style=\"");
+                html.push_str(&style);
+                html.push_str("\">");
+            }
+            push_escaped(&mut html, &span.text);
+            html.push_str("</span>");
+        }
+
+        html.push_str("</div>\n");
+    }
+
+    html.push_str("</div>\n");
+    html
+}
+
+/// Build the inline CSS for a span's attributes
+fn span_style(span: &SnapshotSpan) -> String {
+    let mut rules: Vec<String> = Vec::new();
+
+    if let Some(fg) = &span.fg {
+        rules.push(format!("color:{}", css_color(fg)));
+    }
+    if let Some(bg) = &span.bg {
+        rules.push(format!("background-color:{}", css_color(bg)));
+    }
+    if span.bold {
+        rules.push("font-weight:bold".to_string());
+    }
+    if span.faint {
+        rules.push("opacity:0.5".to_string());
+    }
+    if span.italic {
+        rules.push("font-style:italic".to_string());
+    }
+
+    let decorations: Vec<&str> = [
+        (span.underline, "underline"),
+        (span.strikethrough, "line-through"),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, name)| *name)
+    .collect();
+
+    if !decorations.is_empty() {
+        rules.push(format!("text-decoration:{}", decorations.join(" ")));
+    }
+    if span.underline {
+        if let Some(style) = span.underline_style {
+            rules.push(format!("text-decoration-style:{}", style.css()));
+        }
+        if let Some(color) = &span.underline_color {
+            rules.push(format!("text-decoration-color:{}", css_color(color)));
+        }
+    }
+
+    rules.join(";")
+}
+
+/// Resolve a snapshot color to a concrete CSS hex color
+fn css_color(color: &ColorValue) -> String {
+    match color {
+        ColorValue::Indexed(n) => rgb_hex(xterm_rgb(*n)),
+        ColorValue::Rgb(hex) => hex.clone(),
+    }
+}
+
+/// Append text with HTML special characters escaped
+fn push_escaped(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            _ => out.push(ch),
+        }
+    }
+}
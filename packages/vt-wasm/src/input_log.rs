@@ -0,0 +1,83 @@
+//! A bounded log of fed input, dumped as hex with the bytes spelled out for bug reports.
+
+use std::collections::VecDeque;
+
+/// Names of the C0 control characters, indexed by byte
+const C0_NAMES: [&str; 32] = [
+    "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "BEL", "BS", "HT", "LF", "VT", "FF", "CR",
+    "SO", "SI", "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM", "SUB", "ESC",
+    "FS", "GS", "RS", "US",
+];
+
+/// Bytes shown per dump line
+const BYTES_PER_LINE: usize = 16;
+
+/// The most recent fed bytes, up to a limit
+#[derive(Clone)]
+pub(crate) struct InputLog {
+    bytes: VecDeque<u8>,
+    /// Offset of the first kept byte in the input since logging started
+    offset: usize,
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        InputLog {
+            bytes: VecDeque::new(),
+            offset: 0,
+        }
+    }
+
+    /// Append `data`, dropping the oldest bytes beyond `limit`
+    pub fn push(&mut self, data: &str, limit: usize) {
+        self.bytes.extend(data.as_bytes());
+        self.truncate(limit);
+    }
+
+    pub fn truncate(&mut self, limit: usize) {
+        let excess = self.bytes.len().saturating_sub(limit);
+        self.bytes.drain(..excess);
+        self.offset += excess;
+    }
+
+    /// Dump the logged bytes and clear them; later bytes continue the offsets
+    pub fn take(&mut self) -> String {
+        let bytes: Vec<u8> = self.bytes.drain(..).collect();
+        let dump = hex_dump(&bytes, self.offset);
+        self.offset += bytes.len();
+        dump
+    }
+}
+
+/// Dump `bytes` in lines of an offset, the bytes in hex (padded to a full line) and the
+/// bytes spelled out, control characters by name and `SP` for a space
+fn hex_dump(bytes: &[u8], offset: usize) -> String {
+    let mut dump = String::new();
+
+    for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let names: Vec<String> = chunk.iter().map(|&byte| byte_name(byte)).collect();
+
+        dump.push_str(&format!(
+            "{:08x}  {:<width$}  {}\n",
+            offset + i * BYTES_PER_LINE,
+            hex.join(" "),
+            names.join(" "),
+            width = BYTES_PER_LINE * 3 - 1
+        ));
+    }
+
+    dump
+}
+
+/// How a byte is spelled out: printable ASCII as itself, other bytes by name or as `.`
+fn byte_name(byte: u8) -> String {
+    match byte {
+        0x00..=0x1f => C0_NAMES[byte as usize].to_string(),
+        b' ' => "SP".to_string(),
+        0x7f => "DEL".to_string(),
+        0x21..=0x7e => (byte as char).to_string(),
+        // Part of a UTF-8 sequence; the hex column has it
+        _ => ".".to_string(),
+    }
+}
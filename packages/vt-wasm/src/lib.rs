@@ -1,309 +1,7264 @@
-use avt::{Color, Vt as AvtVt};
-use serde::Serialize;
+use avt::parser::{CtcOp, DecMode, Function, Parser, TbcScope};
+use avt::terminal::{BufferType, Terminal};
+use avt::util::TextUnwrapper;
+use avt::Color;
+use cast::{CastEvent, Recording};
+use input_log::InputLog;
+use line_attrs::{LineAttr, LineAttrs, Shift};
+use overlay::{CellAttrs, LineOverlay, Overlay};
+use palette::{parse_color_spec, rgb_spec, DefaultColors, Palette};
+use row_times::RowTimes;
+use scanner::{Csi, Event, Scanner};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
 use std::panic;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 use wasm_bindgen::prelude::*;
 
+mod ansi;
+mod cast;
+mod html;
+mod input_log;
+mod line_attrs;
+mod markdown;
+mod overlay;
+mod packed;
+mod palette;
+mod row_times;
+mod rtf;
+mod scanner;
+mod search;
+mod svg;
+
+/// Reply to a primary device attributes query: VT100 with advanced video option
+const DEFAULT_DEVICE_ATTRIBUTES: &str = "\x1b[?1;2c";
+
+/// Tab stop interval avt assumes for a fresh terminal and on resize
+const AVT_TAB_WIDTH: usize = 8;
+
+/// DEC private modes avt's terminal acts on
+const AVT_DEC_MODES: [u16; 8] = [1, 6, 7, 25, 47, 1047, 1048, 1049];
+
+/// Unsupported sequences `take_unsupported` reports at most; later ones are dropped
+const MAX_UNSUPPORTED: usize = 1024;
+
+/// Time each slow blink phase (SGR 5) lasts
+const BLINK_INTERVAL_MS: f64 = 500.0;
+/// Time each rapid blink phase (SGR 6) lasts
+const RAPID_BLINK_INTERVAL_MS: f64 = 250.0;
+
+/// Bytes of recent input kept by `set_input_logging` unless set otherwise
+const DEFAULT_INPUT_LOG_LIMIT: usize = 64 * 1024;
+
+/// Bytes of input queued while paused unless `set_pause_queue_limit` says otherwise
+const DEFAULT_PAUSE_QUEUE_LIMIT: usize = 4 * 1024 * 1024;
+
+/// Scrollback lines of a terminal created without a limit, as by TS `createVt`: none
+const DEFAULT_SCROLLBACK_LIMIT: usize = 0;
+
+/// Version of the snapshot shape, carried by every snapshot as `schema_version`. Bump it
+/// whenever a snapshot, line or span field is added, removed or changes meaning.
+const SNAPSHOT_SCHEMA_VERSION: u32 = 2;
+
 /// Create a new virtual terminal instance
 #[wasm_bindgen]
 pub fn create(cols: usize, rows: usize, scrollback_limit: usize) -> Vt {
-    let vt = AvtVt::builder()
-        .size(cols, rows)
-        .scrollback_limit(scrollback_limit)
-        .build();
-    Vt { inner: vt }
+    Vt {
+        terminal: Terminal::new((cols, rows), Some(scrollback_limit)),
+        scrollback_limit,
+        scanner: Scanner::new(),
+        overlay: None,
+        line_attrs: None,
+        row_times: None,
+        cursor_style: CursorStyle::default(),
+        modes: Modes::default(),
+        scroll_region: None,
+        tab_stops: default_tab_stops(cols, AVT_TAB_WIDTH),
+        tab_width: AVT_TAB_WIDTH,
+        title: None,
+        primary_lines: None,
+        palette: Palette::default(),
+        resolve_colors: false,
+        default_colors: DefaultColors::default(),
+        explicit_default_colors: false,
+        pending_scrollback_limit: None,
+        utf8_tail: Vec::new(),
+        diff_baseline: None,
+        panic_rollback: false,
+        recording: None,
+        clipboard: None,
+        blink_clock: None,
+        bells: 0,
+        device_attributes: DEFAULT_DEVICE_ATTRIBUTES.to_string(),
+        responses: String::new(),
+        generation: 0,
+        feed_sync: false,
+        held_rows: Vec::new(),
+        input_log: None,
+        input_log_limit: DEFAULT_INPUT_LOG_LIMIT,
+        count_replacement_chars: false,
+        paused: false,
+        paused_input: String::new(),
+        paused_dropped: 0,
+        pause_queue_limit: DEFAULT_PAUSE_QUEUE_LIMIT,
+        unsupported: None,
+        scrollback_cleared: false,
+        chunked_input: String::new(),
+        chunked_offset: 0,
+        packed_changes: Vec::new(),
+    }
 }
 
-/// Virtual terminal wrapper
+/// Create a terminal that answers primary device attribute queries (`CSI c`) with
+/// `device_attributes` instead of the default VT100-with-AVO reply
 #[wasm_bindgen]
-pub struct Vt {
-    inner: AvtVt,
+pub fn create_with_device_attributes(
+    cols: usize,
+    rows: usize,
+    scrollback_limit: usize,
+    device_attributes: String,
+) -> Vt {
+    let mut vt = create(cols, rows, scrollback_limit);
+    vt.device_attributes = device_attributes;
+    vt
 }
 
+/// Create a terminal from an options object
+/// `{ cols, rows, scrollback_limit, palette?, default_fg?, default_bg?, resize_reflow? }`.
+/// Missing fields default to 80x24 with no scrollback and the xterm colors (see
+/// `CreateOptions` in types.ts). Throws for a zero size, a malformed palette or color, or
+/// `resize_reflow: false`, which isn't supported yet (avt reflows on every width change).
 #[wasm_bindgen]
-impl Vt {
-    /// Feed input to the terminal and return changed row indices.
-    /// Returns null if avt panics (e.g. unsupported sequence) instead of crashing WASM.
-    pub fn feed(&mut self, s: &str) -> JsValue {
-        let inner = &mut self.inner;
-        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
-            let changes = inner.feed_str(s);
-            changes.lines.clone()
-        }));
-        match result {
-            Ok(rows) => serde_wasm_bindgen::to_value(&rows).unwrap_or(JsValue::NULL),
-            Err(_) => JsValue::NULL,
-        }
-    }
+pub fn create_with_options(opts: JsValue) -> Result<Vt, JsValue> {
+    serde_wasm_bindgen::from_value(opts)
+        .map_err(|e| e.to_string())
+        .and_then(create_from_options)
+        .map_err(|msg| JsValue::from_str(&msg))
+}
 
-    /// Get the current terminal view as a structured snapshot (viewport only)
-    pub fn get_view(&self) -> JsValue {
-        let snapshot = create_snapshot(&self.inner);
-        serde_wasm_bindgen::to_value(&snapshot).unwrap_or(JsValue::NULL)
+fn create_from_options(options: CreateOptions) -> Result<Vt, String> {
+    if options.cols == 0 || options.rows == 0 {
+        return Err(format!(
+            "terminal size must be at least 1x1, got {}x{}",
+            options.cols, options.rows
+        ));
+    }
+    if !options.resize_reflow {
+        return Err("resize_reflow: false is not supported, avt always reflows".to_string());
     }
 
-    /// Get all lines (scrollback + viewport), trimmed of trailing empty lines.
-    /// Use this for full terminal history capture.
-    pub fn get_all_lines(&self) -> JsValue {
-        let snapshot = create_full_snapshot(&self.inner);
-        serde_wasm_bindgen::to_value(&snapshot).unwrap_or(JsValue::NULL)
+    let mut vt = create(options.cols, options.rows, options.scrollback_limit);
+
+    if let Some(palette) = &options.palette {
+        vt.palette = Palette::parse(palette)?;
     }
+    vt.default_colors =
+        DefaultColors::parse(options.default_fg.as_deref(), options.default_bg.as_deref())?;
 
-    /// Get cursor position as [col, row] or null if cursor is hidden
-    pub fn get_cursor(&self) -> JsValue {
-        let cursor = self.inner.cursor();
-        if cursor.visible {
-            let pos = [cursor.col, cursor.row];
-            serde_wasm_bindgen::to_value(&pos).unwrap_or(JsValue::NULL)
-        } else {
-            JsValue::NULL
+    Ok(vt)
+}
+
+/// Rebuild a terminal from a `dump()` string produced by another instance
+#[wasm_bindgen]
+pub fn restore(cols: usize, rows: usize, scrollback_limit: usize, dump: &str) -> Vt {
+    let mut vt = create(cols, rows, scrollback_limit);
+    let _ = vt.feed_rows(dump);
+    vt
+}
+
+/// Render the viewport of an asciinema v2 cast as it looked `at_secs` into the recording,
+/// in the `get_view` format. Output and resize events are applied in file order up to that
+/// time; returns null if the cast has no valid header.
+#[wasm_bindgen]
+pub fn render_cast_at(cast: &str, at_secs: f64) -> JsValue {
+    cast_at(cast, at_secs).map_or(JsValue::NULL, |vt| vt.get_view())
+}
+
+fn cast_at(cast: &str, at_secs: f64) -> Option<Vt> {
+    let cast = cast::parse_cast(cast)?;
+    let (cols, rows) = cast.size;
+    let mut vt = create(cols, rows, 0);
+
+    for (time, event) in cast.events {
+        if time > at_secs {
+            break;
         }
-    }
 
-    /// Resize the terminal to new dimensions
-    pub fn resize(&mut self, cols: usize, rows: usize) {
-        self.inner.resize(cols, rows);
+        match event {
+            CastEvent::Output(data) => {
+                let _ = vt.feed_rows(&data);
+            }
+            CastEvent::Resize(cols, rows) => vt.resize(cols, rows),
+        }
     }
 
-    /// Get terminal size as [cols, rows]
-    pub fn get_size(&self) -> JsValue {
-        let (cols, rows) = self.inner.size();
-        let size = [cols, rows];
-        serde_wasm_bindgen::to_value(&size).unwrap_or(JsValue::NULL)
-    }
+    Some(vt)
 }
 
-/// Serializable terminal snapshot
-#[derive(Serialize)]
-struct TerminalSnapshot {
-    cols: usize,
-    rows: usize,
-    lines: Vec<SnapshotLine>,
+/// The `schema_version` snapshots from this build carry, for the host to check it
+/// understands their shape before reading any
+#[wasm_bindgen]
+pub fn supported_schema_version() -> u32 {
+    SNAPSHOT_SCHEMA_VERSION
 }
 
-/// A line in the snapshot
-#[derive(Serialize)]
-struct SnapshotLine {
-    spans: Vec<SnapshotSpan>,
-    // Note: Line.wrapped is pub(crate) in avt, not accessible from outside.
-    // We omit it for now; wrapped line merging can be done heuristically if needed.
-}
+/// Columns `s` takes when fed to a terminal wide enough not to wrap it: what avt prints
+/// for each character, with control characters and escape sequences taking none. avt
+/// doesn't combine characters, so zero-width ones such as combining marks get a column
+/// of their own, and a wide character takes two.
+#[wasm_bindgen]
+pub fn str_width(s: &str) -> usize {
+    let mut parser = Parser::new();
 
-/// A styled span of text
-#[derive(Serialize)]
-struct SnapshotSpan {
-    text: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    fg: Option<ColorValue>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    bg: Option<ColorValue>,
-    #[serde(skip_serializing_if = "is_false")]
-    bold: bool,
-    #[serde(skip_serializing_if = "is_false")]
-    faint: bool,
-    #[serde(skip_serializing_if = "is_false")]
-    italic: bool,
-    #[serde(skip_serializing_if = "is_false")]
-    underline: bool,
-    #[serde(skip_serializing_if = "is_false")]
-    strikethrough: bool,
-    #[serde(skip_serializing_if = "is_false")]
-    blink: bool,
-    #[serde(skip_serializing_if = "is_false")]
-    inverse: bool,
+    s.chars()
+        .filter_map(|ch| match parser.feed(ch) {
+            Some(Function::Print(ch)) => Some(print_width(ch)),
+            _ => None,
+        })
+        .sum()
 }
 
-/// Color value: either a palette index (number) or RGB hex string
-#[derive(Serialize, Clone, PartialEq)]
-#[serde(untagged)]
-enum ColorValue {
-    Indexed(u8),
-    Rgb(String),
+/// Columns avt gives a printed character
+fn print_width(ch: char) -> usize {
+    match ch.width() {
+        Some(2) => 2,
+        _ => 1,
+    }
 }
 
-fn is_false(b: &bool) -> bool {
-    !b
+/// Virtual terminal wrapper
+#[wasm_bindgen]
+pub struct Vt {
+    terminal: Terminal,
+    /// Scrollback cap the terminal was built with (avt keeps it private)
+    scrollback_limit: usize,
+    /// avt's parser, also reporting the sequences its terminal ignores
+    scanner: Scanner,
+    /// Per-cell attributes avt's pen doesn't model, created on first use
+    overlay: Option<Overlay>,
+    /// DEC line attributes (double width/height), tracked from the first one set
+    line_attrs: Option<LineAttrs>,
+    /// When each viewport row last changed, tracked from the first `tick`
+    row_times: Option<RowTimes>,
+    /// Cursor style as last set by DECSCUSR (avt doesn't track it)
+    cursor_style: CursorStyle,
+    /// Input modes avt doesn't track (bracketed paste, mouse reporting, keypad)
+    modes: Modes,
+    /// Scroll margins as set by DECSTBM (avt keeps them private), None for the full screen
+    scroll_region: Option<(usize, usize)>,
+    /// Tab stop columns, ascending, mirroring avt's private list
+    tab_stops: Vec<usize>,
+    /// Interval of the tab stops set by a reset and by a resize adding columns
+    tab_width: usize,
+    /// Window title from the last OSC 0/2
+    title: Option<String>,
+    /// Primary buffer lines captured on entering the alt screen, while avt keeps them private
+    primary_lines: Option<Vec<SnapshotLine>>,
+    /// Indexed-color table used to resolve colors
+    palette: Palette,
+    /// Emit indexed colors as `#RRGGBB` resolved through `palette`
+    resolve_colors: bool,
+    /// Colors assumed for cells without an explicit fg/bg
+    default_colors: DefaultColors,
+    /// Emit `default_colors` on every span instead of omitting default fg/bg
+    explicit_default_colors: bool,
+    /// Scrollback limit to apply once the alt screen is left
+    pending_scrollback_limit: Option<usize>,
+    /// Trailing bytes of an incomplete UTF-8 sequence from the last `feed_bytes` chunk
+    utf8_tail: Vec<u8>,
+    /// Viewport lines as emitted by the last `get_view_diff` call
+    diff_baseline: Option<Vec<SnapshotLine>>,
+    /// Restore the pre-feed state when a feed panics
+    panic_rollback: bool,
+    /// Input and resizes captured since `start_recording`
+    recording: Option<Recording>,
+    /// Latest OSC 52 clipboard write not yet taken by the host
+    clipboard: Option<Clipboard>,
+    /// First and latest time passed to `tick`, in milliseconds
+    blink_clock: Option<(f64, f64)>,
+    /// Bells rung since the last `take_bell_count`
+    bells: usize,
+    /// Reply to primary device attribute queries
+    device_attributes: String,
+    /// Query replies not yet taken by the host
+    responses: String,
+    /// Bumped by every change that can alter what `get_view` returns
+    generation: u64,
+    /// Hold changed rows back while a synchronized update (`?2026`) is open
+    feed_sync: bool,
+    /// Rows changed since the open synchronized update began, not yet reported
+    held_rows: Vec<usize>,
+    /// Recent input kept for `take_input_log`, while input logging is on
+    input_log: Option<InputLog>,
+    /// Bytes of input the log keeps
+    input_log_limit: usize,
+    /// Report U+FFFD characters in fed input from `feed_detailed`
+    count_replacement_chars: bool,
+    /// Queue fed input instead of applying it, until unpaused or flushed
+    paused: bool,
+    /// Input fed while paused, not yet applied
+    paused_input: String,
+    /// Bytes dropped from the front of `paused_input` to keep it within the limit
+    paused_dropped: usize,
+    /// Bytes `paused_input` keeps
+    pause_queue_limit: usize,
+    /// Sequences neither avt nor the wrapper acted on, while capturing them
+    unsupported: Option<Vec<UnsupportedSequence>>,
+    /// ED 3 cleared scrollback during the current feed
+    scrollback_cleared: bool,
+    /// Input queued by `feed_chunked`; the bytes before `chunked_offset` are applied
+    chunked_input: String,
+    chunked_offset: usize,
+    /// Output of `feed_packed_in_place`, kept to reuse its allocation
+    packed_changes: Vec<u8>,
 }
 
-/// Create a terminal snapshot from avt's view
-fn create_snapshot(vt: &AvtVt) -> TerminalSnapshot {
-    let (cols, rows) = vt.size();
-    let mut lines = Vec::new();
+#[wasm_bindgen]
+impl Vt {
+    /// Feed input to the terminal.
+    /// Returns `{ ok: [rows] }` with changed row indices, or `{ error: message }` if avt
+    /// panics (e.g. unsupported sequence) instead of crashing WASM.
+    /// OSC 8 hyperlinks are tracked and reported as `url` on the spans they cover.
+    /// With panic rollback enabled, the error is `{ error, rolled_back: true }`.
+    pub fn feed(&mut self, s: &str) -> JsValue {
+        match self.feed_rows(s) {
+            Err(error) if self.panic_rollback => {
+                let result = RolledBack {
+                    error,
+                    rolled_back: true,
+                };
+                serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+            }
+            result => serde_wasm_bindgen::to_value(&FeedResult::from(result))
+                .unwrap_or(JsValue::NULL),
+        }
+    }
+
+    /// Feed input to the terminal and return bare changed row indices.
+    /// Returns null if avt panics, for callers that don't need the error message.
+    pub fn feed_strict(&mut self, s: &str) -> JsValue {
+        match self.feed_rows(s) {
+            Ok(rows) => serde_wasm_bindgen::to_value(&rows).unwrap_or(JsValue::NULL),
+            Err(_) => JsValue::NULL,
+        }
+    }
 
-    for line in vt.view() {
-        let spans = merge_cells_to_spans(line);
-        lines.push(SnapshotLine { spans });
+    /// Feed input and return `{ ok: { lines, scrollback_added, evicted, resized, bell } }` or
+    /// `{ error }`. `scrollback_added` counts lines that scrolled off the top of the viewport
+    /// into history; `evicted` counts lines the scrollback limit then dropped from the top of
+    /// history, so a copy of it appending the added lines drops that many oldest ones.
+    /// `bell` is set if a BEL rang (a BEL terminating an OSC string doesn't count).
+    /// `scrollback_cleared: true` is added when ED 3 (`CSI 3 J`) erased the history.
+    /// `partial: true` is added when feed sync held this feed's rows back, and
+    /// `replacement_chars` while `set_count_replacement_chars` is enabled.
+    pub fn feed_detailed(&mut self, s: &str) -> JsValue {
+        let result = FeedResult::from(self.feed_changes(s));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
     }
 
-    TerminalSnapshot { cols, rows, lines }
-}
+    /// Feed input and return `{ ok: { viewport_changes, new_scrollback } }` or `{ error }`:
+    /// the changed rows plus the lines that scrolled off the top during this feed, oldest
+    /// first, in the `get_all_lines` line format. Lines already dropped again by the
+    /// scrollback limit (a large paste) are included too. Lines leaving the alt screen
+    /// aren't scrollback and are never reported.
+    pub fn feed_and_collect_scrollback(&mut self, s: &str) -> JsValue {
+        let result = FeedResult::from(self.feed_collecting(s, true).map(|(changes, lines)| {
+            ScrollbackFeed {
+                viewport_changes: changes.lines,
+                new_scrollback: lines,
+            }
+        }));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
 
-/// Create a terminal snapshot from all lines (scrollback + viewport), trimmed of trailing empties.
-fn create_full_snapshot(vt: &AvtVt) -> TerminalSnapshot {
-    let (cols, rows) = vt.size();
-    let mut lines: Vec<SnapshotLine> = Vec::new();
+    /// Feed input and return `{ ok: { changes, responses } }` or `{ error }`: the changed rows
+    /// plus the replies to device queries in this input, for the host to write back to the
+    /// PTY straight away. Replies queued by earlier feeds are left for `take_responses`, as
+    /// are this feed's replies if it fails.
+    pub fn feed_interactive(&mut self, s: &str) -> JsValue {
+        let result = FeedResult::from(self.feed_interactive_rows(s));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
 
-    for line in vt.lines() {
-        let spans = merge_cells_to_spans(line);
-        lines.push(SnapshotLine { spans });
+    /// Feed input and return `{ ok: [{ row, min_col, max_col }] }` or `{ error }`: the
+    /// changed rows with the inclusive range of columns whose cells changed, a wide character
+    /// counting both its columns. Rows avt redrew without a visible change are left out;
+    /// rows released by feed sync span the whole width, since they changed in earlier feeds.
+    pub fn feed_with_damage(&mut self, s: &str) -> JsValue {
+        let result = FeedResult::from(self.feed_damage(s));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
     }
 
-    // Trim trailing empty lines (lines where all spans are whitespace-only)
-    while let Some(last) = lines.last() {
-        let is_empty = last.spans.is_empty()
-            || last.spans.iter().all(|s| s.text.trim().is_empty());
-        if is_empty {
-            lines.pop();
-        } else {
-            break;
+    /// Feed several chunks in order and return the sorted union of changed rows once, as
+    /// `{ ok: [rows] }`. If a chunk panics, feeding stops and `{ error, succeeded }` reports
+    /// the message and how many chunks were fed before it.
+    pub fn feed_many(&mut self, chunks: Vec<String>) -> JsValue {
+        let result = self.feed_many_rows(&chunks);
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// Feed raw bytes (e.g. a PTY chunk, passed as a `Uint8Array`) to the terminal. Returns
+    /// the same shape as `feed`. An incomplete UTF-8 sequence at the end of the chunk is
+    /// buffered until the next call; a valid chunk with nothing buffered is fed in place,
+    /// without the copies a string argument takes.
+    pub fn feed_bytes(&mut self, bytes: &[u8]) -> JsValue {
+        let result = FeedResult::from(self.feed_bytes_rows(bytes));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// `feed_bytes` under the name hosts holding a PTY `Uint8Array` look for: a drop-in for
+    /// `feed` that skips decoding the chunk to a JS string and copying it back
+    pub fn feed_u8(&mut self, data: &[u8]) -> JsValue {
+        self.feed_bytes(data)
+    }
+
+    /// Full reset, as RIS (`ESC c`): clears the screen and scrollback, homes the cursor and
+    /// restores the default pen and modes. Returns the same shape as `feed`.
+    pub fn reset(&mut self) -> JsValue {
+        let result = FeedResult::from(self.reset_rows());
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// Soft reset, as DECSTR (`CSI ! p`): restores the default pen, scroll region, cursor
+    /// visibility and keypad modes, keeping the screen, scrollback and cursor position.
+    /// Returns the same shape as `feed`.
+    pub fn soft_reset(&mut self) -> JsValue {
+        let result = FeedResult::from(self.soft_reset_rows());
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the current terminal view as a structured snapshot (viewport only)
+    pub fn get_view(&self) -> JsValue {
+        let snapshot = self.resolved(create_snapshot(self));
+        serde_wasm_bindgen::to_value(&snapshot).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the viewport like `get_view`, shaped by an options object `{ include_cursor?,
+    /// include_text_only?, merge_spans?, include_columns?, include_timestamps?,
+    /// resolve_colors? }`: `include_cursor` adds the `get_cursor_full` object as `cursor`,
+    /// `include_text_only` drops colors and attributes (leaving one span per line),
+    /// `merge_spans: false` gives every cell its own span, `include_columns` adds the grid
+    /// column each span starts at as `col` (wide characters counting two),
+    /// `include_timestamps` adds the `tick` time each row's content last changed as
+    /// `changed_at` (once `tick` was called; rows keep theirs as they scroll, a resize
+    /// changes them all) and `resolve_colors` overrides `set_resolve_colors` for this call.
+    /// Missing fields keep the `get_view` behavior; returns null for a malformed object.
+    pub fn get_view_opts(&self, opts: JsValue) -> JsValue {
+        match serde_wasm_bindgen::from_value(opts) {
+            Ok(options) => serde_wasm_bindgen::to_value(&self.view_with_options(&options))
+                .unwrap_or(JsValue::NULL),
+            Err(_) => JsValue::NULL,
         }
     }
 
-    TerminalSnapshot { cols, rows, lines }
-}
+    /// Get the viewport like `get_view` with some attributes forced off, as an options object
+    /// `{ strip_blink?, strip_faint?, strip_italic? }`, for renderers that can't show them.
+    /// `strip_blink` drops both blink speeds. Adjacent spans that then look alike are merged.
+    /// Returns null for a malformed object.
+    pub fn get_view_masked(&self, opts: JsValue) -> JsValue {
+        match serde_wasm_bindgen::from_value(opts) {
+            Ok(options) => serde_wasm_bindgen::to_value(&self.view_masked(&options))
+                .unwrap_or(JsValue::NULL),
+            Err(_) => JsValue::NULL,
+        }
+    }
 
-/// Merge consecutive cells with identical pens into spans
-fn merge_cells_to_spans(line: &avt::Line) -> Vec<SnapshotSpan> {
-    let mut spans = Vec::new();
-    let mut current_text = String::new();
-    let mut current_fg: Option<ColorValue> = None;
-    let mut current_bg: Option<ColorValue> = None;
-    let mut current_bold = false;
-    let mut current_faint = false;
-    let mut current_italic = false;
-    let mut current_underline = false;
-    let mut current_strikethrough = false;
-    let mut current_blink = false;
-    let mut current_inverse = false;
+    /// Get the viewport like `get_view`, with each line cut to columns `start_col..end_col`
+    /// for renderers showing a horizontal window of a wide terminal. Spans crossing an edge
+    /// are split, keeping their pen; half of a wide character left inside the window
+    /// becomes a space, so the window keeps its width.
+    pub fn get_view_columns(&self, start_col: usize, end_col: usize) -> JsValue {
+        let snapshot = self.view_columns(start_col, end_col);
+        serde_wasm_bindgen::to_value(&snapshot).unwrap_or(JsValue::NULL)
+    }
 
-    for cell in line.cells() {
-        // Skip zero-width cells (continuation of wide chars)
-        if cell.width() == 0 {
-            continue;
+    /// Like `get_view`, but null when the generation is still `last_gen`, so a poller can
+    /// skip serializing an unchanged screen
+    pub fn get_view_if_changed(&self, last_gen: u64) -> JsValue {
+        if self.generation == last_gen {
+            JsValue::NULL
+        } else {
+            self.get_view()
         }
+    }
 
-        let pen = cell.pen();
+    /// Counter that advances whenever a feed, resize, reset, blink phase or view setting
+    /// may have changed what `get_view` returns. Compare it with the value seen at the last
+    /// render to skip redundant `get_view` calls.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 
-        // Map colors via accessor methods
-        let fg = pen.foreground().map(|c| map_color(&c));
-        let bg = pen.background().map(|c| map_color(&c));
+    /// Hash of what the viewport shows: the size plus every cell's character, width, pen,
+    /// hyperlink, rapid blink and underline style and color, whatever the spans would merge.
+    /// Equal screens hash alike across instances and runs (FNV-1a, not randomly seeded).
+    pub fn view_hash(&self) -> u64 {
+        let (cols, rows) = self.terminal.size();
+        let mut hash = Fnv::new();
+        hash.write(&(cols as u64).to_le_bytes());
+        hash.write(&(rows as u64).to_le_bytes());
 
-        // Use Pen's boolean accessor methods
-        let bold = pen.is_bold();
-        let faint = pen.is_faint();
-        let italic = pen.is_italic();
-        let underline = pen.is_underline();
-        let strikethrough = pen.is_strikethrough();
-        let blink = pen.is_blink();
-        let inverse = pen.is_inverse();
+        for (row, line) in self.terminal.view().enumerate() {
+            let overlay = self.overlay_line(rows - 1 - row);
 
-        // Check if attributes match the current span
-        let attrs_match = fg == current_fg
-            && bg == current_bg
-            && bold == current_bold
-            && faint == current_faint
-            && italic == current_italic
-            && underline == current_underline
-            && strikethrough == current_strikethrough
-            && blink == current_blink
-            && inverse == current_inverse;
+            for (col, cell) in line.cells().iter().enumerate() {
+                let pen = cell.pen();
+                let attrs = overlay.as_ref().and_then(|overlay| overlay.get(col));
+                let flags = [
+                    pen.is_bold(),
+                    pen.is_faint(),
+                    pen.is_italic(),
+                    pen.is_underline(),
+                    pen.is_strikethrough(),
+                    pen.is_blink(),
+                    pen.is_inverse(),
+                    attrs.is_some_and(|attrs| attrs.rapid_blink),
+                ];
 
-        if attrs_match && !current_text.is_empty() {
-            // Continue current span
-            current_text.push(cell.char());
-        } else {
-            // Flush current span if non-empty
-            if !current_text.is_empty() {
-                spans.push(SnapshotSpan {
-                    text: current_text.clone(),
-                    fg: current_fg.clone(),
-                    bg: current_bg.clone(),
-                    bold: current_bold,
-                    faint: current_faint,
-                    italic: current_italic,
-                    underline: current_underline,
-                    strikethrough: current_strikethrough,
-                    blink: current_blink,
-                    inverse: current_inverse,
-                });
-                current_text.clear();
+                hash.write(&(cell.char() as u32).to_le_bytes());
+                hash.write(&[cell.width() as u8]);
+                hash.write(&[flags.iter().rev().fold(0, |bits, &on| bits << 1 | on as u8)]);
+                hash.write_color(pen.foreground());
+                hash.write_color(pen.background());
+                match attrs.and_then(|attrs| attrs.url.as_deref()) {
+                    Some(url) => {
+                        hash.write(&[1]);
+                        hash.write(&(url.len() as u64).to_le_bytes());
+                        hash.write(url.as_bytes());
+                    }
+                    None => hash.write(&[0]),
+                }
+                let style = attrs.and_then(|attrs| attrs.underline_style);
+                hash.write(&[style.map_or(0, |style| style.sgr() as u8)]);
+                match attrs.and_then(|attrs| attrs.underline_color.as_ref()) {
+                    Some(ColorValue::Indexed(n)) => hash.write(&[1, *n]),
+                    Some(ColorValue::Rgb(hex)) => {
+                        hash.write(&[2]);
+                        hash.write(hex.as_bytes());
+                    }
+                    None => hash.write(&[0]),
+                }
             }
-
-            // Start new span
-            current_text.push(cell.char());
-            current_fg = fg;
-            current_bg = bg;
-            current_bold = bold;
-            current_faint = faint;
-            current_italic = italic;
-            current_underline = underline;
-            current_strikethrough = strikethrough;
-            current_blink = blink;
-            current_inverse = inverse;
         }
+
+        hash.0
     }
 
-    // Flush final span
-    if !current_text.is_empty() {
-        spans.push(SnapshotSpan {
-            text: current_text,
-            fg: current_fg,
-            bg: current_bg,
-            bold: current_bold,
-            faint: current_faint,
-            italic: current_italic,
-            underline: current_underline,
-            strikethrough: current_strikethrough,
-            blink: current_blink,
-            inverse: current_inverse,
-        });
+    /// Get the viewport lines in the compact binary layout documented in `packed.rs`,
+    /// for renderers that read them with a `DataView` instead of walking JS objects
+    pub fn get_view_packed(&self) -> Vec<u8> {
+        packed::pack(&self.resolved(create_snapshot(self)).lines)
     }
 
-    spans
-}
+    /// Feed input and return its changes in the binary layout of `get_view_packed`, each
+    /// changed line after its row (see `packed.rs`), skipping the JS objects `feed` builds.
+    /// Throws the panic message if avt panics.
+    pub fn feed_packed(&mut self, s: &str) -> Result<Vec<u8>, JsValue> {
+        let mut out = Vec::new();
+        self.feed_into(s, &mut out).map_err(|error| JsValue::from_str(&error))?;
+        Ok(out)
+    }
 
-/// Map avt Color to serializable ColorValue
-fn map_color(color: &Color) -> ColorValue {
-    match color {
-        Color::Indexed(n) => ColorValue::Indexed(*n),
-        Color::RGB(rgb) => {
-            let hex = format!("#{:02X}{:02X}{:02X}", rgb.r, rgb.g, rgb.b);
-            ColorValue::Rgb(hex)
-        }
+    /// `feed_packed` without allocating per call: the changes are written to a buffer the
+    /// terminal keeps, and the byte length is returned. Read them as
+    /// `new Uint8Array(memory.buffer, vt.packed_changes_ptr(), length)` before the next
+    /// call into the module, which may move or overwrite them.
+    pub fn feed_packed_in_place(&mut self, s: &str) -> Result<usize, JsValue> {
+        let mut out = std::mem::take(&mut self.packed_changes);
+        let result = self.feed_into(s, &mut out);
+        self.packed_changes = out;
+        result.map_err(|error| JsValue::from_str(&error))?;
+        Ok(self.packed_changes.len())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Address in wasm memory of the changes the last `feed_packed_in_place` wrote
+    pub fn packed_changes_ptr(&self) -> *const u8 {
+        self.packed_changes.as_ptr()
+    }
 
-    #[test]
-    fn test_create_and_feed() {
-        let mut vt = create(80, 24, 0);
-        let result = vt.feed("hello");
-        // Should return changed line indices
-        assert!(!result.is_null());
+    /// Get only the viewport lines that changed since the previous call, as
+    /// `[{ row, spans, wrapped? }]`. The first call (or one after a size change or
+    /// `reset_diff_baseline`) returns every row.
+    pub fn get_view_diff(&mut self) -> JsValue {
+        let diff = self.view_diff();
+        serde_wasm_bindgen::to_value(&diff).unwrap_or(JsValue::NULL)
     }
 
-    #[test]
-    fn test_get_view() {
-        let mut vt = create(80, 24, 0);
-        vt.feed("hello world");
-        let view = vt.get_view();
-        assert!(!view.is_null());
+    /// Compare this viewport with `other`'s, returning `{ changed_rows, resized }` where
+    /// `changed_rows` holds `other`'s rows that differ, as `[{ row, spans, wrapped? }]`.
+    /// Every row is reported when the heights differ.
+    pub fn diff(&self, other: &Vt) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.snapshot_diff(other)).unwrap_or(JsValue::NULL)
     }
 
-    #[test]
-    fn test_cursor_position() {
-        let mut vt = create(80, 24, 0);
-        vt.feed("hi");
-        let cursor = vt.get_cursor();
-        assert!(!cursor.is_null());
+    /// Forget the last emitted view so the next `get_view_diff` returns every row
+    pub fn reset_diff_baseline(&mut self) {
+        self.diff_baseline = None;
+    }
+
+    /// Get all lines (scrollback + viewport), trimmed of trailing empty lines.
+    /// Use this for full terminal history capture. While the alt screen is active this
+    /// returns the primary buffer's history, so full-screen apps don't pollute it.
+    pub fn get_all_lines(&self) -> JsValue {
+        let snapshot = self.resolved(create_full_snapshot(self, true));
+        serde_wasm_bindgen::to_value(&snapshot).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get all lines like `get_all_lines` with runs of blank lines collapsed, as
+    /// `{ schema_version, cols, rows, blank_line, lines, blink_on?, rapid_blink_on? }`.
+    /// `blank_line` is the line an untouched row gives (`cols` spaces in the default colors); each
+    /// `{ empty_run: n }` entry of `lines` stands for `n` copies of it, and every other entry
+    /// is a line as in `get_all_lines`.
+    pub fn get_all_lines_rle(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.all_lines_rle()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the viewport like `get_view` with each line as one string plus the styled runs
+    /// covering it, for renderers that measure a whole line at once: `{ schema_version,
+    /// cols, rows, lines: [{ text, runs: [{ start, len, attrs }] }], blink_on?,
+    /// rapid_blink_on? }`. `start` and `len` count characters (not bytes or columns) of
+    /// `text`, and `attrs` holds the span fields other than `text`, `widths` and `col`.
+    pub fn get_view_runs(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.view_runs()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get all lines like `get_all_lines`, shaped by an options object
+    /// `{ trim_trailing_spaces? }`: `trim_trailing_spaces` drops the blanks ending each line
+    /// unless they show something (a background, inverse video, underline or strikethrough).
+    /// Returns null for a malformed object.
+    pub fn get_all_lines_opts(&self, opts: JsValue) -> JsValue {
+        match serde_wasm_bindgen::from_value(opts) {
+            Ok(options) => serde_wasm_bindgen::to_value(&self.all_lines_with_options(&options))
+                .unwrap_or(JsValue::NULL),
+            Err(_) => JsValue::NULL,
+        }
+    }
+
+    /// Like `get_all_lines`, but keeping trailing empty lines: the whole grid of scrollback
+    /// plus viewport, for exact captures
+    pub fn get_all_lines_raw(&self) -> JsValue {
+        let snapshot = self.resolved(create_full_snapshot(self, false));
+        serde_wasm_bindgen::to_value(&snapshot).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the `get_all_lines` snapshot as a JSON string. It's written out line by line
+    /// rather than built up as a whole first, so a long history is held once, as text.
+    pub fn write_all_lines_json(&self) -> String {
+        self.all_lines_json()
+    }
+
+    /// Get a single line by index into scrollback + viewport (0 = oldest scrollback line),
+    /// or null if out of range. Cheap enough to call per visible row of a virtualized list.
+    pub fn get_line(&self, row: usize) -> JsValue {
+        match self.lines_range(row, row.saturating_add(1)).pop() {
+            Some(line) => serde_wasm_bindgen::to_value(&line).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Get lines `start..end` of scrollback + viewport, clamped to the available lines
+    pub fn get_lines_range(&self, start: usize, end: usize) -> JsValue {
+        let lines = self.lines_range(start, end);
+        serde_wasm_bindgen::to_value(&lines).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get up to `count` scrollback lines starting `offset` lines below the oldest one, as
+    /// `{ lines, total }` where `total` is the scrollback length, for paging through a long
+    /// history. Only scrollback is paged, not the viewport; a page past the end is cut short
+    /// or empty.
+    pub fn get_scrollback_page(&self, offset: usize, count: usize) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.scrollback_page(offset, count)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the distinct colors the viewport's spans use, as `{ fg: [color], bg: [color] }`
+    /// in order of first use. Colors are reported as `get_view` reports them, so indexed
+    /// ones come back as `#RRGGBB` under `set_resolve_colors`; default colors are left out
+    /// unless `set_explicit_default_colors` makes them concrete.
+    pub fn collect_colors(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.used_colors()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Map viewport column `col` of `row` to the character under it in the `get_view` spans
+    /// of that row, as `{ span, char_index, byte_offset }`: the span's index, the character's
+    /// index among the span's characters and its UTF-8 byte offset in the span's text. Both
+    /// halves of a wide character map to it. Null when out of range.
+    pub fn col_to_char_index(&self, row: usize, col: usize) -> JsValue {
+        match self.span_position(row, col) {
+            Some(position) => serde_wasm_bindgen::to_value(&position).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Get the viewport cell at (col, row) as `{ char, width, fg?, bg?, bold?, ... }`, or
+    /// null if out of bounds. The right half of a wide character reports the character
+    /// it belongs to with `width: 0`.
+    pub fn get_cell(&self, col: usize, row: usize) -> JsValue {
+        match self.cell_info(col, row) {
+            Some(cell) => serde_wasm_bindgen::to_value(&cell).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Get the text of a viewport selection from (start_col, start_row) to (end_col, end_row),
+    /// both inclusive, as a newline-joined string.
+    /// Rectangular mode takes the same column range from every row. Linear mode selects like
+    /// a mouse drag: from the start to the end of the first row, whole middle rows, and the
+    /// last row up to `end_col`; trailing whitespace is trimmed and soft-wrapped rows join
+    /// without a newline. A wide character is selected when its right half is inside, so a
+    /// selection starting on that half takes the whole character, while one ending on its
+    /// left half leaves it out instead of emitting half of it.
+    pub fn get_region_text(
+        &self,
+        start_col: usize,
+        start_row: usize,
+        end_col: usize,
+        end_row: usize,
+        rectangular: bool,
+    ) -> JsValue {
+        JsValue::from_str(&self.region_text(
+            (start_col, start_row),
+            (end_col, end_row),
+            rectangular,
+        ))
+    }
+
+    /// Find `query` in scrollback and viewport, returning `[{ row, col, len }]` where `row`
+    /// counts from the oldest scrollback line and `len` is in cells. Soft-wrapped rows are
+    /// searched as one line, so a match may continue on the following row.
+    pub fn search(&self, query: &str, case_sensitive: bool) -> JsValue {
+        let matches = search::search(&self.terminal, query, case_sensitive);
+        serde_wasm_bindgen::to_value(&matches).unwrap_or(JsValue::NULL)
+    }
+
+    /// Like `search`, with a regular expression. Returns `{ ok: [{ row, col, len }] }`,
+    /// or `{ error }` if the pattern doesn't compile. Empty matches are skipped.
+    #[cfg(feature = "regex")]
+    pub fn search_regex(&self, pattern: &str, case_sensitive: bool) -> JsValue {
+        let result = FeedResult::from(search::search_regex(
+            &self.terminal,
+            pattern,
+            case_sensitive,
+        ));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the viewport as plain unstyled text, one string per row.
+    /// Much cheaper to serialize than the span structure.
+    pub fn get_text(&self) -> JsValue {
+        let text = view_text(&self.terminal);
+        serde_wasm_bindgen::to_value(&text).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the viewport as one array of grapheme clusters per row, without trailing blanks.
+    /// avt gives combining marks, joiners and the halves of a flag cells of their own; here
+    /// they regroup with their base, so `e` + U+0301 or a ZWJ family emoji is one string.
+    pub fn get_text_graphemes(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&view_graphemes(&self.terminal)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the viewport as `rows` arrays of exactly `cols` strings, one character per cell:
+    /// a space for an empty cell and "" for the column a wide character's right half covers
+    pub fn get_char_grid(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&char_grid(&self.terminal)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get all lines (scrollback + viewport) as plain text, trimmed of trailing empty lines
+    pub fn get_all_text(&self) -> JsValue {
+        let text = all_text(&self.terminal);
+        serde_wasm_bindgen::to_value(&text).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get all lines (scrollback + viewport) as logical lines, soft-wrapped rows joined, as
+    /// `[{ text, start_row, end_row }]`: `text` without trailing blanks and the inclusive
+    /// range of rows it spans, counted from the oldest scrollback line as in `search`.
+    /// Trailing empty lines are dropped, as in `get_all_text`.
+    pub fn get_logical_lines(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&logical_lines(&self.terminal)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Predict where printing `s` at the cursor would wrap, without feeding it: the index
+    /// (in characters of `s`) of each character that starts a new row. A wide character
+    /// that doesn't fit in the last column wraps whole, leaving that column blank. CR, BS
+    /// and TAB move the column as avt does, LF keeps it (newline mode off) and other
+    /// control characters and escape sequences are skipped. Autowrap (`?7`) is assumed on.
+    pub fn measure_wrap(&self, s: &str) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.wrap_breaks(s)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get cursor position as [col, row] or null if cursor is hidden. The column is always
+    /// on screen: a cursor in the pending-wrap position reports the last column. The position
+    /// is absolute, also in origin mode (DECOM), where programs address the cursor relative to
+    /// the scroll region.
+    pub fn get_cursor(&self) -> JsValue {
+        if self.terminal.cursor().visible {
+            let pos = self.cursor_position();
+            serde_wasm_bindgen::to_value(&pos).unwrap_or(JsValue::NULL)
+        } else {
+            JsValue::NULL
+        }
+    }
+
+    /// Get the row the cursor is on as `{ row, spans, wrapped?, line_attr?, cursor_col }`,
+    /// its line as `get_view` gives it, for repainting just that row when the cursor blinks.
+    /// `cursor_col` is the column `get_cursor` reports; null while the cursor is hidden.
+    pub fn get_cursor_line(&self) -> JsValue {
+        match self.cursor_line() {
+            Some(line) => serde_wasm_bindgen::to_value(&line).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Get the full cursor state as `{ col, row, visible, shape, blink, under }`, where
+    /// `shape` is "block", "underline" or "bar" as set by DECSCUSR (default: blinking block)
+    /// and `under` is the cell under the cursor as `get_cell` reports it, or null when the
+    /// cursor is past the end of its line's content (only default blanks from there on)
+    pub fn get_cursor_full(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.cursor_state()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the pen the next printed character would use, as `{ fg, bg, bold, faint, ... }`
+    /// with the span attribute names and color options. avt keeps its pen private, so
+    /// this reads it from a dump, which costs as much as `dump`.
+    pub fn get_active_pen(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.active_pen()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the cursor saved by DECSC (`ESC 7`), `CSI s` or `?1048`/`?1049` on the active
+    /// screen as `{ col, row, pen }`, `pen` as in `get_active_pen`, or null if nothing was
+    /// saved since the last reset. Reads it from a copy of the terminal, so it costs more
+    /// than a `dump`.
+    pub fn get_saved_cursor(&self) -> JsValue {
+        match self.saved_cursor() {
+            Some(saved) => serde_wasm_bindgen::to_value(&saved).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Take the latest clipboard write requested via OSC 52 as `{ selection, text }`, where
+    /// `selection` is `"c"` (clipboard) or `"p"` (primary), or null if there is none
+    pub fn take_clipboard(&mut self) -> JsValue {
+        match self.clipboard.take() {
+            Some(clipboard) => serde_wasm_bindgen::to_value(&clipboard).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Take the replies to device queries (DA, DSR status, cursor position report, OSC
+    /// 4/10/11 color queries) fed so far, for the host to write back to the PTY
+    pub fn take_responses(&mut self) -> String {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Number of bells (BEL outside OSC strings) rung since the previous call
+    pub fn take_bell_count(&mut self) -> usize {
+        std::mem::take(&mut self.bells)
+    }
+
+    /// Get the window title set via OSC 0 or OSC 2, or null if none was set
+    pub fn get_title(&self) -> JsValue {
+        match &self.title {
+            Some(title) => JsValue::from_str(title),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Input modes the host needs to encode keys, pastes and mouse events, as
+    /// `{ bracketed_paste, mouse, mouse_encoding, focus_reporting, application_cursor,
+    /// application_keypad, keyboard_flags, origin_mode }` where `mouse` is
+    /// `"off"|"x10"|"normal"|"button"|"any"` (`?9`/`?1000`/`?1002`/`?1003`), `mouse_encoding`
+    /// is `"default"|"utf8"|"sgr"|"urxvt"`, `keyboard_flags` are the active screen's kitty
+    /// keyboard protocol flags and `origin_mode` is DECOM (`?6`)
+    pub fn get_modes(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.mode_state()).unwrap_or(JsValue::NULL)
+    }
+
+    /// The character set printed characters currently go through, `"ascii"` or
+    /// `"dec_graphics"` (DEC Special Graphics, designated with `ESC ( 0` and translated to
+    /// box drawing characters), for debugging line drawing
+    pub fn get_active_charset(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.active_charset()).unwrap_or(JsValue::NULL)
+    }
+
+    /// The bytes to write to the PTY when the host window gains (`focused`) or loses focus:
+    /// `CSI I` or `CSI O` while the application enabled focus reporting (`?1004`, reported
+    /// as `focus_reporting` by `get_modes`), an empty string otherwise
+    pub fn focus_event(&self, focused: bool) -> String {
+        if !self.modes.focus_events {
+            String::new()
+        } else if focused {
+            "\x1b[I".to_string()
+        } else {
+            "\x1b[O".to_string()
+        }
+    }
+
+    /// Current kitty keyboard protocol flags (`CSI ? u`) of the active screen; pushes and pops
+    /// (`CSI > flags u`, `CSI < n u`) keep a stack per screen
+    pub fn get_keyboard_flags(&self) -> u8 {
+        self.modes.keyboard.flags
+    }
+
+    /// Get the scroll region as `{ top, bottom }`: zero-based, inclusive rows, covering the
+    /// whole screen unless DECSTBM narrowed it. A resize changing the height resets it.
+    pub fn get_scroll_region(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.scroll_region()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Whether the alternate screen buffer (`?1049`/`?1047`/`?47`) is active
+    pub fn is_alternate_screen(&self) -> bool {
+        self.terminal.active_buffer_type() == BufferType::Alternate
+    }
+
+    /// Number of lines currently held in scrollback above the viewport
+    /// (always 0 while the alt screen, which has no history, is active)
+    pub fn scrollback_len(&self) -> usize {
+        line_count(&self.terminal) - self.terminal.size().1
+    }
+
+    /// Change the scrollback cap, dropping the oldest lines beyond it (0 drops all history).
+    /// avt fixes the limit at construction, so this rebuilds the terminal from its content;
+    /// while the alt screen is active the change waits until the primary screen returns.
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        self.touch();
+        if self.is_alternate_screen() {
+            self.pending_scrollback_limit = Some(limit);
+        } else {
+            self.rebuild_terminal(limit);
+        }
+    }
+
+    /// Resize the terminal to new dimensions. Growing taller moves the newest scrollback
+    /// lines back into the top of the viewport (keeping the cursor on its line) before any
+    /// blank rows are added at the bottom; shrinking is avt's own.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        // Input queued while paused came first, so it goes in at the old size. Its changes
+        // need no reporting (a resize redraws every row), and a panic in it leaves the
+        // terminal as consistent as any failed feed, so the result is not needed.
+        if self.paused {
+            let _ = self.apply_paused_input();
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.resize(cols, rows);
+        }
+        self.touch();
+
+        // avt drops the margins whenever the height changes
+        if rows != self.terminal.size().1 {
+            self.scroll_region = None;
+        }
+
+        let old_cols = self.terminal.size().0;
+        let history_before = self.scrollback_len();
+        self.terminal.resize(cols, rows);
+        // Growing taller brings the newest history lines back into the viewport
+        let pulled = history_before.saturating_sub(self.scrollback_len());
+
+        if let Some(line_attrs) = &mut self.line_attrs {
+            line_attrs.resize(cols != old_cols, line_count(&self.terminal));
+        }
+        let now = self.clock_now();
+        if let Some(row_times) = &mut self.row_times {
+            row_times.resize(rows, now);
+        }
+
+        drop(self.terminal.gc());
+
+        if let Some(overlay) = &mut self.overlay {
+            overlay.resize(cols, rows, pulled);
+        }
+
+        // Stops past the new width go; added columns get stops at the tab width rather than
+        // avt's every 8th column
+        self.tab_stops.retain(|&col| col < cols);
+        if cols > old_cols {
+            let added = default_tab_stops(cols, self.tab_width);
+            self.tab_stops.extend(added.into_iter().filter(|&col| col >= old_cols));
+            self.sync_tab_stops();
+        }
+
+        self.held_rows.retain(|&row| row < rows);
+        self.sync_overlay();
+    }
+
+    /// Resize, re-wrapping soft-wrapped logical lines at the new width.
+    /// avt already reflows on every width change (keeping pens and anchoring the cursor to
+    /// its logical position), so this is `resize` under a name that states the guarantee.
+    /// Lines pushed above the viewport are subject to the scrollback limit.
+    pub fn resize_with_reflow(&mut self, cols: usize, rows: usize) {
+        self.resize(cols, rows);
+    }
+
+    /// Serialize the terminal state as escape sequences that recreate it when fed to `restore`
+    pub fn dump(&self) -> String {
+        let mut seq = self.terminal.dump();
+        seq.push_str(&self.modes.dump());
+        seq.push_str(self.scanner.pending());
+        seq
+    }
+
+    /// Export the terminal as a file that `cat` into a terminal of the same size shows the
+    /// same way: the screen is cleared, with `include_scrollback` the scrollback lines are
+    /// printed as ANSI text and scrolled off the top, then the screen is redrawn and the
+    /// cursor put back by `dump`'s sequences. Input modes are left out, so the terminal
+    /// showing the file keeps its own. The alt screen has no scrollback to include.
+    pub fn to_raw_capture(&self, include_scrollback: bool) -> String {
+        let keep = if include_scrollback { self.scrollback_len() } else { 0 };
+        // avt's dump writes CSI as the 8-bit C1 control, which terminals ignore in UTF-8
+        let seq = self.replay_sequence(keep).replace('\u{9b}', "\x1b[");
+        format!("\x1b[H\x1b[2J{}", seq)
+    }
+
+    /// Start (or restart) recording every fed input and resize with its timestamp
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording::new(self.terminal.size()));
+    }
+
+    /// Start or stop logging fed input for `take_input_log`; stopping discards the log.
+    /// Only the most recent bytes are kept, 64 KiB unless `set_input_log_limit` says otherwise.
+    pub fn set_input_logging(&mut self, enabled: bool) {
+        if enabled {
+            self.input_log.get_or_insert_with(InputLog::new);
+        } else {
+            self.input_log = None;
+        }
+    }
+
+    /// Keep at most `limit` bytes of logged input, dropping the oldest
+    pub fn set_input_log_limit(&mut self, limit: usize) {
+        self.input_log_limit = limit;
+        if let Some(log) = &mut self.input_log {
+            log.truncate(limit);
+        }
+    }
+
+    /// Take the input logged since the last call as a hex dump, 16 bytes per line: the
+    /// offset into the logged input, the bytes in hex, and the bytes spelled out with
+    /// control characters by name (`ESC [ 3 1 m`). Empty when logging is off.
+    pub fn take_input_log(&mut self) -> String {
+        self.input_log.as_mut().map_or_else(String::new, InputLog::take)
+    }
+
+    /// Start or stop capturing the sequences fed input uses that neither avt nor the wrapper
+    /// act on, for `take_unsupported`; stopping discards the capture
+    pub fn set_capture_unsupported(&mut self, enabled: bool) {
+        if enabled {
+            self.unsupported.get_or_insert_with(Vec::new);
+        } else {
+            self.unsupported = None;
+        }
+    }
+
+    /// Take the sequences captured since the last call as `[{ raw, kind }]`, where `raw`
+    /// is the sequence as fed (OSC strings without their terminator) and `kind` is
+    /// `"csi"`, `"esc"` or `"osc"`. A mode set or reset (`CSI ? Pm h`) counts when any of
+    /// its modes is unknown. A feed that panicked adds a `"panic"` entry with the sequence
+    /// completed last before the panic. At most 1024 entries are kept between calls.
+    pub fn take_unsupported(&mut self) -> JsValue {
+        let unsupported = self.unsupported.as_mut().map(std::mem::take).unwrap_or_default();
+        serde_wasm_bindgen::to_value(&unsupported).unwrap_or(JsValue::NULL)
+    }
+
+    /// Export the recording as an asciinema v2 cast: the header with the size at the start
+    /// of the recording, then `[time, "o", data]` per feed and `[time, "r", "COLSxROWS"]`
+    /// per resize (the initial size included). Empty if nothing was recorded.
+    pub fn export_cast(&self) -> String {
+        self.recording
+            .as_ref()
+            .map(Recording::to_cast)
+            .unwrap_or_default()
+    }
+
+    /// Render the current viewport as standalone HTML with inline styles.
+    /// Indexed colors are resolved through the palette (xterm 256-color by default), and
+    /// inverse video is applied by swapping colors (defaults included), as in every export.
+    pub fn to_html(&self) -> String {
+        let mut snapshot = self.export_snapshot();
+        resolve_snapshot_colors(&mut snapshot.lines, &self.palette);
+        html::snapshot_to_html(&snapshot)
+    }
+
+    /// Render the current viewport as a standalone SVG document on a grid of `cell_width` by
+    /// `cell_height` cells: a `<rect>` fills the background and every cell range with a
+    /// background color, and each span that shows something becomes a `<text>` with its
+    /// characters placed on their columns. Colors are resolved like `to_html`'s, with the
+    /// defaults filling the background and unstyled text.
+    pub fn to_svg(&self, cell_width: f64, cell_height: f64, font_family: &str) -> String {
+        let mut snapshot = self.export_snapshot();
+        resolve_snapshot_colors(&mut snapshot.lines, &self.palette);
+        let (fg, bg) = self.default_color_values();
+        let layout = svg::SvgLayout {
+            cell_width,
+            cell_height,
+            font_family,
+            fg,
+            bg,
+        };
+        svg::snapshot_to_svg(&snapshot, &layout)
+    }
+
+    /// Render the current viewport as an RTF document (monospace, one paragraph per line)
+    /// for pasting into word processors. Indexed colors are resolved through the palette.
+    pub fn to_rtf(&self) -> String {
+        let mut snapshot = self.export_snapshot();
+        resolve_snapshot_colors(&mut snapshot.lines, &self.palette);
+        rtf::snapshot_to_rtf(&snapshot)
+    }
+
+    /// Render the current viewport, or all lines with `include_scrollback`, as plain text in
+    /// a Markdown code fence, longer than any backtick run in the text so it can't close early
+    pub fn to_markdown(&self, include_scrollback: bool) -> String {
+        let lines = if include_scrollback {
+            all_text(&self.terminal)
+        } else {
+            view_text(&self.terminal)
+        };
+        markdown::text_to_markdown(&lines)
+    }
+
+    /// Render the current viewport back to ANSI text with SGR styling.
+    /// Lines are CRLF-separated; pass `trailing_newline = false` to omit the final one.
+    pub fn to_ansi(&self, trailing_newline: bool) -> String {
+        ansi::snapshot_to_ansi(&self.export_snapshot(), trailing_newline)
+    }
+
+    /// Render the current viewport to ANSI text like `to_ansi`, for terminals with few
+    /// colors: every color is mapped to the nearest (by RGB distance) of the 8 basic xterm
+    /// colors when `max_colors` is below 16, else of the 16, and written as `3x` / `4x`
+    /// (`9x` / `10x` for the bright ones). Indexed colors are compared in this palette.
+    pub fn to_ansi_quantized(&self, max_colors: u8, trailing_newline: bool) -> String {
+        let count = if max_colors < 16 { 8 } else { 16 };
+        let rgb = |color: &ColorValue| match color {
+            ColorValue::Indexed(n) => Some(self.palette.rgb(*n)),
+            ColorValue::Rgb(hex) => palette::parse_hex(hex),
+        };
+        ansi::snapshot_to_quantized_ansi(self.export_snapshot(), trailing_newline, count, rgb)
+    }
+
+    /// Replace the indexed-color palette with up to 256 `#RRGGBB` entries; indices past the
+    /// end keep their xterm color. Returns `{ ok: null }`, or `{ error }` for a bad length
+    /// or malformed hex, in which case the current palette is kept.
+    pub fn set_palette(&mut self, hex_colors: Vec<String>) -> JsValue {
+        let result = FeedResult::from(Palette::parse(&hex_colors).map(|palette| {
+            self.palette = palette;
+            self.touch();
+        }));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// All 256 palette entries as `#RRGGBB`, including changes made with OSC 4
+    pub fn get_palette(&self) -> Vec<String> {
+        self.palette.to_hex()
+    }
+
+    /// When enabled, the `get_view*` methods and `get_all_lines` emit indexed colors as
+    /// `#RRGGBB` strings resolved through the palette instead of bare indices
+    pub fn set_resolve_colors(&mut self, resolve: bool) {
+        self.resolve_colors = resolve;
+        self.touch();
+    }
+
+    /// Set the default foreground/background as `#RRGGBB` (null resets to palette 7 / 0).
+    /// Returns `{ ok: null }`, or `{ error }` for malformed hex, keeping the current colors.
+    pub fn set_default_colors(&mut self, fg: Option<String>, bg: Option<String>) -> JsValue {
+        let result = FeedResult::from(
+            DefaultColors::parse(fg.as_deref(), bg.as_deref()).map(|colors| {
+                self.default_colors = colors;
+                self.touch();
+            }),
+        );
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// The default foreground/background as `{ fg, bg }` `#RRGGBB` strings, including
+    /// changes made with OSC 10/11; unset colors report palette 7 / 0
+    pub fn get_default_colors(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.default_color_state()).unwrap_or(JsValue::NULL)
+    }
+
+    /// When enabled, the `get_view*` methods and `get_all_lines` give every span concrete
+    /// `fg` and `bg`, filling in the default colors. Inverse video is folded into the
+    /// emitted colors, so such spans report `inverse: false`.
+    pub fn set_explicit_default_colors(&mut self, explicit: bool) {
+        self.explicit_default_colors = explicit;
+        self.touch();
+    }
+
+    /// When enabled, every feed first checkpoints the terminal and restores it if avt
+    /// panics, so a half-applied input is never left behind. The checkpoint costs a dump
+    /// plus a scrollback replay per feed.
+    pub fn set_panic_rollback(&mut self, enabled: bool) {
+        self.panic_rollback = enabled;
+    }
+
+    /// When enabled, rows changed while a synchronized update (`CSI ? 2026 h` .. `l`) is
+    /// open are held back: feeds ending inside one report no rows (and `partial: true` from
+    /// `feed_detailed`), and the feed that closes it reports them all at once. Disabling
+    /// it hands any held rows to the next feed.
+    pub fn set_feed_sync(&mut self, enabled: bool) {
+        self.feed_sync = enabled;
+    }
+
+    /// Pause or resume applying input, for hosts whose render loop fell behind. While
+    /// paused every feed only queues its input and reports no changes; resuming applies the
+    /// queue like `flush` does and returns its result (`{ ok: { changes: [] } }` when
+    /// pausing). `resize` and `soft_reset` apply the queue before they take effect, and
+    /// `reset` discards it, so neither waits for resuming.
+    pub fn set_paused(&mut self, paused: bool) -> JsValue {
+        let result = FeedResult::from(self.pause(paused));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// Apply the input queued while paused as a single feed, staying paused, and return
+    /// `{ ok: { changes, dropped_bytes? } }` or `{ error }`: the changed rows and, when the
+    /// queue outgrew its limit, how many of its oldest bytes were dropped
+    pub fn flush(&mut self) -> JsValue {
+        let result = FeedResult::from(self.flush_paused());
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// Feed a large input in slices of about `max_chunk` bytes, so the host can yield between
+    /// them: `s` is queued behind any input still left from earlier calls, one slice is
+    /// applied, and the result is `{ ok: { changes, remaining } }` or `{ error }` with the
+    /// slice's changed rows and the bytes still queued. Call again with an empty string
+    /// until `remaining` is 0. Slices end outside escape sequences, running past
+    /// `max_chunk` to finish one (a slice holds at least one character), so other feeds
+    /// made meanwhile never land inside a sequence; their input goes ahead of what is still
+    /// queued. Only an unterminated sequence at the end of the queue is fed unfinished.
+    /// `reset` and `soft_reset` drop the queue.
+    pub fn feed_chunked(&mut self, s: &str, max_chunk: usize) -> JsValue {
+        let result = FeedResult::from(self.feed_chunk(s, max_chunk));
+        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    }
+
+    /// Queue at most `limit` bytes of input while paused (4 MiB by default), dropping the
+    /// oldest
+    pub fn set_pause_queue_limit(&mut self, limit: usize) {
+        self.pause_queue_limit = limit;
+        self.truncate_paused_input();
+    }
+
+    /// When enabled, `feed_detailed` adds `replacement_chars`: how many U+FFFD characters
+    /// the input held, a sign that it was decoded from bytes that weren't valid UTF-8
+    pub fn set_count_replacement_chars(&mut self, enabled: bool) {
+        self.count_replacement_chars = enabled;
+    }
+
+    /// Whether a synchronized update (`?2026`) is open
+    pub fn in_synchronized_update(&self) -> bool {
+        self.modes.synchronized_update
+    }
+
+    /// Advance the blink clock to `now_ms` (any monotonic clock, e.g. `performance.now()`).
+    /// Once ticked, snapshots carry `blink_on` and `rapid_blink_on`, the phases every
+    /// `blink` / `rapid_blink` span should share; both start on at the first tick.
+    /// Feeds from then on also mark the rows they change with the latest tick's time, for
+    /// `get_view_opts` with `include_timestamps`.
+    pub fn tick(&mut self, now_ms: f64) {
+        let phases = |vt: &Vt| {
+            (
+                vt.blink_phase(BLINK_INTERVAL_MS),
+                vt.blink_phase(RAPID_BLINK_INTERVAL_MS),
+            )
+        };
+        let before = phases(self);
+        let start = self.blink_clock.map_or(now_ms, |(start, _)| start);
+        self.blink_clock = Some((start, now_ms.max(start)));
+
+        if self.row_times.is_none() {
+            let (_, rows) = self.terminal.size();
+            self.row_times = Some(RowTimes::new(rows, now_ms));
+        }
+
+        if phases(self) != before {
+            self.touch();
+        }
+    }
+
+    /// Duplicate the terminal, scrollback and all wrapper state into an independent
+    /// instance. avt's terminal isn't `Clone`, so the copy is rebuilt from a dump plus a
+    /// scrollback replay; while the alt screen is active the primary screen's history can't
+    /// be replayed and is only kept for `get_all_lines`.
+    pub fn clone_vt(&self) -> Vt {
+        let pending = self.scanner.pending();
+        let replay = self.replay_sequence(self.scrollback_limit) + pending;
+        let (terminal, scanner) =
+            replay_terminal(self.terminal.size(), self.scrollback_limit, &replay);
+
+        Vt {
+            terminal,
+            scrollback_limit: self.scrollback_limit,
+            scanner,
+            overlay: self.overlay.as_ref().map(|overlay| overlay.duplicate(pending)),
+            line_attrs: self.line_attrs.clone(),
+            row_times: self.row_times.clone(),
+            cursor_style: self.cursor_style,
+            modes: self.modes,
+            scroll_region: self.scroll_region,
+            tab_stops: self.tab_stops.clone(),
+            tab_width: self.tab_width,
+            title: self.title.clone(),
+            primary_lines: self.primary_lines.clone(),
+            palette: self.palette.clone(),
+            resolve_colors: self.resolve_colors,
+            default_colors: self.default_colors.clone(),
+            explicit_default_colors: self.explicit_default_colors,
+            pending_scrollback_limit: self.pending_scrollback_limit,
+            utf8_tail: self.utf8_tail.clone(),
+            diff_baseline: self.diff_baseline.clone(),
+            panic_rollback: self.panic_rollback,
+            recording: self.recording.clone(),
+            clipboard: self.clipboard.clone(),
+            blink_clock: self.blink_clock,
+            bells: self.bells,
+            device_attributes: self.device_attributes.clone(),
+            responses: self.responses.clone(),
+            generation: self.generation,
+            feed_sync: self.feed_sync,
+            held_rows: self.held_rows.clone(),
+            input_log: self.input_log.clone(),
+            input_log_limit: self.input_log_limit,
+            count_replacement_chars: self.count_replacement_chars,
+            paused: self.paused,
+            paused_input: self.paused_input.clone(),
+            paused_dropped: self.paused_dropped,
+            pause_queue_limit: self.pause_queue_limit,
+            unsupported: self.unsupported.clone(),
+            scrollback_cleared: self.scrollback_cleared,
+            chunked_input: self.chunked_input[self.chunked_offset..].to_string(),
+            chunked_offset: 0,
+            packed_changes: Vec::new(),
+        }
+    }
+
+    /// Get the tab stops as an ascending array of zero-based columns
+    pub fn get_tab_stops(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.tab_stops).unwrap_or(JsValue::NULL)
+    }
+
+    /// Set the interval of the tab stops a reset (RIS or `reset`) restores and a resize adds
+    /// to new columns (8 by default, 0 is read as 1). The current stops are left alone.
+    pub fn set_default_tab_width(&mut self, n: usize) {
+        self.tab_width = n.max(1);
+    }
+
+    /// Get terminal size as [cols, rows]
+    pub fn get_size(&self) -> JsValue {
+        let (cols, rows) = self.terminal.size();
+        let size = [cols, rows];
+        serde_wasm_bindgen::to_value(&size).unwrap_or(JsValue::NULL)
+    }
+}
+
+impl Vt {
+    /// Feed a string to avt, returning the full change set or the panic message
+    fn feed_changes(&mut self, s: &str) -> Result<FeedChanges, String> {
+        self.feed_collecting(s, false).map(|(changes, _)| changes)
+    }
+
+    /// Feed a string to avt, returning the full change set and, when `collect` is set, the
+    /// lines it pushed into scrollback, oldest first
+    fn feed_collecting(
+        &mut self,
+        s: &str,
+        collect: bool,
+    ) -> Result<(FeedChanges, Vec<SnapshotLine>), String> {
+        if self.paused {
+            self.paused_input.push_str(s);
+            self.truncate_paused_input();
+            return Ok((FeedChanges::default(), Vec::new()));
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.output(s);
+        }
+        if let Some(log) = &mut self.input_log {
+            log.push(s, self.input_log_limit);
+        }
+        if !s.is_empty() {
+            self.touch();
+        }
+        let replacement_chars = self
+            .count_replacement_chars
+            .then(|| s.chars().filter(|&ch| ch == char::REPLACEMENT_CHARACTER).count());
+
+        self.guarded(|vt| {
+            let size_before = vt.terminal.size();
+            let len_before = line_count(&vt.terminal);
+            let history_before = vt.scrollback_len();
+            let bells_before = vt.bells;
+            vt.scrollback_cleared = false;
+
+            if let Some(row_times) = &mut vt.row_times {
+                row_times.begin(vt.terminal.view(), len_before);
+            }
+
+            for ch in s.chars() {
+                vt.feed_char(ch);
+            }
+
+            let now = vt.clock_now();
+            if let Some(row_times) = &mut vt.row_times {
+                row_times.finish(vt.terminal.view(), now);
+            }
+
+            let changed = vt.terminal.changes();
+            let (lines, partial) = vt.hold_rows(changed);
+            // Lines trimmed beyond the scrollback limit were still added to history first
+            let mut evicted = Vec::new();
+            let mut evicted_count = 0;
+            for line in vt.terminal.gc() {
+                evicted_count += 1;
+                if collect {
+                    evicted.push(snapshot_line(&line, None, LineAttr::Normal));
+                }
+            }
+            let len_after = line_count(&vt.terminal);
+            vt.sync_overlay();
+
+            let scrollback_added = (len_after + evicted_count).saturating_sub(len_before);
+            let new_scrollback = if collect && !vt.is_alternate_screen() {
+                vt.new_scrollback(evicted, history_before, scrollback_added)
+            } else {
+                Vec::new()
+            };
+
+            let changes = FeedChanges {
+                lines,
+                scrollback_added,
+                // Lines leaving the alt screen were never history
+                evicted: if vt.is_alternate_screen() { 0 } else { evicted_count },
+                resized: vt.terminal.size() != size_before,
+                bell: vt.bells != bells_before,
+                scrollback_cleared: vt.scrollback_cleared,
+                partial,
+                replacement_chars,
+            };
+            (changes, new_scrollback)
+        })
+    }
+
+    fn pause(&mut self, paused: bool) -> Result<PausedFlush, String> {
+        self.paused = paused;
+        if paused {
+            Ok(PausedFlush::default())
+        } else {
+            self.flush_paused()
+        }
+    }
+
+    /// Apply and clear the input queued while paused
+    fn flush_paused(&mut self) -> Result<PausedFlush, String> {
+        let dropped_bytes = std::mem::take(&mut self.paused_dropped);
+        let changes = self.apply_paused_input();
+
+        Ok(PausedFlush {
+            changes: changes?,
+            dropped_bytes,
+        })
+    }
+
+    /// Feed the input queued while paused, leaving the dropped byte count for `flush`
+    fn apply_paused_input(&mut self) -> Result<Vec<usize>, String> {
+        let input = std::mem::take(&mut self.paused_input);
+        self.feed_unpaused(&input)
+    }
+
+    /// Feed `s` even while paused, without touching the queue
+    fn feed_unpaused(&mut self, s: &str) -> Result<Vec<usize>, String> {
+        let paused = std::mem::replace(&mut self.paused, false);
+        let changes = self.feed_rows(s);
+        self.paused = paused;
+        changes
+    }
+
+    /// Drop the oldest queued input beyond the limit, cutting at a character boundary
+    fn truncate_paused_input(&mut self) {
+        let input = &mut self.paused_input;
+        let Some(excess) = input.len().checked_sub(self.pause_queue_limit) else {
+            return;
+        };
+        let cut = (excess..=input.len())
+            .find(|&i| input.is_char_boundary(i))
+            .unwrap_or(input.len());
+        input.drain(..cut);
+        self.paused_dropped += cut;
+    }
+
+    /// The `added` lines a feed pushed into a history that held `history_before` lines,
+    /// given the lines evicted from its top afterwards. Evicted lines go without overlay
+    /// attributes, which leave with them.
+    fn new_scrollback(
+        &self,
+        evicted: Vec<SnapshotLine>,
+        history_before: usize,
+        added: usize,
+    ) -> Vec<SnapshotLine> {
+        // Older history goes first, so only the evicted lines past it are new
+        let evicted_new = evicted.len().saturating_sub(history_before).min(added);
+        let mut lines: Vec<SnapshotLine> = evicted.into_iter().rev().take(evicted_new).collect();
+        lines.reverse();
+        self.apply_color_options(&mut lines, self.resolve_colors);
+
+        let history = self.scrollback_len();
+        let kept = (added - evicted_new).min(history);
+        lines.extend(self.lines_range(history - kept, history));
+        lines
+    }
+
+    /// Apply feed sync to a feed's changed rows: the rows to report, and whether rows are
+    /// being held back for an open synchronized update
+    fn hold_rows(&mut self, lines: Vec<usize>) -> (Vec<usize>, bool) {
+        if self.held_rows.is_empty() && !self.feed_sync {
+            return (lines, false);
+        }
+
+        self.held_rows.extend(lines);
+        if self.feed_sync && self.modes.synchronized_update {
+            return (Vec::new(), true);
+        }
+
+        let mut rows = std::mem::take(&mut self.held_rows);
+        rows.sort_unstable();
+        rows.dedup();
+        (rows, false)
+    }
+
+    /// Advance the generation after a change that may show in `get_view`
+    fn touch(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Run `f`, turning a panic into its message. With panic rollback enabled the state
+    /// from before the call is restored first.
+    fn guarded<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Result<T, String> {
+        let checkpoint = self.panic_rollback.then(|| self.checkpoint());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| f(self)));
+
+        result.map_err(|payload| {
+            self.record_unsupported("panic");
+            if let Some(checkpoint) = checkpoint {
+                self.roll_back(checkpoint);
+            }
+            panic_message(payload)
+        })
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        let pending = self.scanner.pending();
+        Checkpoint {
+            size: self.terminal.size(),
+            replay: self.replay_sequence(self.scrollback_limit) + pending,
+            cursor_style: self.cursor_style,
+            modes: self.modes,
+            scroll_region: self.scroll_region,
+            tab_stops: self.tab_stops.clone(),
+            title: self.title.clone(),
+            primary_lines: self.primary_lines.clone(),
+            pending_scrollback_limit: self.pending_scrollback_limit,
+            overlay: self.overlay.as_ref().map(|overlay| overlay.duplicate(pending)),
+            line_attrs: self.line_attrs.clone(),
+            row_times: self.row_times.clone(),
+            palette: self.palette.clone(),
+            default_colors: self.default_colors.clone(),
+            clipboard: self.clipboard.clone(),
+            responses: self.responses.clone(),
+            bells: self.bells,
+        }
+    }
+
+    fn roll_back(&mut self, checkpoint: Checkpoint) {
+        (self.terminal, self.scanner) =
+            replay_terminal(checkpoint.size, self.scrollback_limit, &checkpoint.replay);
+        self.overlay = checkpoint.overlay;
+        self.line_attrs = checkpoint.line_attrs;
+        self.row_times = checkpoint.row_times;
+        self.palette = checkpoint.palette;
+        self.default_colors = checkpoint.default_colors;
+        self.clipboard = checkpoint.clipboard;
+        self.responses = checkpoint.responses;
+        self.bells = checkpoint.bells;
+        self.cursor_style = checkpoint.cursor_style;
+        self.modes = checkpoint.modes;
+        self.scroll_region = checkpoint.scroll_region;
+        self.tab_stops = checkpoint.tab_stops;
+        self.title = checkpoint.title;
+        self.primary_lines = checkpoint.primary_lines;
+        self.pending_scrollback_limit = checkpoint.pending_scrollback_limit;
+    }
+
+    /// Run one character through the scanner, the terminal and the overlay
+    fn feed_char(&mut self, ch: char) {
+        let (function, event) = self.scanner.feed(ch);
+        let executed = function.is_some();
+
+        if let Some(function) = function {
+            if enters_alt_screen(&function) && !self.is_alternate_screen() {
+                self.primary_lines = Some(create_full_snapshot(self, false).lines);
+            }
+
+            let tracks_rows = self.line_attrs.is_some() || self.row_times.is_some();
+            let shift = tracks_rows.then(|| self.line_shift(&function));
+            let buffer = self.terminal.active_buffer_type();
+            self.track_scroll_region(&function);
+            self.track_tab_stops(&function);
+            self.track_cursor_context(&function);
+            self.track_charsets(&function);
+            self.terminal.execute(function);
+
+            if let Some(shift) = shift {
+                self.follow_line_moves(shift, buffer);
+            }
+            if self.terminal.active_buffer_type() != buffer {
+                // Each screen has a keyboard flag stack of its own
+                let modes = &mut self.modes;
+                std::mem::swap(&mut modes.keyboard, &mut modes.other_keyboard);
+            }
+
+            if self.primary_lines.is_some() && !self.is_alternate_screen() {
+                self.primary_lines = None;
+
+                if let Some(limit) = self.pending_scrollback_limit.take() {
+                    self.rebuild_terminal(limit);
+                }
+            }
+        }
+
+        if let Some(overlay) = &mut self.overlay {
+            overlay.feed(ch);
+        }
+
+        if let Some(event) = event {
+            if !self.handle_event(&event, executed) {
+                let kind = match event {
+                    Event::Osc(_) => "osc",
+                    Event::Csi(_) => "csi",
+                    Event::Esc(_) | Event::Bell => "esc",
+                };
+                self.record_unsupported(kind);
+            }
+        }
+    }
+
+    /// Capture the sequence the scanner completed last as unsupported, while capturing
+    fn record_unsupported(&mut self, kind: &'static str) {
+        let Some(unsupported) = &mut self.unsupported else {
+            return;
+        };
+
+        if unsupported.len() < MAX_UNSUPPORTED {
+            unsupported.push(UnsupportedSequence {
+                raw: self.scanner.last_sequence().to_string(),
+                kind,
+            });
+        }
+    }
+
+    /// Mirror the margin changes avt is about to make for `function`
+    fn track_scroll_region(&mut self, function: &Function) {
+        match *function {
+            Function::Decstbm(top, bottom) => {
+                let (_, rows) = self.terminal.size();
+                let top = (top.max(1) - 1) as usize;
+                let bottom = if bottom == 0 { rows } else { bottom as usize } - 1;
+
+                // avt ignores invalid margins
+                if top < bottom && bottom < rows {
+                    let full = (0, rows - 1);
+                    self.scroll_region = Some((top, bottom)).filter(|&region| region != full);
+                }
+            }
+            Function::Decstr | Function::Ris => self.scroll_region = None,
+            _ => {}
+        }
+    }
+
+    /// Mirror what `function` is about to do to avt's origin mode and saved cursors, which
+    /// it keeps private. Modes are applied in order, as avt does, so a screen switch in the
+    /// middle of a list changes which saved cursor the rest of it uses.
+    fn track_cursor_context(&mut self, function: &Function) {
+        use DecMode::{AltScreenBuffer, Origin, SaveCursor, SaveCursorAltScreenBuffer};
+        let mut alternate = self.is_alternate_screen();
+        let modes = &mut self.modes;
+
+        match function {
+            Function::Decsc | Function::Scosc => modes.save_cursor(),
+            Function::Decrc | Function::Scorc => modes.restore_cursor(),
+            Function::Decset(list) => {
+                for mode in list {
+                    match mode {
+                        Origin => modes.origin_mode = true,
+                        AltScreenBuffer => modes.switch_screen(&mut alternate, true),
+                        SaveCursor => modes.save_cursor(),
+                        SaveCursorAltScreenBuffer => {
+                            modes.save_cursor();
+                            modes.switch_screen(&mut alternate, true);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Function::Decrst(list) => {
+                for mode in list {
+                    match mode {
+                        Origin => modes.origin_mode = false,
+                        AltScreenBuffer => modes.switch_screen(&mut alternate, false),
+                        SaveCursor => modes.restore_cursor(),
+                        SaveCursorAltScreenBuffer => {
+                            modes.switch_screen(&mut alternate, false);
+                            modes.restore_cursor();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Function::Decstr => {
+                modes.origin_mode = false;
+                modes.saved_origin = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Mirror the character set shifts avt keeps private; designations come in as
+    /// `ESC (` / `ESC )` events, avt's charset type being private too
+    fn track_charsets(&mut self, function: &Function) {
+        let modes = &mut self.modes;
+
+        match function {
+            Function::So => modes.shifted_out = true,
+            Function::Si => modes.shifted_out = false,
+            Function::Decstr => {
+                modes.charsets = [Charset::Ascii; 2];
+                modes.shifted_out = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn scroll_region(&self) -> ScrollRegion {
+        let (_, rows) = self.terminal.size();
+        let (top, bottom) = self.scroll_region.unwrap_or((0, rows - 1));
+        ScrollRegion { top, bottom }
+    }
+
+    /// How `function` is about to move the rows carrying line attributes
+    fn line_shift(&self, function: &Function) -> Shift {
+        let cursor = self.terminal.cursor();
+        let region = self.scroll_region();
+        Shift::of(
+            function,
+            (cursor.col, cursor.row),
+            self.terminal.size(),
+            (region.top, region.bottom),
+        )
+    }
+
+    /// Move line attributes and row times along with the lines the last function moved,
+    /// `buffer` being the one that was active before it ran
+    fn follow_line_moves(&mut self, shift: Shift, buffer: BufferType) {
+        let (_, rows) = self.terminal.size();
+        let len = line_count(&self.terminal);
+        // The margins only change after the move, so they're still the ones it used
+        let margin_end = self.scroll_region().bottom + 1;
+        let active = self.terminal.active_buffer_type();
+
+        if let Some(row_times) = &mut self.row_times {
+            row_times.follow(&shift, margin_end, len, active != buffer);
+        }
+        let Some(line_attrs) = &mut self.line_attrs else {
+            return;
+        };
+
+        if active == buffer {
+            line_attrs.follow(shift, rows, margin_end, len);
+        } else {
+            line_attrs.switch_buffer(active == BufferType::Alternate, len);
+        }
+    }
+
+    /// Give the cursor's line the attribute of `ESC # final_char`
+    fn set_line_attr(&mut self, final_char: char) {
+        let Some(attr) = LineAttr::from_esc(final_char) else {
+            return;
+        };
+        if attr.is_normal() && self.line_attrs.is_none() {
+            return;
+        }
+
+        let (_, rows) = self.terminal.size();
+        let row = self.terminal.cursor().row;
+        let len = line_count(&self.terminal);
+        self.line_attrs
+            .get_or_insert_with(|| LineAttrs::new(len))
+            .set(row, rows, attr);
+    }
+
+    /// Mirror the tab stop changes avt is about to make for `function`
+    fn track_tab_stops(&mut self, function: &Function) {
+        let (cols, _) = self.terminal.size();
+        let col = self.terminal.cursor().col;
+
+        match function {
+            // avt never sets a stop in the first column or the pending-wrap column
+            Function::Hts | Function::Ctc(CtcOp::Set) if 0 < col && col < cols => {
+                if let Err(index) = self.tab_stops.binary_search(&col) {
+                    self.tab_stops.insert(index, col);
+                }
+            }
+            Function::Tbc(TbcScope::CurrentColumn) | Function::Ctc(CtcOp::ClearCurrentColumn) => {
+                self.tab_stops.retain(|&stop| stop != col);
+            }
+            Function::Tbc(TbcScope::All) | Function::Ctc(CtcOp::ClearAll) => {
+                self.tab_stops.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Reprogram avt's and the overlay's tab stops from `tab_stops` after avt reset them
+    /// to its own defaults. Only the cursor column is touched, and it's put back.
+    fn sync_tab_stops(&mut self) {
+        let col = self.terminal.cursor().col;
+        let mut seq = String::from("\x1b[3g");
+
+        for stop in &self.tab_stops {
+            seq.push_str(&format!("\x1b[{}G\x1bH", stop + 1));
+        }
+        seq.push_str(&format!("\x1b[{}G", col + 1));
+        self.inject(&seq);
+    }
+
+    /// Run `seq` through avt and the overlay, bypassing the wrapper's own tracking
+    fn inject(&mut self, seq: &str) {
+        let mut parser = Parser::new();
+        for ch in seq.chars() {
+            if let Some(function) = parser.feed(ch) {
+                self.terminal.execute(function);
+            }
+        }
+
+        if let Some(overlay) = &mut self.overlay {
+            overlay.inject(seq);
+        }
+    }
+
+    /// React to a sequence avt's terminal doesn't retain. Returns whether avt (which
+    /// `executed` a function for it) or the wrapper supports the sequence.
+    fn handle_event(&mut self, event: &Event, executed: bool) -> bool {
+        match event {
+            Event::Bell => self.bells = self.bells.saturating_add(1),
+            Event::Osc(payload) => return self.handle_osc(payload),
+            Event::Csi(csi) => return self.handle_csi(csi, executed),
+            Event::Esc(esc) if esc.intermediates.is_empty() => match esc.final_char {
+                'c' => self.hard_reset(),
+                // DECKPAM / DECKPNM
+                '=' => self.modes.application_keypad = true,
+                '>' => self.modes.application_keypad = false,
+                // ST, ending a string sequence
+                '\\' => {}
+                _ => return executed,
+            },
+            Event::Esc(esc) if esc.intermediates == "#" => {
+                self.set_line_attr(esc.final_char);
+                return executed || LineAttr::from_esc(esc.final_char).is_some();
+            }
+            // Designate G0 / G1; avt reads every final but `0` as ASCII
+            Event::Esc(esc) if esc.intermediates == "(" || esc.intermediates == ")" => {
+                let charset = match esc.final_char {
+                    '0' => Charset::DecGraphics,
+                    _ => Charset::Ascii,
+                };
+                self.modes.charsets[(esc.intermediates == ")") as usize] = charset;
+            }
+            _ => return executed,
+        }
+        true
+    }
+
+    /// Act on an OSC string, returning whether its command is one the wrapper supports
+    fn handle_osc(&mut self, payload: &str) -> bool {
+        let (command, args) = payload.split_once(';').unwrap_or((payload, ""));
+
+        match command {
+            // Icon name + title, or title only
+            "0" | "2" => self.title = Some(args.to_string()),
+            "8" => {
+                // OSC 8 ; params ; uri - an empty URI ends the link
+                let uri = args.split_once(';').map_or("", |(_, uri)| uri);
+                self.set_hyperlink((!uri.is_empty()).then(|| uri.to_string()));
+            }
+            // OSC 4 ; index ; spec [; index ; spec ...] - a `?` spec queries the entry
+            "4" => {
+                let mut parts = args.split(';');
+                while let (Some(index), Some(spec)) = (parts.next(), parts.next()) {
+                    if let Ok(index) = index.parse::<u8>() {
+                        self.set_or_query_palette(index, spec);
+                    }
+                }
+            }
+            // OSC 10 / 11 ; spec - further specs carry on to the next color, like in xterm;
+            // those past 11 (cursor and highlight colors) are ignored
+            "10" | "11" => {
+                let base: usize = if command == "10" { 10 } else { 11 };
+                for (offset, spec) in args.split(';').enumerate() {
+                    match base + offset {
+                        10 => self.set_or_query_default_color(true, spec),
+                        11 => self.set_or_query_default_color(false, spec),
+                        _ => break,
+                    }
+                }
+            }
+            "52" => {
+                // OSC 52 ; selections ; base64 - queries (`?`) would leak the host clipboard
+                if let Some((selections, data)) = args.split_once(';') {
+                    if data != "?" {
+                        self.set_clipboard(selections, data);
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_or_query_palette(&mut self, index: u8, spec: &str) {
+        if spec == "?" {
+            let reply = format!("\x1b]4;{};{}\x1b\\", index, rgb_spec(self.palette.rgb(index)));
+            self.responses.push_str(&reply);
+        } else if let Some(rgb) = parse_color_spec(spec) {
+            self.palette.set(index, rgb);
+            self.touch();
+        }
+    }
+
+    fn set_or_query_default_color(&mut self, fg: bool, spec: &str) {
+        if spec == "?" {
+            let (code, rgb) = if fg {
+                (10, self.default_colors.fg(&self.palette))
+            } else {
+                (11, self.default_colors.bg(&self.palette))
+            };
+            let reply = format!("\x1b]{};{}\x1b\\", code, rgb_spec(rgb));
+            self.responses.push_str(&reply);
+        } else if let Some(rgb) = parse_color_spec(spec) {
+            if fg {
+                self.default_colors.fg = Some(rgb);
+            } else {
+                self.default_colors.bg = Some(rgb);
+            }
+            self.touch();
+        }
+    }
+
+    /// Act on a control sequence, returning whether avt (which `executed` a function for
+    /// it) or the wrapper supports it
+    fn handle_csi(&mut self, csi: &Csi, executed: bool) -> bool {
+        if let Some(prefix) = csi.prefix {
+            return self.handle_private_csi(prefix, csi);
+        }
+
+        match (csi.intermediates.as_str(), csi.final_char) {
+            // DECSCUSR; unknown styles are ignored like in xterm
+            (" ", 'q') => {
+                if let Some(style) = CursorStyle::from_decscusr(csi.param(0, 0)) {
+                    self.cursor_style = style;
+                }
+            }
+            ("", 'm') => self.track_sgr(csi),
+            ("", 'J') if csi.param(0, 0) == 3 => self.clear_scrollback(),
+            ("!", 'p') => self.soft_reset_state(),
+            // DECSCA: 1 protects subsequently printed cells, 0 and 2 don't
+            ("\"", 'q') => self.set_protected(csi.param(0, 0) == 1),
+            // Primary DA
+            ("", 'c') if csi.param(0, 0) == 0 => self.responses.push_str(&self.device_attributes),
+            // DSR: operating status, cursor position report
+            ("", 'n') => match csi.param(0, 0) {
+                5 => self.responses.push_str("\x1b[0n"),
+                6 => {
+                    let [col, row] = self.cursor_position();
+                    self.responses
+                        .push_str(&format!("\x1b[{};{}R", row + 1, col + 1));
+                }
+                _ => return false,
+            },
+            // SM / RM; avt knows IRM (4) and LNM (20)
+            ("", 'h' | 'l') => {
+                return csi.params.iter().all(|param| matches!(param.first(), Some(4 | 20)));
+            }
+            _ => return executed,
+        }
+        true
+    }
+
+    /// Act on a control sequence with a private marker, which avt only acts on for
+    /// `CSI ? Pm h` / `l`. Returns whether the sequence is supported.
+    fn handle_private_csi(&mut self, prefix: char, csi: &Csi) -> bool {
+        match (prefix, csi.intermediates.as_str(), csi.final_char) {
+            ('?', "", 'h') => return self.modes.set(csi, true),
+            ('?', "", 'l') => return self.modes.set(csi, false),
+            // DECSED / DECSEL, which avt ignores
+            ('?', "", 'J') => self.selective_erase(csi.param(0, 0), true),
+            ('?', "", 'K') => self.selective_erase(csi.param(0, 0), false),
+            // Kitty keyboard protocol: query, push, pop and set the flags
+            ('?', "", 'u') => {
+                let reply = format!("\x1b[?{}u", self.modes.keyboard.flags);
+                self.responses.push_str(&reply);
+            }
+            ('>', "", 'u') => self.modes.keyboard.push(csi.param(0, 0)),
+            ('<', "", 'u') => self.modes.keyboard.pop(csi.param(0, 1)),
+            ('=', "", 'u') => self.modes.keyboard.set(csi.param(0, 0), csi.param(1, 1)),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Record a clipboard write; payloads that aren't valid base64 are ignored
+    fn set_clipboard(&mut self, selections: &str, data: &str) {
+        let Some(bytes) = decode_base64(data) else {
+            return;
+        };
+
+        // Only the primary selection is kept apart; an empty list means the clipboard
+        let selection = if selections.contains('p') && !selections.contains('c') {
+            "p"
+        } else {
+            "c"
+        };
+
+        self.clipboard = Some(Clipboard {
+            selection: selection.to_string(),
+            text: String::from_utf8_lossy(&bytes).into_owned(),
+        });
+    }
+
+    /// Reset wrapper-side state alongside avt's RIS
+    fn hard_reset(&mut self) {
+        self.cursor_style = CursorStyle::default();
+        self.modes = Modes::default();
+        self.set_hyperlink(None);
+        self.set_protected(false);
+        self.line_attrs = None;
+
+        let (cols, _) = self.terminal.size();
+        self.tab_stops = default_tab_stops(cols, self.tab_width);
+        if self.tab_width != AVT_TAB_WIDTH {
+            self.sync_tab_stops();
+        }
+    }
+
+    /// Reset what avt's DECSTR leaves alone: its pen also drops rapid blink, the underline
+    /// style and color, and DECSCA protection, and xterm returns both keypad modes to normal
+    fn soft_reset_state(&mut self) {
+        self.modes.application_keypad = false;
+        self.terminal.execute(Function::Decrst(vec![DecMode::CursorKeys]));
+        self.set_protected(false);
+
+        if let Some(overlay) = &self.overlay {
+            let mut attrs = overlay.current().clone();
+            reset_sgr_attrs(&mut attrs);
+            if &attrs != overlay.current() {
+                self.update_attrs(|current| *current = attrs);
+            }
+        }
+    }
+
+    /// Mark subsequently printed cells as protected from selective erases, or not
+    fn set_protected(&mut self, protected: bool) {
+        let current = self
+            .overlay
+            .as_ref()
+            .is_some_and(|overlay| overlay.current().protected);
+
+        if protected != current {
+            self.update_attrs(|attrs| attrs.protected = protected);
+        }
+    }
+
+    /// Erase the cells DECSED (`display`) or DECSEL `mode` covers, like ED / EL do, except
+    /// for protected ones. Runs of unprotected cells are erased with ECH, then the cursor
+    /// is put back (a pending wrap is lost, as when tab stops are synced).
+    fn selective_erase(&mut self, mode: u16, display: bool) {
+        let (cols, rows) = self.terminal.size();
+        let cursor = self.terminal.cursor();
+        let col = cursor.col.min(cols - 1);
+
+        let (row_range, first, last) = match (display, mode) {
+            (true, 0) => (cursor.row..rows, col, cols - 1),
+            (true, 1) => (0..cursor.row + 1, 0, col),
+            (true, 2) => (0..rows, 0, cols - 1),
+            (false, 0) => (cursor.row..cursor.row + 1, col, cols - 1),
+            (false, 1) => (cursor.row..cursor.row + 1, 0, col),
+            (false, 2) => (cursor.row..cursor.row + 1, 0, cols - 1),
+            _ => return,
+        };
+
+        // Absolute positioning needs origin mode off for a moment
+        let mut seq = String::from(if self.modes.origin_mode { "\x1b[?6l" } else { "" });
+
+        for row in row_range {
+            // Only the cursor's row is cut at the cursor; other rows are erased in full
+            let (start, end) = if row == cursor.row {
+                (first, last + 1)
+            } else {
+                (0, cols)
+            };
+            let protected = self.protected_cells(row);
+            let mut col = start;
+
+            while col < end {
+                if protected[col] {
+                    col += 1;
+                    continue;
+                }
+                let run_start = col;
+                while col < end && !protected[col] {
+                    col += 1;
+                }
+                seq.push_str(&format!(
+                    "\x1b[{};{}H\x1b[{}X",
+                    row + 1,
+                    run_start + 1,
+                    col - run_start
+                ));
+            }
+        }
+
+        if self.modes.origin_mode {
+            let top = self.scroll_region().top;
+            seq.push_str(&format!("\x1b[?6h\x1b[{};{}H", cursor.row - top + 1, col + 1));
+        } else {
+            seq.push_str(&format!("\x1b[{};{}H", cursor.row + 1, col + 1));
+        }
+        self.inject(&seq);
+    }
+
+    /// Which cells of viewport row `row` are protected; the right half of a wide
+    /// character goes with its left half
+    fn protected_cells(&self, row: usize) -> Vec<bool> {
+        let (cols, rows) = self.terminal.size();
+        let Some(overlay) = self.overlay_line(rows - 1 - row) else {
+            return vec![false; cols];
+        };
+        let mut protected: Vec<bool> = Vec::with_capacity(cols);
+
+        for (col, cell) in self.terminal.line(row).cells().iter().enumerate() {
+            let continuation = cell.width() == 0 && col > 0;
+            protected.push(if continuation {
+                protected[col - 1]
+            } else {
+                overlay.get(col).is_some_and(|attrs| attrs.protected)
+            });
+        }
+        protected
+    }
+
+    /// Attach `url` to subsequently printed cells
+    fn set_hyperlink(&mut self, url: Option<String>) {
+        if url.is_none() && self.overlay.is_none() {
+            return;
+        }
+
+        self.update_attrs(|attrs| attrs.url = url);
+    }
+
+    /// Follow the SGR attributes avt ignores: rapid blink (SGR 6), which replaces slow
+    /// blink and is cleared by SGR 0, 5 and 25; the underline style (`4:n`), whose
+    /// underline avt skips too, so it's set here; and the underline color (SGR 58 / 59)
+    fn track_sgr(&mut self, csi: &Csi) {
+        let current = self
+            .overlay
+            .as_ref()
+            .map(|overlay| overlay.current().clone())
+            .unwrap_or_default();
+        let mut attrs = current.clone();
+        // Underline turned on or off by a `4:n` avt skipped
+        let mut underline = None;
+        let mut i = 0;
+
+        if csi.params.is_empty() {
+            reset_sgr_attrs(&mut attrs);
+        }
+
+        while let Some(parts) = csi.params.get(i) {
+            match parts.first().copied().unwrap_or(0) {
+                0 => {
+                    reset_sgr_attrs(&mut attrs);
+                    underline = None;
+                }
+                5 | 25 => attrs.rapid_blink = false,
+                6 => attrs.rapid_blink = true,
+                4 if parts.len() > 1 => match UnderlineStyle::from_sgr(parts[1]) {
+                    Some(style) => {
+                        attrs.underline_style = Some(style);
+                        underline = Some(true);
+                    }
+                    None if parts[1] == 0 => {
+                        attrs.underline_style = None;
+                        underline = Some(false);
+                    }
+                    None => {}
+                },
+                4 | 24 => {
+                    attrs.underline_style = None;
+                    underline = None;
+                }
+                58 => {
+                    let (color, used) = extended_color(&csi.params[i..]);
+                    attrs.underline_color = color;
+                    i += used;
+                }
+                59 => attrs.underline_color = None,
+                // `38;5;n` and `38;2;r;g;b` spread a color over several parameters
+                38 | 48 => i += extended_color(&csi.params[i..]).1,
+                _ => {}
+            }
+            i += 1;
+        }
+
+        if let Some(on) = underline {
+            self.inject(if on { "\x1b[4m" } else { "\x1b[24m" });
+        }
+        if attrs != current {
+            self.update_attrs(|current| *current = attrs);
+        }
+    }
+
+    /// Change the attributes applied to subsequently printed cells, creating the overlay
+    fn update_attrs(&mut self, update: impl FnOnce(&mut CellAttrs)) {
+        let overlay = self
+            .overlay
+            .get_or_insert_with(|| Overlay::new(&self.terminal, self.scanner.pending()));
+        let mut attrs = overlay.current().clone();
+        update(&mut attrs);
+        overlay.set_current(attrs);
+    }
+
+    /// ED 3, which avt parses but ignores: drop all scrollback, keeping the screen. The alt
+    /// screen has no history of its own, so the primary screen's is kept there.
+    fn clear_scrollback(&mut self) {
+        if !self.is_alternate_screen() && self.scrollback_len() > 0 {
+            self.replace_terminal(0, self.scrollback_limit);
+            self.scrollback_cleared = true;
+        }
+    }
+
+    /// Replace the terminal with one capped at `limit` scrollback lines and the same content
+    fn rebuild_terminal(&mut self, limit: usize) {
+        self.replace_terminal(limit, limit);
+    }
+
+    /// Replace the terminal with one capped at `limit` scrollback lines, holding the same
+    /// screen and the newest `keep` history lines
+    fn replace_terminal(&mut self, keep: usize, limit: usize) {
+        let mut parser = Parser::new();
+        let mut terminal = Terminal::new(self.terminal.size(), Some(limit));
+
+        for ch in self.replay_sequence(keep.min(limit)).chars() {
+            if let Some(function) = parser.feed(ch) {
+                terminal.execute(function);
+            }
+        }
+
+        drop(terminal.gc());
+        self.terminal = terminal;
+        self.scrollback_limit = limit;
+        self.sync_overlay();
+    }
+
+    /// Input that recreates the terminal in a fresh one with room for `limit` scrollback
+    /// lines: the newest history lines that fit are replayed and scrolled off the top, then
+    /// the screen itself is restored from avt's dump
+    fn replay_sequence(&self, limit: usize) -> String {
+        let (_, rows) = self.terminal.size();
+        let scrollback = self.scrollback_len();
+        let keep = scrollback.min(limit);
+        let mut seq = String::new();
+
+        if keep > 0 {
+            for (i, line) in self.terminal.lines().skip(scrollback - keep).take(keep).enumerate() {
+                ansi::push_line(&mut seq, &snapshot_line(line, None, LineAttr::Normal));
+
+                // A wrapped line ends in the pending-wrap column, so the next one continues it
+                if !is_wrapped(line) || i == keep - 1 {
+                    seq.push_str("\r\n");
+                }
+            }
+
+            seq.push_str(&"\n".repeat(rows - 1));
+            seq.push_str("\x1b[H");
+        }
+
+        seq.push_str(&self.terminal.dump());
+        seq
+    }
+
+    /// Let the overlay's history follow the terminal's scrollback
+    fn sync_overlay(&mut self) {
+        let (_, rows) = self.terminal.size();
+        let len = line_count(&self.terminal);
+
+        if let Some(overlay) = &mut self.overlay {
+            overlay.sync(len - rows);
+        }
+        if let Some(line_attrs) = &mut self.line_attrs {
+            line_attrs.sync(len);
+        }
+    }
+
+    fn mode_state(&self) -> ModeState {
+        ModeState {
+            bracketed_paste: self.modes.bracketed_paste,
+            mouse: self.modes.mouse,
+            mouse_encoding: self.modes.mouse_encoding,
+            focus_reporting: self.modes.focus_events,
+            application_cursor: self.terminal.cursor_keys_app_mode(),
+            application_keypad: self.modes.application_keypad,
+            keyboard_flags: self.modes.keyboard.flags,
+            origin_mode: self.modes.origin_mode,
+        }
+    }
+
+    /// Indices of the characters of `s` that would start a new row (see `measure_wrap`)
+    fn wrap_breaks(&self, s: &str) -> Vec<usize> {
+        let (cols, _) = self.terminal.size();
+        // avt leaves the cursor at `cols` while a wrap is pending
+        let mut col = self.terminal.cursor().col;
+        let mut parser = Parser::new();
+        let mut breaks = Vec::new();
+
+        for (index, ch) in s.chars().enumerate() {
+            let width = match parser.feed(ch) {
+                Some(Function::Print(ch)) => print_width(ch),
+                Some(Function::Cr | Function::Nel) => {
+                    col = 0;
+                    continue;
+                }
+                // From the pending-wrap position BS lands before the last column
+                Some(Function::Bs) => {
+                    col = col.saturating_sub(if col == cols { 2 } else { 1 });
+                    continue;
+                }
+                Some(Function::Ht) => {
+                    let next = self.tab_stops.iter().find(|&&stop| stop > col);
+                    col = next.map_or(cols - 1, |&stop| stop.min(cols - 1));
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if col + width > cols {
+                breaks.push(index);
+                col = 0;
+            }
+            col += width;
+        }
+
+        breaks
+    }
+
+    /// Cursor position as [col, row], with a cursor in the pending-wrap column (one past
+    /// the last) reported on the last column
+    fn cursor_position(&self) -> [usize; 2] {
+        let (cols, _) = self.terminal.size();
+        let cursor = self.terminal.cursor();
+        [cursor.col.min(cols - 1), cursor.row]
+    }
+
+    fn active_charset(&self) -> Charset {
+        self.modes.charsets[self.modes.shifted_out as usize]
+    }
+
+    fn cursor_line(&self) -> Option<CursorLine> {
+        if !self.terminal.cursor().visible {
+            return None;
+        }
+
+        let (_, rows) = self.terminal.size();
+        let [col, row] = self.cursor_position();
+        let mut line = self.line_snapshot(self.terminal.line(row), rows - 1 - row);
+        self.apply_color_options(std::slice::from_mut(&mut line), self.resolve_colors);
+
+        Some(CursorLine {
+            row,
+            line,
+            cursor_col: col,
+        })
+    }
+
+    fn cursor_state(&self) -> CursorState {
+        let [col, row] = self.cursor_position();
+
+        CursorState {
+            col,
+            row,
+            visible: self.terminal.cursor().visible,
+            shape: self.cursor_style.shape,
+            blink: self.cursor_style.blink,
+            under: self.content_cell(col, row),
+        }
+    }
+
+    fn all_lines_with_options(&self, options: &AllLinesOptions) -> TerminalSnapshot {
+        let mut snapshot = create_full_snapshot(self, true);
+
+        if options.trim_trailing_spaces {
+            for line in &mut snapshot.lines {
+                trim_trailing_spaces(&mut line.spans);
+            }
+        }
+
+        self.resolved(snapshot)
+    }
+
+    /// `create_full_snapshot(self, true)` with color options as JSON, one line at a time
+    fn all_lines_json(&self) -> String {
+        let (cols, rows) = self.terminal.size();
+        let mut json = format!(
+            "{{\"schema_version\":{},\"cols\":{},\"rows\":{},\"lines\":[",
+            SNAPSHOT_SCHEMA_VERSION, cols, rows
+        );
+        let push_line = |json: &mut String, mut line: SnapshotLine| {
+            if !json.ends_with('[') {
+                json.push(',');
+            }
+            self.apply_color_options(std::slice::from_mut(&mut line), self.resolve_colors);
+            json.push_str(&serde_json::to_string(&line).expect("snapshot lines serialize"));
+        };
+        let is_blank_line = |line: &SnapshotLine| line.spans.iter().all(is_blank);
+
+        match &self.primary_lines {
+            Some(lines) => {
+                let kept = lines.iter().rposition(|line| !is_blank_line(line));
+                for line in &lines[..kept.map_or(0, |last| last + 1)] {
+                    push_line(&mut json, line.clone());
+                }
+            }
+            None => {
+                let lines: Vec<&avt::Line> = self.terminal.lines().collect();
+                let total = lines.len();
+                let snapshot = |i: usize| self.line_snapshot(lines[i], total - 1 - i);
+                let kept = (0..total).rev().find(|&i| !is_blank_line(&snapshot(i)));
+
+                for i in 0..kept.map_or(0, |last| last + 1) {
+                    push_line(&mut json, snapshot(i));
+                }
+            }
+        }
+
+        json.push(']');
+        for (key, phase) in [
+            ("blink_on", self.blink_phase(BLINK_INTERVAL_MS)),
+            ("rapid_blink_on", self.blink_phase(RAPID_BLINK_INTERVAL_MS)),
+        ] {
+            if let Some(on) = phase {
+                json.push_str(&format!(",\"{}\":{}", key, on));
+            }
+        }
+        json.push('}');
+        json
+    }
+
+    fn all_lines_rle(&self) -> RleSnapshot {
+        let snapshot = self.resolved(create_full_snapshot(self, true));
+        let blank = Terminal::new((snapshot.cols, 1), Some(0));
+        let mut blank_line = snapshot_line(blank.line(0), None, LineAttr::Normal);
+        self.apply_color_options(std::slice::from_mut(&mut blank_line), self.resolve_colors);
+
+        let mut lines = Vec::new();
+        for line in snapshot.lines {
+            match lines.last_mut() {
+                _ if line != blank_line => lines.push(RleLine::Line(line)),
+                Some(RleLine::EmptyRun { empty_run }) => *empty_run += 1,
+                _ => lines.push(RleLine::EmptyRun { empty_run: 1 }),
+            }
+        }
+
+        RleSnapshot {
+            schema_version: snapshot.schema_version,
+            cols: snapshot.cols,
+            rows: snapshot.rows,
+            blank_line,
+            lines,
+            blink_on: snapshot.blink_on,
+            rapid_blink_on: snapshot.rapid_blink_on,
+        }
+    }
+
+    fn view_runs(&self) -> ViewRuns {
+        let snapshot = self.resolved(create_snapshot(self));
+        let lines = snapshot
+            .lines
+            .into_iter()
+            .map(|line| {
+                let mut text = String::new();
+                let mut runs = Vec::with_capacity(line.spans.len());
+
+                for span in &line.spans {
+                    let len = span.text.chars().count();
+                    runs.push(TextRun {
+                        start: runs.last().map_or(0, |run: &TextRun| run.start + run.len),
+                        len,
+                        attrs: span_style(span),
+                    });
+                    text.push_str(&span.text);
+                }
+
+                RunLine { text, runs }
+            })
+            .collect();
+
+        ViewRuns {
+            schema_version: snapshot.schema_version,
+            cols: snapshot.cols,
+            rows: snapshot.rows,
+            lines,
+            blink_on: snapshot.blink_on,
+            rapid_blink_on: snapshot.rapid_blink_on,
+        }
+    }
+
+    fn view_masked(&self, options: &MaskOptions) -> TerminalSnapshot {
+        let mut snapshot = self.resolved(create_snapshot(self));
+
+        for line in &mut snapshot.lines {
+            line.spans = mask_spans(std::mem::take(&mut line.spans), options);
+        }
+
+        snapshot
+    }
+
+    fn view_columns(&self, start: usize, end: usize) -> TerminalSnapshot {
+        let mut snapshot = self.resolved(create_snapshot(self));
+
+        for line in &mut snapshot.lines {
+            line.spans = clip_spans(&line.spans, start, end);
+        }
+
+        snapshot
+    }
+
+    fn active_pen(&self) -> ActivePen {
+        // avt dumps its pen as the last `CSI 0 ... m`, after every cell it prints
+        let dump = self.terminal.dump();
+        let sgr = dump.rfind("\x1b[0").map_or("", |start| {
+            let rest = &dump[start..];
+            rest.find('m').map_or("", |end| &rest[..=end])
+        });
+
+        // Print with it on a scratch terminal to read it back as a span
+        let mut probe = Terminal::new((1, 1), Some(0));
+        let mut parser = Parser::new();
+        for ch in sgr.chars().chain(['x']) {
+            if let Some(function) = parser.feed(ch) {
+                probe.execute(function);
+            }
+        }
+        let mut line = snapshot_line(probe.line(0), None, LineAttr::Normal);
+        self.apply_color_options(std::slice::from_mut(&mut line), self.resolve_colors);
+        let attrs = self.overlay.as_ref().map(|overlay| overlay.current().clone());
+        pen_of(line.spans.swap_remove(0), attrs.unwrap_or_default())
+    }
+
+    fn saved_cursor(&self) -> Option<SavedCursor> {
+        self.modes.saved_origin?;
+
+        // avt keeps the saved context private: restore it on a copy and print with its pen
+        let mut probe = Terminal::new(self.terminal.size(), Some(0));
+        let mut parser = Parser::new();
+        for ch in self.terminal.dump().chars() {
+            if let Some(function) = parser.feed(ch) {
+                probe.execute(function);
+            }
+        }
+        probe.execute(Function::Decrc);
+        let cursor = probe.cursor();
+        probe.execute(Function::Print('x'));
+
+        let mut line = snapshot_line(probe.line(cursor.row), None, LineAttr::Normal);
+        line.spans = clip_spans(&line.spans, cursor.col, cursor.col + 1);
+        self.apply_color_options(std::slice::from_mut(&mut line), self.resolve_colors);
+
+        Some(SavedCursor {
+            col: cursor.col,
+            row: cursor.row,
+            // DECSC doesn't save the overlay's attributes
+            pen: pen_of(line.spans.swap_remove(0), CellAttrs::default()),
+        })
+    }
+
+    fn view_with_options(&self, options: &ViewOptions) -> TerminalSnapshot {
+        let mut snapshot = create_snapshot(self);
+
+        if options.include_text_only {
+            for line in &mut snapshot.lines {
+                line.spans = plain_spans(&line.spans);
+            }
+        } else {
+            let resolve = options.resolve_colors.unwrap_or(self.resolve_colors);
+            self.apply_color_options(&mut snapshot.lines, resolve);
+        }
+
+        if !options.merge_spans {
+            for line in &mut snapshot.lines {
+                line.spans = cell_spans(std::mem::take(&mut line.spans));
+            }
+        }
+
+        if options.include_columns {
+            for line in &mut snapshot.lines {
+                set_span_columns(&mut line.spans);
+            }
+        }
+
+        if let Some(row_times) = self.row_times.as_ref().filter(|_| options.include_timestamps) {
+            for (row, line) in snapshot.lines.iter_mut().enumerate() {
+                line.changed_at = row_times.get(row);
+            }
+        }
+
+        if options.include_cursor {
+            snapshot.cursor = Some(self.cursor_state());
+        }
+
+        snapshot
+    }
+
+    /// Apply the `explicit_default_colors` and `resolve_colors` options to a snapshot
+    fn resolved(&self, mut snapshot: TerminalSnapshot) -> TerminalSnapshot {
+        self.apply_color_options(&mut snapshot.lines, self.resolve_colors);
+        snapshot
+    }
+
+    fn apply_color_options(&self, lines: &mut [SnapshotLine], resolve_colors: bool) {
+        if self.explicit_default_colors {
+            let (fg, bg) = self.default_color_values();
+            fill_default_colors(lines, &fg, &bg);
+        }
+
+        if resolve_colors {
+            resolve_snapshot_colors(lines, &self.palette);
+        }
+    }
+
+    /// Latest time passed to `tick`
+    fn clock_now(&self) -> f64 {
+        self.blink_clock.map_or(0.0, |(_, now)| now)
+    }
+
+    /// The default foreground and background as `#RRGGBB`
+    fn default_color_state(&self) -> DefaultColorState {
+        DefaultColorState {
+            fg: palette::rgb_hex(self.default_colors.fg(&self.palette)),
+            bg: palette::rgb_hex(self.default_colors.bg(&self.palette)),
+        }
+    }
+
+    /// The default foreground and background as `#RRGGBB` color values
+    fn default_color_values(&self) -> (ColorValue, ColorValue) {
+        (
+            ColorValue::Rgb(palette::rgb_hex(self.default_colors.fg(&self.palette))),
+            ColorValue::Rgb(palette::rgb_hex(self.default_colors.bg(&self.palette))),
+        )
+    }
+
+    /// The viewport for the HTML, RTF and ANSI exports: inverse spans get their colors
+    /// swapped, defaults filled in first, so the output shows what the terminal does
+    fn export_snapshot(&self) -> TerminalSnapshot {
+        let mut snapshot = create_snapshot(self);
+        let (fg, bg) = self.default_color_values();
+
+        let inverse = snapshot.lines.iter_mut().flat_map(|line| line.spans.iter_mut());
+        for span in inverse.filter(|span| span.inverse) {
+            fill_span_colors(span, &fg, &bg);
+        }
+
+        snapshot
+    }
+
+    fn used_colors(&self) -> UsedColors {
+        let mut colors = UsedColors::default();
+        let add = |set: &mut Vec<ColorValue>, color: &Option<ColorValue>| {
+            if let Some(color) = color {
+                if !set.contains(color) {
+                    set.push(color.clone());
+                }
+            }
+        };
+
+        for line in self.resolved(create_snapshot(self)).lines {
+            for span in &line.spans {
+                add(&mut colors.fg, &span.fg);
+                add(&mut colors.bg, &span.bg);
+            }
+        }
+
+        colors
+    }
+
+    fn span_position(&self, row: usize, col: usize) -> Option<SpanPosition> {
+        let (_, rows) = self.terminal.size();
+        if row >= rows {
+            return None;
+        }
+
+        let line = self.line_snapshot(self.terminal.line(row), rows - 1 - row);
+        let mut start = 0;
+
+        for (span_index, span) in line.spans.iter().enumerate() {
+            for (char_index, (byte_offset, _)) in span.text.char_indices().enumerate() {
+                let width = span.widths.as_ref().map_or(1, |widths| widths[char_index]);
+                if col < start + width as usize {
+                    return Some(SpanPosition {
+                        span: span_index,
+                        char_index,
+                        byte_offset,
+                    });
+                }
+                start += width as usize;
+            }
+        }
+
+        None
+    }
+
+    fn cell_info(&self, col: usize, row: usize) -> Option<CellInfo> {
+        let (cols, rows) = self.terminal.size();
+        if col >= cols || row >= rows {
+            return None;
+        }
+
+        let cells = self.terminal.line(row).cells();
+        let continuation = cells[col].width() == 0 && col > 0;
+        let origin = if continuation { col - 1 } else { col };
+        let cell = &cells[origin];
+        let pen = cell.pen();
+        let attrs = self
+            .overlay_line(rows - 1 - row)
+            .and_then(|overlay| overlay.get(origin).cloned())
+            .unwrap_or_default();
+
+        Some(CellInfo {
+            ch: cell.char().to_string(),
+            width: if continuation { 0 } else { cell.width() as u8 },
+            fg: pen.foreground().map(|c| map_color(&c)),
+            bg: pen.background().map(|c| map_color(&c)),
+            bold: pen.is_bold(),
+            faint: pen.is_faint(),
+            italic: pen.is_italic(),
+            underline: pen.is_underline(),
+            underline_style: attrs.underline_style.filter(|_| pen.is_underline()),
+            underline_color: attrs.underline_color.filter(|_| pen.is_underline()),
+            strikethrough: pen.is_strikethrough(),
+            blink: pen.is_blink() && !attrs.rapid_blink,
+            rapid_blink: attrs.rapid_blink,
+            inverse: pen.is_inverse(),
+            url: attrs.url,
+        })
+    }
+
+    /// The cell at (col, row), unless it and the rest of its row are default blanks
+    fn content_cell(&self, col: usize, row: usize) -> Option<CellInfo> {
+        let (_, rows) = self.terminal.size();
+        let overlay = self.overlay_line(rows - 1 - row);
+        let cells = self.terminal.line(row).cells();
+        let default_blank = |(col, cell): (usize, &avt::Cell)| {
+            cell.char() == ' '
+                && cell.pen().is_default()
+                && overlay.as_ref().is_none_or(|overlay| overlay.get(col).is_none())
+        };
+
+        if cells.iter().enumerate().skip(col).all(default_blank) {
+            None
+        } else {
+            self.cell_info(col, row)
+        }
+    }
+
+    fn region_text(
+        &self,
+        start: (usize, usize),
+        end: (usize, usize),
+        rectangular: bool,
+    ) -> String {
+        let (cols, rows) = self.terminal.size();
+        let last_col = cols - 1;
+
+        if rectangular {
+            let (left, right) = (start.0.min(end.0), start.0.max(end.0).min(last_col));
+            let (top, bottom) = (start.1.min(end.1), start.1.max(end.1).min(rows - 1));
+
+            return (top..=bottom)
+                .map(|row| row_slice(self.terminal.line(row), left, right))
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+
+        // Order the endpoints as they appear in reading order
+        let (start, end) = if (start.1, start.0) <= (end.1, end.0) {
+            (start, end)
+        } else {
+            (end, start)
+        };
+        let mut text = String::new();
+
+        for row in start.1..=end.1.min(rows - 1) {
+            let line = self.terminal.line(row);
+            let from = if row == start.1 { start.0 } else { 0 };
+            let to = if row == end.1 { end.0.min(last_col) } else { last_col };
+            let continues = to == last_col && row < end.1 && is_wrapped(line);
+            let slice = row_slice(line, from, to);
+
+            if continues {
+                text.push_str(&slice);
+            } else {
+                text.push_str(slice.trim_end());
+
+                if row < end.1 {
+                    text.push('\n');
+                }
+            }
+        }
+
+        text
+    }
+
+    fn scrollback_page(&self, offset: usize, count: usize) -> ScrollbackPage {
+        let total = self.scrollback_len();
+        let end = offset.saturating_add(count).min(total);
+
+        ScrollbackPage {
+            lines: self.lines_range(offset.min(end), end),
+            total,
+        }
+    }
+
+    /// Snapshot of lines `start..end` of scrollback + viewport, clamped to what exists
+    fn lines_range(&self, start: usize, end: usize) -> Vec<SnapshotLine> {
+        let total = line_count(&self.terminal);
+        let end = end.min(total);
+
+        let mut lines: Vec<SnapshotLine> = self
+            .terminal
+            .lines()
+            .enumerate()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .map(|(i, line)| self.line_snapshot(line, total - 1 - i))
+            .collect();
+
+        self.apply_color_options(&mut lines, self.resolve_colors);
+        lines
+    }
+
+    /// Snapshot of `line`, which is `from_bottom` lines above the last viewport row
+    fn line_snapshot(&self, line: &avt::Line, from_bottom: usize) -> SnapshotLine {
+        let line_attr = self
+            .line_attrs
+            .as_ref()
+            .map_or(LineAttr::Normal, |line_attrs| line_attrs.line(from_bottom));
+        snapshot_line(line, self.overlay_line(from_bottom), line_attr)
+    }
+
+    /// Overlay attributes for the line `from_bottom` lines above the last viewport row
+    fn overlay_line(&self, from_bottom: usize) -> Option<LineOverlay<'_>> {
+        self.overlay.as_ref()?.line(from_bottom)
+    }
+
+    /// Feed a string to avt, returning changed row indices or the panic message
+    fn feed_rows(&mut self, s: &str) -> Result<Vec<usize>, String> {
+        self.feed_changes(s).map(|changes| changes.lines)
+    }
+
+    /// Queue `s` and apply the next slice of at most `max_chunk` bytes, or of one character
+    /// if that is longer
+    fn feed_chunk(&mut self, s: &str, max_chunk: usize) -> Result<ChunkedFeed, String> {
+        if self.chunked_offset > 0 && !s.is_empty() {
+            self.chunked_input.drain(..self.chunked_offset);
+            self.chunked_offset = 0;
+        }
+        self.chunked_input.push_str(s);
+
+        let rest = &self.chunked_input[self.chunked_offset..];
+        let end = self
+            .scanner
+            .ground_boundary(rest, max_chunk.max(1))
+            .unwrap_or(rest.len());
+        let chunk = rest[..end].to_string();
+
+        self.chunked_offset += end;
+        if self.chunked_offset == self.chunked_input.len() {
+            self.chunked_input.clear();
+            self.chunked_offset = 0;
+        }
+
+        Ok(ChunkedFeed {
+            changes: self.feed_rows(&chunk)?,
+            remaining: self.chunked_input.len() - self.chunked_offset,
+        })
+    }
+
+    /// Feed a string and write its packed changes over `out`, reusing its capacity
+    fn feed_into(&mut self, s: &str, out: &mut Vec<u8>) -> Result<(), String> {
+        let rows = self.feed_rows(s)?;
+        let (_, height) = self.terminal.size();
+        let view: Vec<&avt::Line> = self.terminal.view().collect();
+        let mut lines: Vec<SnapshotLine> = rows
+            .iter()
+            .map(|&row| self.line_snapshot(view[row], height - 1 - row))
+            .collect();
+        self.apply_color_options(&mut lines, self.resolve_colors);
+
+        out.clear();
+        packed::pack_changes(out, &rows, &lines);
+        Ok(())
+    }
+
+    /// Feed a string, returning the changed columns of each changed row
+    fn feed_damage(&mut self, s: &str) -> Result<Vec<Damage>, String> {
+        let (_, rows) = self.terminal.size();
+        let before: Vec<_> = (0..rows).map(|row| self.row_cells(row)).collect();
+        let released = self.held_rows.clone();
+        let changed = self.feed_rows(s)?;
+        let (cols, rows) = self.terminal.size();
+
+        let damage = changed
+            .into_iter()
+            .filter(|&row| row < rows)
+            .filter_map(|row| {
+                if released.contains(&row) {
+                    return Some(Damage {
+                        row,
+                        min_col: 0,
+                        max_col: cols - 1,
+                    });
+                }
+                let after = self.row_cells(row);
+                let old = before.get(row);
+                let differs = |col: &usize| old.and_then(|old| old.get(*col)) != after.get(*col);
+                let min_col = (0..cols).find(differs)?;
+                let max_col = (0..cols).rfind(differs)?;
+                // Widen to both halves of a wide character at either end
+                let is_continuation = |col: usize| after[col].0.width() == 0;
+                let is_wide = |col: usize| after[col].0.width() == 2;
+                let min_col = min_col - (min_col > 0 && is_continuation(min_col)) as usize;
+                let max_col = max_col + (max_col + 1 < cols && is_wide(max_col)) as usize;
+                Some(Damage {
+                    row,
+                    min_col,
+                    max_col,
+                })
+            })
+            .collect();
+
+        Ok(damage)
+    }
+
+    /// The cells of viewport row `row` with the overlay attributes each carries
+    fn row_cells(&self, row: usize) -> Vec<(avt::Cell, Option<CellAttrs>)> {
+        let (_, rows) = self.terminal.size();
+        let overlay = self.overlay_line(rows - 1 - row);
+
+        self.terminal
+            .line(row)
+            .cells()
+            .iter()
+            .enumerate()
+            .map(|(col, cell)| (*cell, overlay.as_ref().and_then(|o| o.get(col)).cloned()))
+            .collect()
+    }
+
+    /// Feed a string, returning the changed rows together with the replies it produced
+    fn feed_interactive_rows(&mut self, s: &str) -> Result<InteractiveFeed, String> {
+        let queued = self.responses.len();
+        let changes = self.feed_rows(s)?;
+        Ok(InteractiveFeed {
+            changes,
+            responses: self.responses.split_off(queued),
+        })
+    }
+
+    fn feed_many_rows(&mut self, chunks: &[String]) -> BatchResult {
+        let mut rows: Vec<usize> = Vec::new();
+
+        for (succeeded, chunk) in chunks.iter().enumerate() {
+            match self.feed_rows(chunk) {
+                Ok(changed) => rows.extend(changed),
+                Err(error) => return BatchResult::Error { error, succeeded },
+            }
+        }
+
+        rows.sort_unstable();
+        rows.dedup();
+        BatchResult::Ok { ok: rows }
+    }
+
+    /// Compare the current viewport against the diff baseline and advance the baseline
+    fn view_diff(&mut self) -> Vec<LineDiff> {
+        let current = self.resolved(create_snapshot(self)).lines;
+        let baseline = self.diff_baseline.take().unwrap_or_default();
+        let diff = diff_lines(&baseline, &current);
+
+        self.diff_baseline = Some(current);
+        diff
+    }
+
+    /// Whether blinking text of `interval` phases is visible, or None before the first tick
+    fn blink_phase(&self, interval: f64) -> Option<bool> {
+        let (start, now) = self.blink_clock?;
+        Some((((now - start) / interval) as u64).is_multiple_of(2))
+    }
+
+    fn snapshot_diff(&self, other: &Vt) -> SnapshotDiff {
+        let ours = self.resolved(create_snapshot(self));
+        let theirs = other.resolved(create_snapshot(other));
+
+        SnapshotDiff {
+            changed_rows: diff_lines(&ours.lines, &theirs.lines),
+            resized: (ours.cols, ours.rows) != (theirs.cols, theirs.rows),
+        }
+    }
+
+    /// Reset through avt's RIS so wrapper-side state resets along with it
+    fn reset_rows(&mut self) -> Result<Vec<usize>, String> {
+        self.utf8_tail.clear();
+        self.clear_chunked_input();
+        // Everything queued while paused would be wiped by the reset anyway
+        self.paused_input.clear();
+        // ESC also aborts any sequence left unfinished by a previous feed
+        self.feed_unpaused("\x1bc")
+    }
+
+    fn soft_reset_rows(&mut self) -> Result<Vec<usize>, String> {
+        self.utf8_tail.clear();
+        self.clear_chunked_input();
+        let mut rows = self.apply_paused_input()?;
+        rows.extend(self.feed_unpaused("\x1b[!p")?);
+        rows.sort_unstable();
+        rows.dedup();
+        Ok(rows)
+    }
+
+    /// Drop the input `feed_chunked` still has queued
+    fn clear_chunked_input(&mut self) {
+        self.chunked_input.clear();
+        self.chunked_offset = 0;
+    }
+
+    /// Decode a byte chunk (prefixed by any buffered tail) and feed the complete characters
+    fn feed_bytes_rows(&mut self, bytes: &[u8]) -> Result<Vec<usize>, String> {
+        if self.utf8_tail.is_empty() {
+            if let Ok(s) = std::str::from_utf8(bytes) {
+                return self.feed_rows(s);
+            }
+        }
+        let s = decode_utf8_chunk(&mut self.utf8_tail, bytes);
+        self.feed_rows(&s)
+    }
+}
+
+/// Total number of lines (scrollback + viewport) without walking the buffer.
+/// avt's line iterator is backed by a VecDeque, so its size hint is exact.
+fn line_count(terminal: &Terminal) -> usize {
+    terminal.lines().size_hint().0
+}
+
+/// Build a terminal from `replay` (see `Vt::replay_sequence`). The scanner is fed too, so
+/// a sequence left unfinished at the end of it resumes with the next feed.
+fn replay_terminal(size: (usize, usize), limit: usize, replay: &str) -> (Terminal, Scanner) {
+    let mut terminal = Terminal::new(size, Some(limit));
+    let mut scanner = Scanner::new();
+
+    for ch in replay.chars() {
+        if let (Some(function), _) = scanner.feed(ch) {
+            terminal.execute(function);
+        }
+    }
+
+    drop(terminal.gc());
+    (terminal, scanner)
+}
+
+/// Tab stops every `width` columns, the first column excluded
+fn default_tab_stops(cols: usize, width: usize) -> Vec<usize> {
+    (width..cols).step_by(width).collect()
+}
+
+/// Whether `function` switches avt to the alternate screen buffer
+fn enters_alt_screen(function: &Function) -> bool {
+    match function {
+        Function::Decset(modes) => modes.iter().any(|mode| {
+            matches!(
+                mode,
+                DecMode::AltScreenBuffer | DecMode::SaveCursorAltScreenBuffer
+            )
+        }),
+        _ => false,
+    }
+}
+
+/// Clear what SGR 0 resets of the attributes `track_sgr` follows
+fn reset_sgr_attrs(attrs: &mut CellAttrs) {
+    attrs.rapid_blink = false;
+    attrs.underline_style = None;
+    attrs.underline_color = None;
+}
+
+/// Read the extended color starting at `params[0]` (`38`, `48` or `58`), in the colon form
+/// (`58:5:n`, `58:2:r:g:b`, `58:2::r:g:b`) or spread over the following parameters
+/// (`58;5;n`, `58;2;r;g;b`). Returns the color and the number of following parameters used.
+fn extended_color(params: &[Vec<u16>]) -> (Option<ColorValue>, usize) {
+    let rgb = |r: u16, g: u16, b: u16| {
+        let rgb = (r.min(255) as u8, g.min(255) as u8, b.min(255) as u8);
+        ColorValue::Rgb(palette::rgb_hex(rgb))
+    };
+    let param = |i: usize| params.get(i).and_then(|parts| parts.first()).copied();
+
+    match params[0].as_slice() {
+        [_, 5, n] => (Some(ColorValue::Indexed((*n).min(255) as u8)), 0),
+        [_, 2, r, g, b] | [_, 2, _, r, g, b] => (Some(rgb(*r, *g, *b)), 0),
+        [_] => match param(1) {
+            Some(5) => (param(2).map(|n| ColorValue::Indexed(n.min(255) as u8)), 2),
+            Some(2) => match (param(2), param(3), param(4)) {
+                (Some(r), Some(g), Some(b)) => (Some(rgb(r, g, b)), 4),
+                _ => (None, 4),
+            },
+            _ => (None, 0),
+        },
+        _ => (None, 0),
+    }
+}
+
+/// Extract a readable message from a caught panic payload
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        (*msg).to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Decode standard base64, with or without padding
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    let digits = data.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for ch in digits.bytes() {
+        let value = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+
+    // A lone trailing digit can't encode a whole byte
+    (bits < 6).then_some(bytes)
+}
+
+/// Decode as much UTF-8 as possible from `tail` + `bytes`.
+/// Invalid sequences become U+FFFD; an incomplete sequence at the very end is left in `tail`.
+fn decode_utf8_chunk(tail: &mut Vec<u8>, bytes: &[u8]) -> String {
+    tail.extend_from_slice(bytes);
+    let buf = std::mem::take(tail);
+    let mut out = String::with_capacity(buf.len());
+    let mut rest = &buf[..];
+
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                out.push_str(valid);
+                break;
+            }
+            Err(err) => {
+                let (valid, after) = rest.split_at(err.valid_up_to());
+                // The prefix up to valid_up_to is guaranteed to be valid UTF-8
+                out.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                match err.error_len() {
+                    Some(len) => {
+                        out.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    }
+                    None => {
+                        // Incomplete sequence at the end: keep it for the next chunk
+                        tail.extend_from_slice(after);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// 64-bit FNV-1a, a hasher that gives the same value on every run
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Hash a pen color, telling a default, indexed and RGB color apart
+    fn write_color(&mut self, color: Option<Color>) {
+        match color {
+            None => self.write(&[0]),
+            Some(Color::Indexed(n)) => self.write(&[1, n]),
+            Some(Color::RGB(rgb)) => self.write(&[2, rgb.r, rgb.g, rgb.b]),
+        }
+    }
+}
+
+/// Result of a fallible call such as `feed`: the payload on success, or the error message
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum FeedResult<T> {
+    Ok(T),
+    Error(String),
+}
+
+impl<T> From<Result<T, String>> for FeedResult<T> {
+    fn from(result: Result<T, String>) -> Self {
+        match result {
+            Ok(rows) => FeedResult::Ok(rows),
+            Err(msg) => FeedResult::Error(msg),
+        }
+    }
+}
+
+/// `feed` error after the pre-feed state was restored
+#[derive(Serialize, Debug, PartialEq)]
+struct RolledBack {
+    error: String,
+    rolled_back: bool,
+}
+
+/// Fields of the `create_with_options` object
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct CreateOptions {
+    cols: usize,
+    rows: usize,
+    scrollback_limit: usize,
+    /// Up to 256 `#RRGGBB` entries, as for `set_palette`
+    palette: Option<Vec<String>>,
+    default_fg: Option<String>,
+    default_bg: Option<String>,
+    resize_reflow: bool,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        CreateOptions {
+            cols: 80,
+            rows: 24,
+            scrollback_limit: DEFAULT_SCROLLBACK_LIMIT,
+            palette: None,
+            default_fg: None,
+            default_bg: None,
+            resize_reflow: true,
+        }
+    }
+}
+
+/// Fields of the `get_view_opts` object
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ViewOptions {
+    include_cursor: bool,
+    include_text_only: bool,
+    merge_spans: bool,
+    include_columns: bool,
+    include_timestamps: bool,
+    /// None keeps the `set_resolve_colors` setting
+    resolve_colors: Option<bool>,
+}
+
+impl Default for ViewOptions {
+    fn default() -> Self {
+        ViewOptions {
+            include_cursor: false,
+            include_text_only: false,
+            merge_spans: true,
+            include_columns: false,
+            include_timestamps: false,
+            resolve_colors: None,
+        }
+    }
+}
+
+/// Fields of the `get_all_lines_opts` object
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct AllLinesOptions {
+    trim_trailing_spaces: bool,
+}
+
+/// Fields of the `get_view_masked` object
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct MaskOptions {
+    strip_blink: bool,
+    strip_faint: bool,
+    strip_italic: bool,
+}
+
+/// Pre-feed state restored when a feed panics with rollback enabled
+struct Checkpoint {
+    size: (usize, usize),
+    /// Input that recreates the terminal and the scanner's unfinished sequence
+    replay: String,
+    cursor_style: CursorStyle,
+    modes: Modes,
+    scroll_region: Option<(usize, usize)>,
+    tab_stops: Vec<usize>,
+    title: Option<String>,
+    primary_lines: Option<Vec<SnapshotLine>>,
+    pending_scrollback_limit: Option<usize>,
+    overlay: Option<Overlay>,
+    line_attrs: Option<LineAttrs>,
+    row_times: Option<RowTimes>,
+    palette: Palette,
+    default_colors: DefaultColors,
+    clipboard: Option<Clipboard>,
+    responses: String,
+    bells: usize,
+}
+
+/// Result of `feed_many`: `{ ok: [rows] }` or `{ error, succeeded }`
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum BatchResult {
+    Ok { ok: Vec<usize> },
+    Error { error: String, succeeded: usize },
+}
+
+/// Detailed change set produced by a single feed
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct FeedChanges {
+    lines: Vec<usize>,
+    scrollback_added: usize,
+    /// Lines dropped from the top of history by the scrollback limit
+    evicted: usize,
+    resized: bool,
+    bell: bool,
+    /// ED 3 erased the scrollback
+    #[serde(skip_serializing_if = "is_false")]
+    scrollback_cleared: bool,
+    /// Rows were held back for an open synchronized update
+    #[serde(skip_serializing_if = "is_false")]
+    partial: bool,
+    /// U+FFFD characters in the input, while counting them is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replacement_chars: Option<usize>,
+}
+
+/// Changed columns of a row, returned by `feed_with_damage`
+#[derive(Serialize, Debug, PartialEq)]
+struct Damage {
+    row: usize,
+    min_col: usize,
+    max_col: usize,
+}
+
+/// Result of `flush` and `set_paused`
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct PausedFlush {
+    changes: Vec<usize>,
+    #[serde(skip_serializing_if = "is_zero")]
+    dropped_bytes: usize,
+}
+
+/// Result of `feed_chunked`
+#[derive(Serialize, Debug, PartialEq)]
+struct ChunkedFeed {
+    changes: Vec<usize>,
+    /// Bytes of input still queued
+    remaining: usize,
+}
+
+/// Result of `get_scrollback_page`
+#[derive(Serialize, Debug, PartialEq)]
+struct ScrollbackPage {
+    lines: Vec<SnapshotLine>,
+    total: usize,
+}
+
+/// Result of `feed_interactive`
+#[derive(Serialize, Debug, PartialEq)]
+struct InteractiveFeed {
+    changes: Vec<usize>,
+    responses: String,
+}
+
+/// Result of `feed_and_collect_scrollback`
+#[derive(Serialize, Debug, PartialEq)]
+struct ScrollbackFeed {
+    viewport_changes: Vec<usize>,
+    new_scrollback: Vec<SnapshotLine>,
+}
+
+/// Cursor shape selected by DECSCUSR
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+/// Cursor style set by `CSI Ps SP q`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CursorStyle {
+    shape: CursorShape,
+    blink: bool,
+}
+
+impl Default for CursorStyle {
+    /// Blinking block, matching xterm
+    fn default() -> Self {
+        CursorStyle {
+            shape: CursorShape::Block,
+            blink: true,
+        }
+    }
+}
+
+impl CursorStyle {
+    fn from_decscusr(ps: u16) -> Option<Self> {
+        let (shape, blink) = match ps {
+            0 | 1 => (CursorShape::Block, true),
+            2 => (CursorShape::Block, false),
+            3 => (CursorShape::Underline, true),
+            4 => (CursorShape::Underline, false),
+            5 => (CursorShape::Bar, true),
+            6 => (CursorShape::Bar, false),
+            _ => return None,
+        };
+
+        Some(CursorStyle { shape, blink })
+    }
+}
+
+/// Which mouse events are reported
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum MouseTracking {
+    #[default]
+    Off,
+    /// `?9`: button presses only
+    X10,
+    /// `?1000`: presses and releases
+    Normal,
+    /// `?1002`: also motion while a button is held
+    Button,
+    /// `?1003`: all motion
+    Any,
+}
+
+/// Character set designated to G0 or G1, as avt models them
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Charset {
+    #[default]
+    Ascii,
+    /// DEC Special Graphics (`ESC ( 0`), lowercase letters drawing lines and boxes
+    DecGraphics,
+}
+
+/// How reported mouse events are encoded
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum MouseEncoding {
+    #[default]
+    Default,
+    /// `?1005`
+    Utf8,
+    /// `?1006`
+    Sgr,
+    /// `?1015`
+    Urxvt,
+}
+
+/// DEC private modes and keypad mode tracked by the wrapper
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Modes {
+    bracketed_paste: bool,
+    mouse: MouseTracking,
+    mouse_encoding: MouseEncoding,
+    focus_events: bool,
+    application_keypad: bool,
+    /// `?2026`, used to batch a frame's output
+    synchronized_update: bool,
+    /// Kitty keyboard protocol flags of the active screen
+    keyboard: KeyboardFlags,
+    /// Kitty keyboard protocol flags of the other screen
+    other_keyboard: KeyboardFlags,
+    /// DECOM (`?6`): cursor addressing relative to the scroll region
+    origin_mode: bool,
+    /// Origin mode saved with the active screen's cursor, None while none is saved. avt
+    /// restores saved cursors from the dump itself.
+    saved_origin: Option<bool>,
+    /// Origin mode saved with the other screen's cursor
+    other_saved_origin: Option<bool>,
+    /// G0 and G1 as designated by `ESC (` / `ESC )`, shared by both screens
+    charsets: [Charset; 2],
+    /// Whether SO shifted G1 in, until SI shifts G0 back
+    shifted_out: bool,
+}
+
+impl Modes {
+    /// DECSC and the like save the origin mode along with the cursor
+    fn save_cursor(&mut self) {
+        self.saved_origin = Some(self.origin_mode);
+    }
+
+    /// DECRC with nothing saved restores the defaults
+    fn restore_cursor(&mut self) {
+        self.origin_mode = self.saved_origin.unwrap_or(false);
+    }
+
+    /// Follow a switch to the alternate (`to`) or primary screen, from the one `alternate`
+    /// says is active
+    fn switch_screen(&mut self, alternate: &mut bool, to: bool) {
+        if *alternate != to {
+            std::mem::swap(&mut self.saved_origin, &mut self.other_saved_origin);
+            *alternate = to;
+        }
+    }
+
+    /// Apply `CSI ? Pm h` (`on`) or `CSI ? Pm l`. Tracking modes and encodings each
+    /// replace one another, and resetting one only turns off the active one. Returns
+    /// whether avt or the wrapper knows every mode.
+    fn set(&mut self, csi: &Csi, on: bool) -> bool {
+        let mut known = true;
+
+        for param in &csi.params {
+            let mode = param.first().copied().unwrap_or(0);
+
+            let tracking = match mode {
+                9 => Some(MouseTracking::X10),
+                1000 => Some(MouseTracking::Normal),
+                1002 => Some(MouseTracking::Button),
+                1003 => Some(MouseTracking::Any),
+                _ => None,
+            };
+            let encoding = match mode {
+                1005 => Some(MouseEncoding::Utf8),
+                1006 => Some(MouseEncoding::Sgr),
+                1015 => Some(MouseEncoding::Urxvt),
+                _ => None,
+            };
+
+            if let Some(tracking) = tracking {
+                if on {
+                    self.mouse = tracking;
+                } else if self.mouse == tracking {
+                    self.mouse = MouseTracking::Off;
+                }
+            } else if let Some(encoding) = encoding {
+                if on {
+                    self.mouse_encoding = encoding;
+                } else if self.mouse_encoding == encoding {
+                    self.mouse_encoding = MouseEncoding::Default;
+                }
+            } else if mode == 1004 {
+                self.focus_events = on;
+            } else if mode == 2004 {
+                self.bracketed_paste = on;
+            } else if mode == 2026 {
+                self.synchronized_update = on;
+            } else {
+                known &= AVT_DEC_MODES.contains(&mode);
+            }
+        }
+
+        known
+    }
+
+    /// Sequences that re-enable the non-default modes when fed to a fresh terminal
+    fn dump(&self) -> String {
+        let mut seq = String::new();
+        let mut set = |mode: u16| seq.push_str(&format!("\x1b[?{}h", mode));
+
+        match self.mouse {
+            MouseTracking::Off => {}
+            MouseTracking::X10 => set(9),
+            MouseTracking::Normal => set(1000),
+            MouseTracking::Button => set(1002),
+            MouseTracking::Any => set(1003),
+        }
+        match self.mouse_encoding {
+            MouseEncoding::Default => {}
+            MouseEncoding::Utf8 => set(1005),
+            MouseEncoding::Sgr => set(1006),
+            MouseEncoding::Urxvt => set(1015),
+        }
+        if self.focus_events {
+            set(1004);
+        }
+        if self.bracketed_paste {
+            set(2004);
+        }
+        if self.synchronized_update {
+            set(2026);
+        }
+        if self.application_keypad {
+            seq.push_str("\x1b=");
+        }
+        seq.push_str(&self.keyboard.dump());
+
+        seq
+    }
+}
+
+/// Entries kept on a kitty keyboard flag stack; pushing more drops the oldest
+const KEYBOARD_STACK_SIZE: usize = 8;
+
+/// A screen's kitty keyboard protocol flags (`CSI = u`, `CSI > u`, `CSI < u`): the
+/// current ones plus the ones saved below them by pushes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct KeyboardFlags {
+    flags: u8,
+    stack: [u8; KEYBOARD_STACK_SIZE],
+    depth: usize,
+}
+
+impl KeyboardFlags {
+    /// `CSI > flags u`: save the current flags and switch to `flags`
+    fn push(&mut self, flags: u16) {
+        if self.depth == KEYBOARD_STACK_SIZE {
+            self.stack.rotate_left(1);
+            self.depth -= 1;
+        }
+        self.stack[self.depth] = self.flags;
+        self.depth += 1;
+        self.flags = keyboard_bits(flags);
+    }
+
+    /// `CSI < n u`: restore the flags saved `n` pushes ago; popping past the bottom of the
+    /// stack clears them
+    fn pop(&mut self, n: u16) {
+        for _ in 0..n {
+            self.flags = match self.depth {
+                0 => 0,
+                _ => {
+                    self.depth -= 1;
+                    self.stack[self.depth]
+                }
+            };
+        }
+    }
+
+    /// `CSI = flags ; mode u`: replace the current flags (mode 1), or set (2) or clear (3)
+    /// the given ones
+    fn set(&mut self, flags: u16, mode: u16) {
+        let flags = keyboard_bits(flags);
+        match mode {
+            1 => self.flags = flags,
+            2 => self.flags |= flags,
+            3 => self.flags &= !flags,
+            _ => {}
+        }
+    }
+
+    /// Sequences that rebuild the stack on a fresh terminal
+    fn dump(&self) -> String {
+        let mut levels = self.stack[..self.depth].iter().chain([&self.flags]);
+        let mut seq = match levels.next() {
+            Some(0) | None => String::new(),
+            Some(bottom) => format!("\x1b[={};1u", bottom),
+        };
+        for flags in levels {
+            seq.push_str(&format!("\x1b[>{}u", flags));
+        }
+        seq
+    }
+}
+
+/// The defined keyboard flag bits of a parameter
+fn keyboard_bits(flags: u16) -> u8 {
+    (flags & 0x1f) as u8
+}
+
+/// Input modes returned by `get_modes`
+#[derive(Serialize, Debug, PartialEq)]
+struct ModeState {
+    bracketed_paste: bool,
+    mouse: MouseTracking,
+    mouse_encoding: MouseEncoding,
+    /// Focus in/out reporting (`?1004`)
+    focus_reporting: bool,
+    application_cursor: bool,
+    application_keypad: bool,
+    keyboard_flags: u8,
+    origin_mode: bool,
+}
+
+/// Default colors returned by `get_default_colors`
+#[derive(Serialize, Debug, PartialEq)]
+struct DefaultColorState {
+    fg: String,
+    bg: String,
+}
+
+/// Distinct colors returned by `collect_colors`
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct UsedColors {
+    fg: Vec<ColorValue>,
+    bg: Vec<ColorValue>,
+}
+
+/// Where a column falls in a row's spans, returned by `col_to_char_index`
+#[derive(Serialize, Debug, PartialEq)]
+struct SpanPosition {
+    span: usize,
+    char_index: usize,
+    byte_offset: usize,
+}
+
+/// A match returned by `search`
+#[derive(Serialize, Debug, PartialEq)]
+struct SearchMatch {
+    row: usize,
+    col: usize,
+    len: usize,
+}
+
+/// A line returned by `get_logical_lines`
+#[derive(Serialize, Debug, PartialEq)]
+struct LogicalLine {
+    text: String,
+    start_row: usize,
+    end_row: usize,
+}
+
+/// Scroll margins returned by `get_scroll_region`
+#[derive(Serialize, Debug, PartialEq)]
+struct ScrollRegion {
+    top: usize,
+    bottom: usize,
+}
+
+/// Cursor position, visibility and style returned by `get_cursor_full`
+#[derive(Serialize, Debug, PartialEq)]
+struct CursorState {
+    col: usize,
+    row: usize,
+    visible: bool,
+    shape: CursorShape,
+    blink: bool,
+    /// Cell under the cursor, None past the end of the line's content
+    under: Option<CellInfo>,
+}
+
+/// Pen returned by `get_active_pen`, with the attributes of `SnapshotSpan`
+#[derive(Serialize, Debug, PartialEq)]
+struct ActivePen {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fg: Option<ColorValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bg: Option<ColorValue>,
+    #[serde(skip_serializing_if = "is_false")]
+    bold: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    faint: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    italic: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    underline: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    strikethrough: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    blink: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    rapid_blink: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    inverse: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+/// The pen a span of one printed cell shows, with the overlay attributes it was printed with
+fn pen_of(span: SnapshotSpan, attrs: CellAttrs) -> ActivePen {
+    let CellAttrs { url, rapid_blink, .. } = attrs;
+
+    ActivePen {
+        fg: span.fg,
+        bg: span.bg,
+        bold: span.bold,
+        faint: span.faint,
+        italic: span.italic,
+        underline: span.underline,
+        strikethrough: span.strikethrough,
+        blink: span.blink && !rapid_blink,
+        rapid_blink,
+        inverse: span.inverse,
+        url,
+    }
+}
+
+/// Result of `get_saved_cursor`
+#[derive(Serialize, Debug, PartialEq)]
+struct SavedCursor {
+    col: usize,
+    row: usize,
+    pen: ActivePen,
+}
+
+/// Serializable terminal snapshot
+#[derive(Serialize, Debug, PartialEq)]
+struct TerminalSnapshot {
+    /// `SNAPSHOT_SCHEMA_VERSION`
+    schema_version: u32,
+    cols: usize,
+    rows: usize,
+    lines: Vec<SnapshotLine>,
+    /// Blink phases shared by all blinking spans, once `tick` was called
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blink_on: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rapid_blink_on: Option<bool>,
+    /// Cursor state, when asked for with `get_view_opts`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<CursorState>,
+}
+
+/// Result of `get_all_lines_rle`
+#[derive(Serialize, Debug)]
+struct RleSnapshot {
+    schema_version: u32,
+    cols: usize,
+    rows: usize,
+    /// The line each `empty_run` repeats
+    blank_line: SnapshotLine,
+    lines: Vec<RleLine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blink_on: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rapid_blink_on: Option<bool>,
+}
+
+/// Result of `get_view_runs`
+#[derive(Serialize, Debug)]
+struct ViewRuns {
+    schema_version: u32,
+    cols: usize,
+    rows: usize,
+    lines: Vec<RunLine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blink_on: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rapid_blink_on: Option<bool>,
+}
+
+/// A line of `get_view_runs`: its text and the runs partitioning it
+#[derive(Serialize, Debug, PartialEq)]
+struct RunLine {
+    text: String,
+    runs: Vec<TextRun>,
+}
+
+/// `len` characters of a line's text from character `start`, styled by `attrs`
+#[derive(Serialize, Debug, PartialEq)]
+struct TextRun {
+    start: usize,
+    len: usize,
+    /// A span without its text, widths and column
+    attrs: SnapshotSpan,
+}
+
+/// A line of `get_all_lines_rle`, or a run of blank lines
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum RleLine {
+    Line(SnapshotLine),
+    EmptyRun { empty_run: usize },
+}
+
+/// A line in the snapshot
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct SnapshotLine {
+    spans: Vec<SnapshotSpan>,
+    /// True when the line soft-wraps into the next one (same logical line)
+    #[serde(skip_serializing_if = "is_false")]
+    wrapped: bool,
+    /// Double width/height set by DECDWL/DECDHL (omitted when normal)
+    #[serde(skip_serializing_if = "LineAttr::is_normal")]
+    line_attr: LineAttr,
+    /// Time of the `tick` clock the row last changed, from `include_timestamps`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed_at: Option<f64>,
+}
+
+/// A styled span of text
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct SnapshotSpan {
+    /// Empty only in the `attrs` of `get_view_runs`, where it's left out
+    #[serde(skip_serializing_if = "String::is_empty")]
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fg: Option<ColorValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bg: Option<ColorValue>,
+    #[serde(skip_serializing_if = "is_false")]
+    bold: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    faint: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    italic: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    underline: bool,
+    /// Style set with SGR `4:n`, absent for a plain SGR 4 underline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    underline_style: Option<UnderlineStyle>,
+    /// Color set with SGR 58, absent for the text color
+    #[serde(skip_serializing_if = "Option::is_none")]
+    underline_color: Option<ColorValue>,
+    #[serde(skip_serializing_if = "is_false")]
+    strikethrough: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    blink: bool,
+    /// Rapid blink (SGR 6); `blink` is the slow kind (SGR 5)
+    #[serde(skip_serializing_if = "is_false")]
+    rapid_blink: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    inverse: bool,
+    /// Target of the OSC 8 hyperlink covering this span
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    /// Protected by DECSCA from selective erases
+    #[serde(skip_serializing_if = "is_false")]
+    protected: bool,
+    /// Column width (1 or 2) of each character, present only if the span has a wide one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    widths: Option<Vec<u8>>,
+    /// Grid column the span starts at, when asked for with `get_view_opts`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    col: Option<usize>,
+}
+
+/// A sequence captured for `take_unsupported`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct UnsupportedSequence {
+    raw: String,
+    kind: &'static str,
+}
+
+/// Clipboard write requested via OSC 52
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct Clipboard {
+    selection: String,
+    text: String,
+}
+
+/// A single viewport cell returned by `get_cell`
+#[derive(Serialize, Debug, PartialEq)]
+struct CellInfo {
+    #[serde(rename = "char")]
+    ch: String,
+    /// 1 or 2 for the cell holding a character, 0 for the right half of a wide one
+    width: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fg: Option<ColorValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bg: Option<ColorValue>,
+    #[serde(skip_serializing_if = "is_false")]
+    bold: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    faint: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    italic: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    underline: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    underline_style: Option<UnderlineStyle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    underline_color: Option<ColorValue>,
+    #[serde(skip_serializing_if = "is_false")]
+    strikethrough: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    blink: bool,
+    /// Rapid blink (SGR 6); `blink` is the slow kind (SGR 5)
+    #[serde(skip_serializing_if = "is_false")]
+    rapid_blink: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    inverse: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+/// A changed viewport line reported by `get_view_diff`
+#[derive(Serialize, Debug, PartialEq)]
+struct LineDiff {
+    row: usize,
+    #[serde(flatten)]
+    line: SnapshotLine,
+}
+
+/// The cursor's row returned by `get_cursor_line`
+#[derive(Serialize, Debug, PartialEq)]
+struct CursorLine {
+    row: usize,
+    #[serde(flatten)]
+    line: SnapshotLine,
+    cursor_col: usize,
+}
+
+/// Difference between two terminals' viewports returned by `diff`
+#[derive(Serialize, Debug, PartialEq)]
+struct SnapshotDiff {
+    changed_rows: Vec<LineDiff>,
+    resized: bool,
+}
+
+/// Color value: either a palette index (number) or RGB hex string
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+enum ColorValue {
+    Indexed(u8),
+    Rgb(String),
+}
+
+/// Underline style selected with SGR `4:n`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+enum UnderlineStyle {
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    /// The style of `4:n`, None for no underline (0) or an unknown style
+    fn from_sgr(n: u16) -> Option<Self> {
+        match n {
+            1 => Some(UnderlineStyle::Single),
+            2 => Some(UnderlineStyle::Double),
+            3 => Some(UnderlineStyle::Curly),
+            4 => Some(UnderlineStyle::Dotted),
+            5 => Some(UnderlineStyle::Dashed),
+            _ => None,
+        }
+    }
+
+    /// The `n` of `4:n`
+    fn sgr(self) -> u16 {
+        self as u16 + 1
+    }
+
+    /// The CSS `text-decoration-style`
+    fn css(self) -> &'static str {
+        match self {
+            UnderlineStyle::Single => "solid",
+            UnderlineStyle::Double => "double",
+            UnderlineStyle::Curly => "wavy",
+            UnderlineStyle::Dotted => "dotted",
+            UnderlineStyle::Dashed => "dashed",
+        }
+    }
+
+    /// The RTF control word that turns the underline on
+    fn rtf(self) -> &'static str {
+        match self {
+            UnderlineStyle::Single => "\\ul",
+            UnderlineStyle::Double => "\\uldb",
+            UnderlineStyle::Curly => "\\ulwave",
+            UnderlineStyle::Dotted => "\\uld",
+            UnderlineStyle::Dashed => "\\uldash",
+        }
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// Create a terminal snapshot from avt's view
+fn create_snapshot(vt: &Vt) -> TerminalSnapshot {
+    let (cols, rows) = vt.terminal.size();
+    let mut lines = Vec::new();
+
+    for (row, line) in vt.terminal.view().enumerate() {
+        lines.push(vt.line_snapshot(line, rows - 1 - row));
+    }
+
+    TerminalSnapshot {
+        schema_version: SNAPSHOT_SCHEMA_VERSION,
+        cols,
+        rows,
+        lines,
+        blink_on: vt.blink_phase(BLINK_INTERVAL_MS),
+        rapid_blink_on: vt.blink_phase(RAPID_BLINK_INTERVAL_MS),
+        cursor: None,
+    }
+}
+
+/// Create a terminal snapshot from all lines (scrollback + viewport), optionally trimmed of
+/// trailing empties.
+fn create_full_snapshot(vt: &Vt, trim: bool) -> TerminalSnapshot {
+    let (cols, rows) = vt.terminal.size();
+
+    // The alt screen has no history of its own; report the primary buffer instead
+    let mut lines: Vec<SnapshotLine> = match &vt.primary_lines {
+        Some(lines) => lines.clone(),
+        None => {
+            let total = line_count(&vt.terminal);
+
+            vt.terminal
+                .lines()
+                .enumerate()
+                .map(|(i, line)| vt.line_snapshot(line, total - 1 - i))
+                .collect()
+        }
+    };
+
+    if trim {
+        while lines.last().is_some_and(|last| last.spans.iter().all(is_blank)) {
+            lines.pop();
+        }
+    }
+
+    TerminalSnapshot {
+        schema_version: SNAPSHOT_SCHEMA_VERSION,
+        cols,
+        rows,
+        lines,
+        blink_on: vt.blink_phase(BLINK_INTERVAL_MS),
+        rapid_blink_on: vt.blink_phase(RAPID_BLINK_INTERVAL_MS),
+        cursor: None,
+    }
+}
+
+/// Whether a span shows nothing: whitespace without a background, inverse or decoration
+fn is_blank(span: &SnapshotSpan) -> bool {
+    span.text.trim().is_empty() && !shows_blanks(span)
+}
+
+/// Whether blanks in a span are visible: a background, inverse video or a line through
+/// or under them
+fn shows_blanks(span: &SnapshotSpan) -> bool {
+    span.bg.is_some() || span.inverse || span.underline || span.strikethrough
+}
+
+/// Drop the invisible whitespace ending a line's spans
+fn trim_trailing_spaces(spans: &mut Vec<SnapshotSpan>) {
+    while let Some(last) = spans.last_mut() {
+        if shows_blanks(last) {
+            return;
+        }
+
+        let kept = last.text.trim_end().chars().count();
+        if kept == 0 {
+            spans.pop();
+            continue;
+        }
+
+        last.text = last.text.chars().take(kept).collect();
+        last.widths = last.widths.as_ref().and_then(|w| wide_widths(&w[..kept]));
+        return;
+    }
+}
+
+/// Convert an avt line into a snapshot line
+fn snapshot_line(
+    line: &avt::Line,
+    overlay: Option<LineOverlay>,
+    line_attr: LineAttr,
+) -> SnapshotLine {
+    SnapshotLine {
+        spans: merge_cells_to_spans(line, overlay.as_ref()),
+        wrapped: is_wrapped(line),
+        line_attr,
+        changed_at: None,
+    }
+}
+
+/// Lines of `current` that differ from the same row of `baseline`, or all of them when
+/// the line counts differ
+fn diff_lines(baseline: &[SnapshotLine], current: &[SnapshotLine]) -> Vec<LineDiff> {
+    let full = baseline.len() != current.len();
+
+    current
+        .iter()
+        .enumerate()
+        .filter(|(row, line)| full || baseline[*row] != **line)
+        .map(|(row, line)| LineDiff {
+            row,
+            line: line.clone(),
+        })
+        .collect()
+}
+
+/// Whether a line soft-wraps into the next one.
+/// avt keeps `Line.wrapped` crate-private, but its public `TextUnwrapper` withholds
+/// output exactly when a line is wrapped, which lets us read the flag reliably.
+fn is_wrapped(line: &avt::Line) -> bool {
+    TextUnwrapper::new().push(line).is_none()
+}
+
+/// Text of columns `from..=to` of a line; a wide character is included when its right
+/// half falls inside the range
+fn row_slice(line: &avt::Line, from: usize, to: usize) -> String {
+    line.cells()
+        .iter()
+        .enumerate()
+        .filter(|(col, cell)| {
+            let width = cell.width();
+            width > 0 && col + width - 1 <= to && col + width > from
+        })
+        .map(|(_, cell)| cell.char())
+        .collect()
+}
+
+/// Plain text of each viewport line
+fn view_text(terminal: &Terminal) -> Vec<String> {
+    terminal.view().map(|line| line.text()).collect()
+}
+
+/// Viewport cells by row and column, wide character continuations as ""
+fn char_grid(terminal: &Terminal) -> Vec<Vec<String>> {
+    let cell = |cell: &avt::Cell| match cell.width() {
+        0 => String::new(),
+        _ => cell.char().to_string(),
+    };
+
+    terminal.view().map(|line| line.cells().iter().map(cell).collect()).collect()
+}
+
+/// Viewport rows split into extended grapheme clusters, trailing blanks dropped
+fn view_graphemes(terminal: &Terminal) -> Vec<Vec<String>> {
+    view_text(terminal)
+        .iter()
+        .map(|text| text.trim_end().graphemes(true).map(str::to_string).collect())
+        .collect()
+}
+
+/// All lines (scrollback + viewport) with soft-wrapped rows joined, trimmed of trailing
+/// empty lines
+fn logical_lines(terminal: &Terminal) -> Vec<LogicalLine> {
+    let mut lines: Vec<LogicalLine> = Vec::new();
+    let mut text = String::new();
+    let mut start_row = 0;
+    let total = line_count(terminal);
+
+    for (row, line) in terminal.lines().enumerate() {
+        text.push_str(&line.text());
+
+        if !is_wrapped(line) || row + 1 == total {
+            lines.push(LogicalLine {
+                text: text.trim_end().to_string(),
+                start_row,
+                end_row: row,
+            });
+            text.clear();
+            start_row = row + 1;
+        }
+    }
+
+    while lines.last().is_some_and(|line| line.text.is_empty()) {
+        lines.pop();
+    }
+
+    lines
+}
+
+/// Plain text of all lines (scrollback + viewport), trimmed of trailing empty lines
+fn all_text(terminal: &Terminal) -> Vec<String> {
+    let mut lines: Vec<String> = terminal.lines().map(|line| line.text()).collect();
+
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+
+    lines
+}
+
+/// Merge consecutive cells with identical pens (and overlay attributes) into spans
+fn merge_cells_to_spans(line: &avt::Line, overlay: Option<&LineOverlay>) -> Vec<SnapshotSpan> {
+    let mut spans = Vec::new();
+    let mut current_text = String::new();
+    let mut current_fg: Option<ColorValue> = None;
+    let mut current_bg: Option<ColorValue> = None;
+    let mut current_bold = false;
+    let mut current_faint = false;
+    let mut current_italic = false;
+    let mut current_underline = false;
+    let mut current_underline_style: Option<UnderlineStyle> = None;
+    let mut current_underline_color: Option<&ColorValue> = None;
+    let mut current_strikethrough = false;
+    let mut current_blink = false;
+    let mut current_rapid_blink = false;
+    let mut current_inverse = false;
+    let mut current_url: Option<&str> = None;
+    let mut current_protected = false;
+    let mut current_widths: Vec<u8> = Vec::new();
+
+    for (col, cell) in line.cells().iter().enumerate() {
+        // Skip zero-width cells (continuation of wide chars)
+        if cell.width() == 0 {
+            continue;
+        }
+
+        let pen = cell.pen();
+
+        // Map colors via accessor methods
+        let fg = pen.foreground().map(|c| map_color(&c));
+        let bg = pen.background().map(|c| map_color(&c));
+
+        // Use Pen's boolean accessor methods
+        let bold = pen.is_bold();
+        let faint = pen.is_faint();
+        let italic = pen.is_italic();
+        let underline = pen.is_underline();
+        let strikethrough = pen.is_strikethrough();
+        let inverse = pen.is_inverse();
+        let attrs = overlay.and_then(|overlay| overlay.get(col));
+        let url = attrs.and_then(|attrs| attrs.url.as_deref());
+        let rapid_blink = attrs.is_some_and(|attrs| attrs.rapid_blink);
+        let protected = attrs.is_some_and(|attrs| attrs.protected);
+        // The underline's style and color only show while it's on
+        let underline_attrs = attrs.filter(|_| underline);
+        let underline_style = underline_attrs.and_then(|attrs| attrs.underline_style);
+        let underline_color = underline_attrs.and_then(|attrs| attrs.underline_color.as_ref());
+        // Rapid blink replaces the slow blink avt may still have in the pen
+        let blink = pen.is_blink() && !rapid_blink;
+
+        // Check if attributes match the current span
+        let attrs_match = fg == current_fg
+            && bg == current_bg
+            && bold == current_bold
+            && faint == current_faint
+            && italic == current_italic
+            && underline == current_underline
+            && underline_style == current_underline_style
+            && underline_color == current_underline_color
+            && strikethrough == current_strikethrough
+            && blink == current_blink
+            && rapid_blink == current_rapid_blink
+            && inverse == current_inverse
+            && url == current_url
+            && protected == current_protected;
+
+        if attrs_match && !current_text.is_empty() {
+            // Continue current span
+            current_text.push(cell.char());
+            current_widths.push(cell.width() as u8);
+        } else {
+            // Flush current span if non-empty
+            if !current_text.is_empty() {
+                spans.push(SnapshotSpan {
+                    text: current_text.clone(),
+                    fg: current_fg.clone(),
+                    bg: current_bg.clone(),
+                    bold: current_bold,
+                    faint: current_faint,
+                    italic: current_italic,
+                    underline: current_underline,
+                    underline_style: current_underline_style,
+                    underline_color: current_underline_color.cloned(),
+                    strikethrough: current_strikethrough,
+                    blink: current_blink,
+                    rapid_blink: current_rapid_blink,
+                    inverse: current_inverse,
+                    url: current_url.map(str::to_string),
+                    protected: current_protected,
+                    widths: wide_widths(&current_widths),
+                    col: None,
+                });
+                current_text.clear();
+                current_widths.clear();
+            }
+
+            // Start new span
+            current_text.push(cell.char());
+            current_widths.push(cell.width() as u8);
+            current_fg = fg;
+            current_bg = bg;
+            current_bold = bold;
+            current_faint = faint;
+            current_italic = italic;
+            current_underline = underline;
+            current_underline_style = underline_style;
+            current_underline_color = underline_color;
+            current_strikethrough = strikethrough;
+            current_blink = blink;
+            current_rapid_blink = rapid_blink;
+            current_inverse = inverse;
+            current_url = url;
+            current_protected = protected;
+        }
+    }
+
+    // Flush final span
+    if !current_text.is_empty() {
+        spans.push(SnapshotSpan {
+            text: current_text,
+            fg: current_fg,
+            bg: current_bg,
+            bold: current_bold,
+            faint: current_faint,
+            italic: current_italic,
+            underline: current_underline,
+            underline_style: current_underline_style,
+            underline_color: current_underline_color.cloned(),
+            strikethrough: current_strikethrough,
+            blink: current_blink,
+            rapid_blink: current_rapid_blink,
+            inverse: current_inverse,
+            url: current_url.map(str::to_string),
+            protected: current_protected,
+            widths: wide_widths(&current_widths),
+            col: None,
+        });
+    }
+
+    spans
+}
+
+/// A line's text as a single unstyled span (none for an empty line)
+fn plain_spans(spans: &[SnapshotSpan]) -> Vec<SnapshotSpan> {
+    let mut text = String::new();
+    let mut widths = Vec::new();
+
+    for span in spans {
+        text.push_str(&span.text);
+        match &span.widths {
+            Some(span_widths) => widths.extend(span_widths),
+            None => widths.extend(std::iter::repeat_n(1, span.text.chars().count())),
+        }
+    }
+
+    if text.is_empty() {
+        return Vec::new();
+    }
+
+    vec![SnapshotSpan {
+        text,
+        fg: None,
+        bg: None,
+        bold: false,
+        faint: false,
+        italic: false,
+        underline: false,
+        underline_style: None,
+        underline_color: None,
+        strikethrough: false,
+        blink: false,
+        rapid_blink: false,
+        inverse: false,
+        url: None,
+        protected: false,
+        widths: wide_widths(&widths),
+        col: None,
+    }]
+}
+
+/// Split spans into one per cell (a wide character being one cell), keeping their styles
+fn cell_spans(spans: Vec<SnapshotSpan>) -> Vec<SnapshotSpan> {
+    let mut cells = Vec::new();
+
+    for span in spans {
+        for (i, ch) in span.text.chars().enumerate() {
+            let width = span.widths.as_ref().map_or(1, |widths| widths[i]);
+
+            cells.push(SnapshotSpan {
+                text: ch.to_string(),
+                widths: wide_widths(&[width]),
+                ..span.clone()
+            });
+        }
+    }
+
+    cells
+}
+
+/// Turn off the attributes `options` strips, merging spans left looking alike
+fn mask_spans(spans: Vec<SnapshotSpan>, options: &MaskOptions) -> Vec<SnapshotSpan> {
+    let mut masked: Vec<SnapshotSpan> = Vec::new();
+
+    for mut span in spans {
+        if options.strip_blink {
+            span.blink = false;
+            span.rapid_blink = false;
+        }
+        span.faint &= !options.strip_faint;
+        span.italic &= !options.strip_italic;
+
+        match masked.last_mut() {
+            Some(last) if same_style(last, &span) => {
+                let mut widths = span_widths(last);
+                widths.extend(span_widths(&span));
+                last.text.push_str(&span.text);
+                last.widths = wide_widths(&widths);
+            }
+            _ => masked.push(span),
+        }
+    }
+
+    masked
+}
+
+/// Whether two spans differ only in their text
+fn same_style(a: &SnapshotSpan, b: &SnapshotSpan) -> bool {
+    span_style(a) == span_style(b)
+}
+
+/// A span's style: the span without its text, widths and column
+fn span_style(span: &SnapshotSpan) -> SnapshotSpan {
+    SnapshotSpan {
+        text: String::new(),
+        widths: None,
+        col: None,
+        ..span.clone()
+    }
+}
+
+/// Column width of each character of a span
+fn span_widths(span: &SnapshotSpan) -> Vec<u8> {
+    match &span.widths {
+        Some(widths) => widths.clone(),
+        None => vec![1; span.text.chars().count()],
+    }
+}
+
+/// Cut spans to columns `start..end`; the columns of a wide character cut by an edge that
+/// fall inside become spaces with its pen
+fn clip_spans(spans: &[SnapshotSpan], start: usize, end: usize) -> Vec<SnapshotSpan> {
+    let mut clipped = Vec::new();
+    let mut col = 0;
+
+    for span in spans {
+        let mut text = String::new();
+        let mut widths = Vec::new();
+
+        for (i, ch) in span.text.chars().enumerate() {
+            let width = span.widths.as_ref().map_or(1, |widths| widths[i]);
+            let next = col + width as usize;
+
+            if col >= start && next <= end {
+                text.push(ch);
+                widths.push(width);
+            } else {
+                let inside = next.min(end).saturating_sub(col.max(start));
+                text.extend(std::iter::repeat_n(' ', inside));
+                widths.extend(std::iter::repeat_n(1, inside));
+            }
+            col = next;
+        }
+
+        if !text.is_empty() {
+            clipped.push(SnapshotSpan {
+                text,
+                widths: wide_widths(&widths),
+                ..span.clone()
+            });
+        }
+    }
+
+    clipped
+}
+
+/// Number each span with the grid column it starts at
+fn set_span_columns(spans: &mut [SnapshotSpan]) {
+    let mut col = 0;
+
+    for span in spans {
+        span.col = Some(col);
+        col += span_widths(span).iter().map(|&width| width as usize).sum::<usize>();
+    }
+}
+
+/// Per-character column widths of a span, kept only when it contains a wide character
+fn wide_widths(widths: &[u8]) -> Option<Vec<u8>> {
+    widths.contains(&2).then(|| widths.to_vec())
+}
+
+/// Give every span concrete colors, swapping them for inverse video
+fn fill_default_colors(lines: &mut [SnapshotLine], fg: &ColorValue, bg: &ColorValue) {
+    for span in lines.iter_mut().flat_map(|line| line.spans.iter_mut()) {
+        fill_span_colors(span, fg, bg);
+    }
+}
+
+/// Give a span concrete colors, swapping them for inverse video
+fn fill_span_colors(span: &mut SnapshotSpan, fg: &ColorValue, bg: &ColorValue) {
+    let mut span_fg = span.fg.take().unwrap_or_else(|| fg.clone());
+    let mut span_bg = span.bg.take().unwrap_or_else(|| bg.clone());
+
+    if span.inverse {
+        std::mem::swap(&mut span_fg, &mut span_bg);
+        span.inverse = false;
+    }
+
+    span.fg = Some(span_fg);
+    span.bg = Some(span_bg);
+}
+
+/// Replace indexed span colors with their RGB hex value from `palette`
+fn resolve_snapshot_colors(lines: &mut [SnapshotLine], palette: &Palette) {
+    for span in lines.iter_mut().flat_map(|line| line.spans.iter_mut()) {
+        let colors = [&mut span.fg, &mut span.bg, &mut span.underline_color];
+        for color in colors.into_iter().flatten() {
+            if let ColorValue::Indexed(index) = *color {
+                *color = ColorValue::Rgb(palette::rgb_hex(palette.rgb(index)));
+            }
+        }
+    }
+}
+
+/// Map avt Color to serializable ColorValue
+fn map_color(color: &Color) -> ColorValue {
+    match color {
+        Color::Indexed(n) => ColorValue::Indexed(*n),
+        Color::RGB(rgb) => ColorValue::Rgb(palette::rgb_hex((rgb.r, rgb.g, rgb.b))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_and_feed() {
+        let mut vt = create(80, 24, 0);
+        let result = vt.feed("hello");
+        // Should return changed line indices
+        assert!(!result.is_null());
+    }
+
+    #[test]
+    fn test_get_view() {
+        let mut vt = create(80, 24, 0);
+        vt.feed("hello world");
+        let view = vt.get_view();
+        assert!(!view.is_null());
+    }
+
+    #[test]
+    fn test_cursor_position() {
+        let mut vt = create(80, 24, 0);
+        vt.feed("hi");
+        let cursor = vt.get_cursor();
+        assert!(!cursor.is_null());
+    }
+
+    #[test]
+    fn test_get_size() {
+        let vt = create(80, 24, 0);
+        let size = vt.get_size();
+        assert!(!size.is_null());
+    }
+
+    #[test]
+    fn test_feed_bytes_split_sequence() {
+        let mut vt = create(80, 24, 0);
+        // "é" is 0xC3 0xA9 in UTF-8; feed it one byte at a time
+        vt.feed_bytes_rows(&[0xC3]).unwrap();
+        assert_eq!(vt.utf8_tail, vec![0xC3]);
+        assert_eq!(vt.terminal.view().next().unwrap().text().trim_end(), "");
+
+        vt.feed_bytes_rows(&[0xA9]).unwrap();
+        assert!(vt.utf8_tail.is_empty());
+        assert_eq!(vt.terminal.view().next().unwrap().text().trim_end(), "é");
+    }
+
+    #[test]
+    fn test_feed_result_ok() {
+        let mut vt = create(80, 24, 0);
+        // A fresh terminal reports every row dirty on the first feed
+        vt.feed_rows("hello").unwrap();
+        let result = FeedResult::from(vt.feed_rows("\r\nworld"));
+        assert_eq!(result, FeedResult::Ok(vec![1]));
+    }
+
+    #[test]
+    fn test_feed_changes_scrollback_added() {
+        let mut vt = create(10, 3, 100);
+        let changes = vt.feed_changes("a\r\nb\r\nc").unwrap();
+        assert_eq!(changes.scrollback_added, 0);
+        assert!(!changes.resized);
+
+        let changes = vt.feed_changes("\r\nd\r\ne").unwrap();
+        assert_eq!(changes.scrollback_added, 2);
+
+        // Lines evicted by a full scrollback still count as added history
+        let mut capped = create(10, 2, 1);
+        let changes = capped.feed_changes("a\r\nb\r\nc\r\nd").unwrap();
+        assert_eq!(changes.scrollback_added, 2);
+    }
+
+    #[test]
+    fn test_feed_changes_evicted() {
+        let mut vt = create(10, 2, 3);
+        let changes = vt.feed_changes("1\r\n2\r\n3\r\n4\r\n5").unwrap();
+        assert_eq!((changes.scrollback_added, changes.evicted), (3, 0));
+
+        // Once history is full, every new line pushes the oldest one out
+        let changes = vt.feed_changes("\r\n6\r\n7").unwrap();
+        assert_eq!((changes.scrollback_added, changes.evicted), (2, 2));
+        assert_eq!(all_text(&vt.terminal)[0].trim_end(), "3");
+
+        vt.feed_rows("\x1b[?1049h").unwrap();
+        let changes = vt.feed_changes("\r\n\r\n\r\n").unwrap();
+        assert_eq!(changes.evicted, 0);
+    }
+
+    #[test]
+    fn test_snapshot_wrapped_flag() {
+        let mut vt = create(10, 3, 0);
+        vt.feed_rows("0123456789abc\r\nshort").unwrap();
+
+        let snapshot = create_snapshot(&vt);
+        assert!(snapshot.lines[0].wrapped);
+        assert!(!snapshot.lines[1].wrapped);
+        assert!(!snapshot.lines[2].wrapped);
+    }
+
+    #[test]
+    fn test_view_diff_only_changed_rows() {
+        let mut vt = create(20, 4, 0);
+        vt.feed_rows("first line").unwrap();
+
+        let diff = vt.view_diff();
+        assert_eq!(diff.len(), 4);
+
+        vt.feed_rows("\r\nsecond line").unwrap();
+        let diff = vt.view_diff();
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].row, 1);
+        assert_eq!(diff[0].line.spans[0].text.trim_end(), "second line");
+
+        assert!(vt.view_diff().is_empty());
+
+        vt.reset_diff_baseline();
+        assert_eq!(vt.view_diff().len(), 4);
+    }
+
+    #[test]
+    fn test_to_html_styles() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b[1;31m<red>\x1b[0m & plain").unwrap();
+
+        let html = vt.to_html();
+        assert!(html.contains("font-weight:bold"));
+        assert!(html.contains("color:#CD0000"));
+        assert!(html.contains("&lt;red&gt;"));
+        assert!(html.contains(" &amp; plain"));
+        assert_eq!(html.matches("<div class=\"line\">").count(), 2);
+    }
+
+    #[test]
+    fn test_export_inverse() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b[7minv\x1b[31mred\x1b[0m plain").unwrap();
+        let hex = |index| palette::rgb_hex(palette::xterm_rgb(index));
+
+        // Default colors swap too: text in the default background on the default foreground
+        let html = vt.to_html();
+        assert!(html.contains(&format!("color:{};background-color:{}\">inv", hex(0), hex(7))));
+        assert!(html.contains(&format!("color:{};background-color:{}\">red", hex(0), hex(1))));
+        assert!(html.contains("<span> plain"));
+
+        let ansi = vt.to_ansi(false);
+        assert!(ansi.starts_with("\x1b[0;38;2;0;0;0;48;2;229;229;229minv"));
+        assert!(ansi.contains("\x1b[0;38;2;0;0;0;48;5;1mred"));
+        assert!(!ansi.contains(";7"));
+
+        vt.default_colors = DefaultColors::parse(Some("#112233"), Some("#445566")).unwrap();
+        let rtf = vt.to_rtf();
+        assert!(rtf.contains("\\red68\\green85\\blue102;\\red17\\green34\\blue51;"));
+    }
+
+    #[test]
+    fn test_to_rtf() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b[1;31mhot\x1b[0m {a\\b} é").unwrap();
+        let rtf = vt.to_rtf();
+
+        assert!(rtf.starts_with("{\\rtf1"));
+        // Palette red, as the first color table entry
+        assert!(rtf.contains("{\\colortbl;\\red205\\green0\\blue0;}"));
+        assert!(rtf.contains("\\cf1\\b hot\\cf0\\b0  \\{a\\\\b\\} \\u233?"));
+        assert_eq!(rtf.matches("\\par\n").count(), 2);
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let mut vt = create(12, 4, 10);
+        vt.feed_rows("\x1b[1;31mold\x1b[0m\r\n\r\n\r\n\r\nsay \x1b[4mhi\x1b[0m  ").unwrap();
+        assert_eq!(vt.to_markdown(false), "```\n\n\n\nsay hi\n```\n");
+        assert_eq!(vt.to_markdown(true), "```\nold\n\n\n\nsay hi\n```\n");
+
+        vt.feed_rows("\r\n```rust\r\n`").unwrap();
+        assert!(vt.to_markdown(false).starts_with("````\n"));
+        assert!(vt.to_markdown(false).ends_with("\n```rust\n`\n````\n"));
+    }
+
+    #[test]
+    fn test_to_ansi_quantized() {
+        let mut vt = create(30, 1, 0);
+        vt.feed_rows("\x1b[38;2;250;10;10;48;2;0;0;200mrgb\x1b[0m \x1b[38;5;226;41mcube\x1b[0m")
+            .unwrap();
+
+        // Near-red on near-blue, the cube's yellow, and the basic red background kept
+        let ansi = vt.to_ansi_quantized(16, false);
+        assert!(ansi.starts_with("\x1b[0;91;44mrgb"));
+        assert!(ansi.contains("\x1b[0;93;41mcube"));
+        assert!(!ansi.contains("38;2") && !ansi.contains("38;5"));
+
+        // Bright colors aren't available with 8
+        let ansi = vt.to_ansi_quantized(8, false);
+        assert!(ansi.starts_with("\x1b[0;31;44mrgb"));
+        assert!(ansi.contains("\x1b[0;33;41mcube"));
+    }
+
+    #[test]
+    fn test_to_ansi_round_trip() {
+        let mut vt = create(20, 3, 0);
+        vt.feed_rows("\x1b[1;31mred\x1b[0m plain \x1b[38;2;255;128;0;44mrgb\x1b[0m\r\n")
+            .unwrap();
+        vt.feed_rows("\x1b[3;4;38;5;200mfancy\x1b[0m").unwrap();
+
+        let ansi = vt.to_ansi(false);
+        assert!(ansi.contains("\x1b[0;1;38;5;1m"));
+        assert!(ansi.contains("38;2;255;128;0"));
+        assert!(!ansi.ends_with("\r\n"));
+        assert!(vt.to_ansi(true).ends_with("\r\n"));
+
+        let mut copy = create(20, 3, 0);
+        copy.feed_rows(&ansi).unwrap();
+        assert_eq!(view_text(&copy.terminal), view_text(&vt.terminal));
+        assert_eq!(create_snapshot(&copy), create_snapshot(&vt));
+    }
+
+    #[test]
+    fn test_xterm_palette() {
+        assert_eq!(palette::xterm_rgb(1), (0xCD, 0x00, 0x00));
+        assert_eq!(palette::xterm_rgb(16), (0, 0, 0));
+        assert_eq!(palette::xterm_rgb(196), (0xFF, 0, 0));
+        assert_eq!(palette::xterm_rgb(255), (0xEE, 0xEE, 0xEE));
+    }
+
+    #[test]
+    fn test_panic_message_payloads() {
+        assert_eq!(panic_message(Box::new("boom")), "boom");
+        assert_eq!(panic_message(Box::new("bang".to_string())), "bang");
+        assert_eq!(panic_message(Box::new(42)), "unknown panic");
+    }
+
+    #[test]
+    fn test_dump_restore_round_trip() {
+        let mut vt = create(20, 5, 0);
+        vt.feed_rows("\x1b[1;31mred\x1b[0m plain\r\n\x1b[42mgreen bg\x1b[3;7H")
+            .unwrap();
+
+        let restored = restore(20, 5, 0, &vt.dump());
+
+        assert_eq!(create_snapshot(&restored), create_snapshot(&vt));
+        assert_eq!(restored.terminal.cursor(), vt.terminal.cursor());
+    }
+
+    #[test]
+    fn test_view_graphemes() {
+        let mut vt = create(20, 2, 0);
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        vt.feed_rows(&format!("e\u{301}x {family}\r\n\u{1F1FA}\u{1F1F8}!")).unwrap();
+
+        let rows = view_graphemes(&vt.terminal);
+        assert_eq!(rows[0], vec!["e\u{301}", "x", " ", family]);
+        assert_eq!(rows[1], vec!["\u{1F1FA}\u{1F1F8}", "!"]);
+    }
+
+    #[test]
+    fn test_char_grid() {
+        let mut vt = create(4, 1, 0);
+        vt.feed_rows("AB").unwrap();
+        assert_eq!(char_grid(&vt.terminal), vec![vec!["A", "B", " ", " "]]);
+
+        vt.feed_rows("\r\u{4E2D}").unwrap();
+        assert_eq!(char_grid(&vt.terminal), vec![vec!["\u{4E2D}", "", " ", " "]]);
+    }
+
+    #[test]
+    fn test_text_extraction() {
+        let mut vt = create(8, 3, 100);
+        vt.feed_rows("\x1b[1mone\x1b[0m\r\ntwo\r\nthree\r\nfour").unwrap();
+
+        assert_eq!(view_text(&vt.terminal), vec!["two     ", "three   ", "four    "]);
+        assert_eq!(all_text(&vt.terminal).len(), 4);
+        assert_eq!(all_text(&vt.terminal)[0], "one     ");
+
+        let empty = create(8, 3, 100);
+        assert!(all_text(&empty.terminal).is_empty());
+    }
+
+    #[test]
+    fn test_osc8_hyperlink_spans() {
+        let mut vt = create(30, 3, 10);
+        vt.feed_rows("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\ plain\r\n")
+            .unwrap();
+        vt.feed_rows("\x1b]8;id=1;https://b.example\x07other\x1b]8;;\x07").unwrap();
+
+        let spans = &create_snapshot(&vt).lines[0].spans;
+        assert_eq!(spans[0].text, "link");
+        assert_eq!(spans[0].url.as_deref(), Some("https://example.com"));
+        assert_eq!(spans[1].text.trim_end(), " plain");
+        assert_eq!(spans[1].url, None);
+
+        let spans = &create_snapshot(&vt).lines[1].spans;
+        assert_eq!(spans[0].url.as_deref(), Some("https://b.example"));
+
+        // Links keep their target once scrolled into history
+        vt.feed_rows("\r\n\r\n\r\n").unwrap();
+        let full = create_full_snapshot(&vt, true);
+        assert_eq!(full.lines[0].spans[0].url.as_deref(), Some("https://example.com"));
+        assert_eq!(full.lines[1].spans[0].url.as_deref(), Some("https://b.example"));
+    }
+
+    #[test]
+    fn test_active_pen() {
+        let mut vt = create(10, 2, 0);
+        let pen = vt.active_pen();
+        assert!(pen.fg.is_none() && !pen.bold);
+
+        vt.feed_rows("\x1b[1;31m").unwrap();
+        let pen = vt.active_pen();
+        assert!(pen.bold && !pen.italic);
+        assert_eq!(pen.fg, Some(ColorValue::Indexed(1)));
+        assert_eq!(pen.bg, None);
+
+        // Printing leaves the pen as it was; what avt doesn't model comes from the overlay
+        vt.feed_rows("a\x1b[22;6;48;2;0;0;255m\x1b]8;;http://x\x07b").unwrap();
+        let pen = vt.active_pen();
+        assert!(!pen.bold && pen.rapid_blink && !pen.blink);
+        assert_eq!(pen.fg, Some(ColorValue::Indexed(1)));
+        assert_eq!(pen.bg, Some(ColorValue::Rgb("#0000FF".to_string())));
+        assert_eq!(pen.url.as_deref(), Some("http://x"));
+
+        vt.set_resolve_colors(true);
+        vt.feed_rows("\x1b[0m\x1b]8;;\x07\x1b[32m").unwrap();
+        let pen = vt.active_pen();
+        assert_eq!(pen.fg, Some(ColorValue::Rgb(palette::rgb_hex(palette::xterm_rgb(2)))));
+        assert!(pen.bg.is_none() && !pen.rapid_blink && pen.url.is_none());
+    }
+
+    #[test]
+    fn test_cursor_style_decscusr() {
+        let mut vt = create(10, 2, 0);
+        assert_eq!(vt.cursor_state().shape, CursorShape::Block);
+        assert!(vt.cursor_state().blink);
+
+        vt.feed_rows("ab\x1b[4 q").unwrap();
+        let state = vt.cursor_state();
+        assert_eq!(state.shape, CursorShape::Underline);
+        assert!(!state.blink);
+        assert_eq!((state.col, state.row, state.visible), (2, 0, true));
+
+        vt.feed_rows("\x1b[5 q").unwrap();
+        assert_eq!(vt.cursor_state().shape, CursorShape::Bar);
+
+        vt.feed_rows("\x1bc").unwrap();
+        assert_eq!(vt.cursor_style, CursorStyle::default());
+    }
+
+    #[test]
+    fn test_cursor_under() {
+        let mut vt = create(10, 2, 0);
+        vt.feed_rows("a\x1b[1;32;44mbc\x1b[0m  \x1b[4m \x1b[0m").unwrap();
+
+        vt.feed_rows("\x1b[1;3H").unwrap();
+        let under = vt.cursor_state().under.unwrap();
+        assert_eq!(under, vt.cell_info(2, 0).unwrap());
+        assert_eq!(under.ch, "c");
+        assert_eq!(under.fg, Some(ColorValue::Indexed(2)));
+        assert_eq!(under.bg, Some(ColorValue::Indexed(4)));
+        assert!(under.bold);
+
+        // A blank before more content is still content; the underlined blank ends it
+        vt.feed_rows("\x1b[1;5H").unwrap();
+        assert_eq!(vt.cursor_state().under.unwrap().ch, " ");
+        vt.feed_rows("\x1b[1;7H").unwrap();
+        assert!(vt.cursor_state().under.is_none());
+        vt.feed_rows("\x1b[2;1H").unwrap();
+        assert!(vt.cursor_state().under.is_none());
+    }
+
+    #[test]
+    fn test_title_osc() {
+        let mut vt = create(10, 2, 0);
+        assert_eq!(vt.title, None);
+
+        vt.feed_rows("\x1b]0;first\x07text\x1b]2;second title\x1b\\").unwrap();
+        assert_eq!(vt.title.as_deref(), Some("second title"));
+        assert_eq!(view_text(&vt.terminal)[0].trim_end(), "text");
+
+        // Split across feeds
+        vt.feed_rows("\x1b]2;thi").unwrap();
+        vt.feed_rows("rd\x07").unwrap();
+        assert_eq!(vt.title.as_deref(), Some("third"));
+    }
+
+    #[test]
+    fn test_oversized_osc_dropped() {
+        let mut vt = create(10, 2, 0);
+        let payload = "x".repeat(scanner::MAX_OSC_LEN + 1);
+        vt.feed_rows(&format!("\x1b]2;{payload}")).unwrap();
+        assert!(vt.scanner.pending().len() <= scanner::MAX_OSC_LEN);
+        assert!(vt.dump().len() < scanner::MAX_OSC_LEN);
+
+        vt.feed_rows("more\x07text").unwrap();
+        assert_eq!(vt.title, None);
+        assert_eq!(view_text(&vt.terminal)[0].trim_end(), "text");
+        assert_eq!(vt.scanner.pending(), "");
+
+        // The cap doesn't affect the next OSC
+        vt.feed_rows("\x1b]2;title\x07").unwrap();
+        assert_eq!(vt.title.as_deref(), Some("title"));
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        let mut vt = create(10, 2, 10);
+        vt.feed_rows("one\r\ntwo\r\nthree").unwrap();
+        let history = create_full_snapshot(&vt, true);
+        assert!(!vt.is_alternate_screen());
+
+        vt.feed_rows("\x1b[?1049h\x1b[Hvim screen").unwrap();
+        assert!(vt.is_alternate_screen());
+        assert_eq!(view_text(&vt.terminal)[0].trim_end(), "vim screen");
+        assert_eq!(create_full_snapshot(&vt, true), history);
+
+        vt.feed_rows("\x1b[?1049l").unwrap();
+        assert!(!vt.is_alternate_screen());
+        assert_eq!(create_full_snapshot(&vt, true), history);
+
+        vt.feed_rows("\x1b[?47h").unwrap();
+        assert!(vt.is_alternate_screen());
+        vt.feed_rows("\x1b[?47l").unwrap();
+        assert!(!vt.is_alternate_screen());
+    }
+
+    #[test]
+    fn test_grow_pulls_scrollback_back() {
+        let mut vt = create(10, 3, 10);
+        vt.feed_rows("\x1b]8;;https://a\x1b\\1\x1b]8;;\x1b\\\r\n2\r\n3\r\n4\r\n5").unwrap();
+        assert_eq!(vt.scrollback_len(), 2);
+        assert_eq!(vt.terminal.cursor().row, 2);
+
+        vt.resize(10, 6);
+        let view = view_text(&vt.terminal);
+        let rows: Vec<&str> = view.iter().map(|row| row.trim_end()).collect();
+        assert_eq!(rows, vec!["1", "2", "3", "4", "5", ""]);
+        assert_eq!(vt.scrollback_len(), 0);
+        // The cursor stays on its line, now within the grown viewport
+        assert_eq!((vt.terminal.cursor().col, vt.terminal.cursor().row), (1, 4));
+
+        // The hyperlink came back with its line, and new output lands on the cursor's row
+        assert_eq!(vt.cell_info(0, 0).unwrap().url.as_deref(), Some("https://a"));
+        vt.feed_rows("\x1b]8;;https://b\x1b\\x").unwrap();
+        assert_eq!(vt.cell_info(1, 4).unwrap().url.as_deref(), Some("https://b"));
+        assert_eq!(vt.cell_info(0, 4).unwrap().url, None);
+    }
+
+    #[test]
+    fn test_resize_with_reflow_round_trip() {
+        let paragraph: String = (0..120).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let mut vt = create(120, 5, 100);
+        vt.feed_rows(&format!("\x1b[1m{}\x1b[0m{}", &paragraph[..60], &paragraph[60..]))
+            .unwrap();
+        let original = create_full_snapshot(&vt, true);
+        let cursor = vt.terminal.cursor();
+
+        vt.resize_with_reflow(40, 5);
+        let narrow = all_text(&vt.terminal);
+        assert_eq!(narrow.len(), 3);
+        assert_eq!(narrow.concat(), paragraph);
+        assert!(create_full_snapshot(&vt, true).lines[0].spans[0].bold);
+
+        vt.resize_with_reflow(120, 5);
+        assert_eq!(all_text(&vt.terminal), vec![paragraph]);
+        assert_eq!(create_full_snapshot(&vt, true), original);
+        // The pending-wrap column past the last cell clamps back onto it
+        assert_eq!(vt.terminal.cursor().row, cursor.row);
+    }
+
+    #[test]
+    fn test_custom_palette_resolution() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b[31mred\x1b[0m \x1b[32mgreen").unwrap();
+
+        let spans = &vt.resolved(create_snapshot(&vt)).lines[0].spans;
+        assert_eq!(spans[0].fg, Some(ColorValue::Indexed(1)));
+
+        vt.palette = Palette::parse(&["#000000".to_string(), "#ff0000".to_string()]).unwrap();
+        vt.set_resolve_colors(true);
+        let spans = &vt.resolved(create_snapshot(&vt)).lines[0].spans;
+        assert_eq!(spans[0].fg, Some(ColorValue::Rgb("#FF0000".to_string())));
+        // Entries past the custom palette fall back to xterm
+        assert_eq!(spans[2].fg, Some(ColorValue::Rgb("#00CD00".to_string())));
+        assert!(vt.to_html().contains("color:#FF0000"));
+
+        assert!(Palette::parse(&["#12345".to_string()]).is_err());
+        assert!(Palette::parse(&vec!["#000000".to_string(); 257]).is_err());
+    }
+
+    #[test]
+    fn test_explicit_default_colors_inverse() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("plain \x1b[7minverse\x1b[0m").unwrap();
+        vt.default_colors = DefaultColors::parse(Some("#c0c0c0"), Some("#101010")).unwrap();
+        vt.set_explicit_default_colors(true);
+
+        let spans = &vt.resolved(create_snapshot(&vt)).lines[0].spans;
+        assert_eq!(spans[0].fg, Some(ColorValue::Rgb("#C0C0C0".to_string())));
+        assert_eq!(spans[0].bg, Some(ColorValue::Rgb("#101010".to_string())));
+        assert_eq!(spans[1].text, "inverse");
+        assert_eq!(spans[1].fg, Some(ColorValue::Rgb("#101010".to_string())));
+        assert_eq!(spans[1].bg, Some(ColorValue::Rgb("#C0C0C0".to_string())));
+        assert!(!spans[1].inverse);
+
+        // Unset defaults fall back to the palette's white and black
+        vt.default_colors = DefaultColors::default();
+        let spans = &vt.resolved(create_snapshot(&vt)).lines[0].spans;
+        assert_eq!(spans[0].fg, Some(ColorValue::Rgb("#E5E5E5".to_string())));
+        assert!(DefaultColors::parse(Some("red"), None).is_err());
+    }
+
+    #[test]
+    fn test_wide_char_widths() {
+        let mut vt = create(10, 1, 0);
+        vt.feed_rows("a界b世").unwrap();
+
+        let spans = &create_snapshot(&vt).lines[0].spans;
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text.chars().count(), 8);
+        let widths = spans[0].widths.as_ref().unwrap();
+        assert_eq!(&widths[..4], &[1, 2, 1, 2]);
+        let columns: usize = widths.iter().map(|&w| w as usize).sum();
+        assert_eq!(columns, vt.terminal.size().0);
+
+        let mut narrow = create(10, 1, 0);
+        narrow.feed_rows("abc").unwrap();
+        assert_eq!(create_snapshot(&narrow).lines[0].spans[0].widths, None);
+    }
+
+    #[test]
+    fn test_view_columns() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b[31mabcde\x1b[32mfghij\x1b[33mklmno\x1b[34mpqrst").unwrap();
+        vt.feed_rows("\r\n1\u{4E2D}\u{6587}x").unwrap();
+
+        let snapshot = vt.view_columns(7, 13);
+        let spans: Vec<(&str, Option<&ColorValue>)> = snapshot.lines[0]
+            .spans
+            .iter()
+            .map(|span| (span.text.as_str(), span.fg.as_ref()))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("hij", Some(&ColorValue::Indexed(2))),
+                ("klm", Some(&ColorValue::Indexed(3))),
+            ]
+        );
+
+        // Both wide characters are cut, one at each edge
+        let spans = &vt.view_columns(2, 4).lines[1].spans;
+        assert_eq!((spans.len(), spans[0].text.as_str()), (1, "  "));
+        assert_eq!(spans[0].widths, None);
+        let spans = &vt.view_columns(1, 5).lines[1].spans;
+        assert_eq!(spans[0].text, "\u{4E2D}\u{6587}");
+        assert_eq!(spans[0].widths, Some(vec![2, 2]));
+
+        assert!(vt.view_columns(5, 5).lines[0].spans.is_empty());
+        assert_eq!(vt.view_columns(18, 100).lines[0].spans[0].text, "st");
+    }
+
+    #[test]
+    fn test_all_lines_json() {
+        let mut vt = create(6, 3, 10);
+        assert_eq!(vt.all_lines_json(), r#"{"schema_version":2,"cols":6,"rows":3,"lines":[]}"#);
+
+        vt.feed_rows("\x1b[1;31mab\x1b[0m \"q\"\r\n\u{4E2D}\x1b]8;;http://x\x07y\x1b#6").unwrap();
+        assert_eq!(
+            vt.all_lines_json(),
+            concat!(
+                r#"{"schema_version":2,"cols":6,"rows":3,"lines":["#,
+                r#"{"spans":[{"text":"ab","fg":1,"bold":true},"#,
+                r#"{"text":" \"q\""}]},{"spans":["#,
+                "{\"text\":\"\u{4E2D}\",\"widths\":[2]},",
+                r#"{"text":"y","url":"http://x"},{"text":"   "}],"line_attr":"double-width"}]}"#
+            )
+        );
+
+        let full = |vt: &Vt| {
+            serde_json::to_string(&vt.resolved(create_full_snapshot(vt, true))).unwrap()
+        };
+        vt.feed_rows("\x1b[38;2;1;2;3mlong\r\n\u{1F600}\\hist\r\n\r\n\r\n\r\n").unwrap();
+        vt.set_resolve_colors(true);
+        vt.tick(0.0);
+        assert_eq!(vt.all_lines_json(), full(&vt));
+
+        // The alt screen reports the primary buffer captured on entering it
+        vt.feed_rows("\x1b[?1049halt").unwrap();
+        assert_eq!(vt.all_lines_json(), full(&vt));
+    }
+
+    #[test]
+    fn test_trim_trailing_spaces() {
+        let mut vt = create(8, 3, 0);
+        vt.feed_rows("abc   \r\n\x1b[1mx\x1b[0m  \x1b[44m  \x1b[0m\r\n\u{4E2D}z").unwrap();
+        let trim = AllLinesOptions {
+            trim_trailing_spaces: true,
+        };
+
+        let texts = |snapshot: TerminalSnapshot| -> Vec<Vec<String>> {
+            let lines = snapshot.lines.into_iter();
+            lines.map(|line| line.spans.into_iter().map(|span| span.text).collect()).collect()
+        };
+        assert_eq!(
+            texts(vt.all_lines_with_options(&AllLinesOptions::default()))[0],
+            vec!["abc     "]
+        );
+        assert_eq!(
+            texts(vt.all_lines_with_options(&trim)),
+            vec![
+                vec!["abc"],
+                // A colored background keeps its blanks, and the ones before it
+                vec!["x", "  ", "  "],
+                vec!["\u{4E2D}z"],
+            ]
+        );
+        let lines = vt.all_lines_with_options(&trim).lines;
+        assert_eq!(lines[2].spans[0].widths, Some(vec![2, 1]));
+    }
+
+    #[test]
+    fn test_lines_range() {
+        let mut vt = create(10, 3, 100);
+        vt.feed_rows("one\r\ntwo\r\n\r\nfour\r\nfive").unwrap();
+        assert_eq!(line_count(&vt.terminal), 5);
+
+        let line = vt.lines_range(1, 2).pop().unwrap();
+        assert_eq!(line.spans[0].text.trim_end(), "two");
+        assert_eq!(vt.lines_range(0, 5), create_full_snapshot(&vt, true).lines);
+
+        // Empty rows still come back as a line of blanks
+        let empty = vt.lines_range(2, 3).pop().unwrap();
+        assert!(empty.spans.iter().all(|span| span.text.trim().is_empty()));
+
+        assert!(vt.lines_range(5, 6).is_empty());
+        assert!(vt.lines_range(3, 1).is_empty());
+        assert_eq!(vt.lines_range(3, 100).len(), 2);
+    }
+
+    #[test]
+    fn test_get_cell() {
+        let mut vt = create(10, 2, 0);
+        vt.feed_rows("A\x1b[1;32m界\x1b[0mB").unwrap();
+
+        let a = vt.cell_info(0, 0).unwrap();
+        assert_eq!((a.ch.as_str(), a.width, a.bold), ("A", 1, false));
+
+        let wide = vt.cell_info(1, 0).unwrap();
+        assert_eq!((wide.ch.as_str(), wide.width), ("界", 2));
+        assert!(wide.bold);
+        assert_eq!(wide.fg, Some(ColorValue::Indexed(2)));
+
+        let continuation = vt.cell_info(2, 0).unwrap();
+        assert_eq!((continuation.ch.as_str(), continuation.width), ("界", 0));
+        assert!(continuation.bold);
+
+        let b = vt.cell_info(3, 0).unwrap();
+        assert_eq!((b.ch.as_str(), b.width), ("B", 1));
+
+        assert_eq!(vt.cell_info(4, 0).unwrap().ch, " ");
+        assert!(vt.cell_info(10, 0).is_none());
+        assert!(vt.cell_info(0, 2).is_none());
+    }
+
+    #[test]
+    fn test_region_text() {
+        let mut vt = create(10, 4, 0);
+        vt.feed_rows("abcdefghij0123456789\r\nx界yz\r\nlast row").unwrap();
+
+        // Rows 0-1 are one soft-wrapped line; row 2 has a wide char at columns 1-2
+        assert_eq!(vt.region_text((2, 0), (3, 2), true), "cd\n23\n界y");
+        assert_eq!(vt.region_text((3, 2), (2, 0), true), "cd\n23\n界y");
+        // Selecting only the right half of 界 still yields it, ending on its left half drops it
+        assert_eq!(vt.region_text((2, 2), (2, 2), true), "界");
+        assert_eq!(vt.region_text((1, 2), (1, 2), true), "");
+        assert_eq!(vt.region_text((0, 0), (1, 2), true), "ab
+01
+x");
+        assert_eq!(vt.region_text((2, 2), (3, 2), false), "界y");
+        assert_eq!(vt.region_text((5, 1), (1, 2), false), "56789
+x");
+
+        assert_eq!(
+            vt.region_text((8, 0), (1, 3), false),
+            "ij0123456789\nx界yz\nla"
+        );
+        assert_eq!(vt.region_text((1, 3), (8, 0), false), "ij0123456789\nx界yz\nla");
+        assert_eq!(vt.region_text((5, 3), (9, 3), false), "row");
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut vt = create(10, 3, 10);
+        vt.feed_rows("\x1b[1;31mone\r\ntwo\r\nthree\r\nfour\x1b[4 q\x1b[?25l\x1b[5;").unwrap();
+        assert_eq!(line_count(&vt.terminal), 4);
+
+        let rows = vt.reset_rows().unwrap();
+        assert_eq!(rows, vec![0, 1, 2]);
+        assert!(view_text(&vt.terminal).iter().all(|line| line.trim().is_empty()));
+        assert_eq!(line_count(&vt.terminal), 3);
+        let cursor = vt.terminal.cursor();
+        assert_eq!((cursor.col, cursor.row, cursor.visible), (0, 0, true));
+        assert_eq!(vt.cursor_style, CursorStyle::default());
+
+        vt.feed_rows("x").unwrap();
+        let span = &create_snapshot(&vt).lines[0].spans[0];
+        assert_eq!((span.fg.as_ref(), span.bold), (None, false));
+    }
+
+    #[test]
+    fn test_set_scrollback_limit() {
+        let mut vt = create(10, 3, 100);
+        for i in 0..10 {
+            vt.feed_rows(&format!("\x1b[1mline {i}\x1b[0m\r\n")).unwrap();
+        }
+        vt.feed_rows("\x1b[32mtail").unwrap();
+        assert_eq!(vt.scrollback_len(), 8);
+        let view = create_snapshot(&vt);
+        let cursor = vt.terminal.cursor();
+
+        vt.set_scrollback_limit(4);
+        assert_eq!(vt.scrollback_len(), 4);
+        assert_eq!(all_text(&vt.terminal)[0].trim_end(), "line 4");
+        assert!(create_full_snapshot(&vt, true).lines[0].spans[0].bold);
+        assert_eq!(create_snapshot(&vt), view);
+        assert_eq!(vt.terminal.cursor(), cursor);
+
+        // The new cap applies to future output, with the pen carried over
+        vt.feed_rows("\r\nmore\r\nmore").unwrap();
+        assert_eq!(vt.scrollback_len(), 4);
+        assert_eq!(all_text(&vt.terminal)[0].trim_end(), "line 6");
+        let spans = &create_snapshot(&vt).lines[2].spans;
+        assert_eq!(spans[0].fg, Some(ColorValue::Indexed(2)));
+
+        vt.set_scrollback_limit(0);
+        assert_eq!(vt.scrollback_len(), 0);
+
+        vt.set_scrollback_limit(50);
+        vt.feed_rows(&"x\r\n".repeat(20)).unwrap();
+        assert_eq!(vt.scrollback_len(), 20);
+
+        // Deferred while the alt screen hides the primary history
+        vt.feed_rows("\x1b[?1049h").unwrap();
+        vt.set_scrollback_limit(5);
+        assert_eq!(vt.pending_scrollback_limit, Some(5));
+        vt.feed_rows("\x1b[?1049l").unwrap();
+        assert_eq!(vt.scrollback_len(), 5);
+    }
+
+    #[test]
+    fn test_feed_collecting_scrollback() {
+        let texts = |lines: &[SnapshotLine]| -> Vec<String> {
+            lines
+                .iter()
+                .map(|line| {
+                    let text: String = line.spans.iter().map(|s| s.text.as_str()).collect();
+                    text.trim_end().to_string()
+                })
+                .collect()
+        };
+        let mut vt = create(10, 2, 3);
+
+        let (_, lines) = vt.feed_collecting("a\r\nb", true).unwrap();
+        assert!(lines.is_empty());
+        let (_, lines) = vt.feed_collecting("\r\nc\r\nd", true).unwrap();
+        assert_eq!(texts(&lines), vec!["a", "b"]);
+
+        // More lines than the scrollback holds: all of them are returned, once
+        let (_, lines) = vt.feed_collecting("\r\n1\r\n2\r\n3\r\n4\r\n5", true).unwrap();
+        assert_eq!(texts(&lines), vec!["c", "d", "1", "2", "3"]);
+        assert_eq!(vt.scrollback_len(), 3);
+        let (_, lines) = vt.feed_collecting("x", true).unwrap();
+        assert!(lines.is_empty());
+
+        // Scrolling the alt screen adds no scrollback
+        let (_, lines) = vt.feed_collecting("\x1b[?1049h\r\n\r\n\r\n", true).unwrap();
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn test_feed_many_union() {
+        let chunks = ["one\r\n", "\x1b[3;1Hthree", "\x1b[1;5H!"].map(String::from);
+
+        let mut batched = create(10, 4, 0);
+        batched.feed_rows("").unwrap();
+        let result = batched.feed_many_rows(&chunks);
+
+        let mut single = create(10, 4, 0);
+        single.feed_rows("").unwrap();
+        let rows = single.feed_rows(&chunks.concat()).unwrap();
+
+        assert_eq!(result, BatchResult::Ok { ok: vec![0, 2] });
+        assert_eq!(result, BatchResult::Ok { ok: rows });
+        assert_eq!(create_snapshot(&batched), create_snapshot(&single));
+    }
+
+    // Reference decoder for `get_view_packed` on the JS side:
+    //
+    //     function decodePacked(bytes) {
+    //       const view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+    //       const utf8 = new TextDecoder();
+    //       let offset = 0;
+    //       const u8 = () => view.getUint8(offset++);
+    //       const u16 = () => { const v = view.getUint16(offset, true); offset += 2; return v; };
+    //       const u32 = () => { const v = view.getUint32(offset, true); offset += 4; return v; };
+    //       const color = (value) => {
+    //         const tag = value >>> 24;
+    //         if (tag === 1) return value & 0xff;
+    //         if (tag === 2) return '#' + (value & 0xffffff).toString(16).toUpperCase().padStart(6, '0');
+    //         return undefined;
+    //       };
+    //       const lines = [];
+    //       for (let lineCount = u32(); lines.length < lineCount; ) {
+    //         const spanCount = u32();
+    //         const wrapped = (u8() & 1) !== 0;
+    //         const spans = [];
+    //         for (let i = 0; i < spanCount; i++) {
+    //           const attrs = u16();
+    //           const fg = color(u32());
+    //           const bg = color(u32());
+    //           const length = u32();
+    //           const text = utf8.decode(bytes.subarray(offset, offset + length));
+    //           offset += length;
+    //           spans.push({
+    //             text, fg, bg,
+    //             bold: (attrs & 1) !== 0, faint: (attrs & 2) !== 0, italic: (attrs & 4) !== 0,
+    //             underline: (attrs & 8) !== 0, strikethrough: (attrs & 16) !== 0,
+    //             blink: (attrs & 32) !== 0, inverse: (attrs & 64) !== 0,
+    //             rapid_blink: (attrs & 128) !== 0,
+    //           });
+    //         }
+    //         lines.push({ spans, wrapped });
+    //       }
+    //       return lines;
+    //     }
+    fn unpack(bytes: &[u8]) -> Vec<SnapshotLine> {
+        let mut reader = PackedReader { bytes, offset: 0 };
+        let line_count = reader.u32();
+        (0..line_count).map(|_| reader.line()).collect()
+    }
+
+    /// Reference decoder for `feed_packed`: a row before each line
+    fn unpack_changes(bytes: &[u8]) -> Vec<(usize, SnapshotLine)> {
+        let mut reader = PackedReader { bytes, offset: 0 };
+        let change_count = reader.u32();
+        (0..change_count)
+            .map(|_| (reader.u32() as usize, reader.line()))
+            .collect()
+    }
+
+    struct PackedReader<'a> {
+        bytes: &'a [u8],
+        offset: usize,
+    }
+
+    impl PackedReader<'_> {
+        fn take(&mut self, n: usize) -> &[u8] {
+            let slice = &self.bytes[self.offset..self.offset + n];
+            self.offset += n;
+            slice
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn color(&mut self) -> Option<ColorValue> {
+            let value = self.u32();
+            match value & 0xFF00_0000 {
+                packed::TAG_INDEXED => Some(ColorValue::Indexed(value as u8)),
+                packed::TAG_RGB => Some(ColorValue::Rgb(palette::rgb_hex((
+                    (value >> 16) as u8,
+                    (value >> 8) as u8,
+                    value as u8,
+                )))),
+                _ => None,
+            }
+        }
+
+        fn line(&mut self) -> SnapshotLine {
+            let span_count = self.u32();
+            let wrapped = self.take(1)[0] & packed::WRAPPED != 0;
+            let spans = (0..span_count)
+                .map(|_| {
+                    let attrs = u16::from_le_bytes(self.take(2).try_into().unwrap());
+                    let fg = self.color();
+                    let bg = self.color();
+                    let length = self.u32() as usize;
+                    let text = String::from_utf8(self.take(length).to_vec()).unwrap();
+                    SnapshotSpan {
+                        text,
+                        fg,
+                        bg,
+                        bold: attrs & packed::BOLD != 0,
+                        faint: attrs & packed::FAINT != 0,
+                        italic: attrs & packed::ITALIC != 0,
+                        underline: attrs & packed::UNDERLINE != 0,
+                        underline_style: None,
+                        underline_color: None,
+                        strikethrough: attrs & packed::STRIKETHROUGH != 0,
+                        blink: attrs & packed::BLINK != 0,
+                        rapid_blink: attrs & packed::RAPID_BLINK != 0,
+                        inverse: attrs & packed::INVERSE != 0,
+                        url: None,
+                        protected: false,
+                        widths: None,
+                        col: None,
+                    }
+                })
+                .collect();
+            SnapshotLine {
+                spans,
+                wrapped,
+                line_attr: LineAttr::Normal,
+                changed_at: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_view_packed_round_trip() {
+        let mut vt = create(10, 3, 0);
+        vt.feed_rows("\x1b[1;31mred\x1b[0m \x1b[3;4;48;2;1;2;255mrgb\x1b[0m ü\x1b[7mé\x1b[0m")
+            .unwrap();
+        vt.feed_rows("\r\n0123456789wrapped").unwrap();
+
+        let bytes = vt.get_view_packed();
+        let lines = unpack(&bytes);
+
+        assert_eq!(lines, create_snapshot(&vt).lines);
+        assert!(lines[1].wrapped);
+        assert_eq!(lines[0].spans[0].fg, Some(ColorValue::Indexed(1)));
+        assert_eq!(lines[0].spans[2].bg, Some(ColorValue::Rgb("#0102FF".to_string())));
+    }
+
+    #[test]
+    fn test_feed_into() {
+        let mut vt = create(10, 3, 0);
+        // A new terminal reports every row once
+        vt.feed_rows("").unwrap();
+        let mut out = Vec::with_capacity(1024);
+        let buffer = out.as_ptr();
+
+        vt.feed_into("\x1b[1;31mred\x1b[0m\r\nnext", &mut out).unwrap();
+        let view = create_snapshot(&vt).lines;
+        assert_eq!(
+            unpack_changes(&out),
+            vec![(0, view[0].clone()), (1, view[1].clone())]
+        );
+
+        vt.feed_into("\x1b[3;1Hlast", &mut out).unwrap();
+        assert_eq!(unpack_changes(&out), vec![(2, create_snapshot(&vt).lines[2].clone())]);
+
+        vt.feed_into("\x1b[?25l", &mut out).unwrap();
+        assert_eq!(unpack_changes(&out), vec![]);
+        assert_eq!(out.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn test_feed_packed_in_place() {
+        let mut vt = create(10, 3, 0);
+        vt.feed_rows("").unwrap();
+        let mut copy = create(10, 3, 0);
+        copy.feed_rows("").unwrap();
+
+        let len = vt.feed_packed_in_place(&"x".repeat(30)).unwrap();
+        let mut expected = Vec::new();
+        copy.feed_into(&"x".repeat(30), &mut expected).unwrap();
+        assert_eq!(vt.packed_changes[..len], expected[..]);
+        let (buffer, capacity) = (vt.packed_changes_ptr(), vt.packed_changes.capacity());
+
+        // Later, smaller changes reuse the buffer
+        for input in ["\x1b[Hab", "\x1b[2;1Hcd", "\x1b[?25l"] {
+            let len = vt.feed_packed_in_place(input).unwrap();
+            copy.feed_into(input, &mut expected).unwrap();
+            assert_eq!(vt.packed_changes[..len], expected[..]);
+            assert_eq!(vt.packed_changes_ptr(), buffer);
+            assert_eq!(vt.packed_changes.capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn test_feed_large_scrolling_stream() {
+        let mut vt = create(80, 24, 1000);
+        let stream: String = (0..20_000).map(|i| format!("line {}\r\n", i)).collect();
+
+        let rows = vt.feed_rows(&stream).unwrap();
+
+        // Every viewport row changed once, however much scrolled past
+        assert_eq!(rows, (0..24).collect::<Vec<_>>());
+        assert_eq!(vt.terminal.lines().count(), 1000 + 24);
+        assert_eq!(view_text(&vt.terminal)[22].trim_end(), "line 19999");
+    }
+
+    #[test]
+    fn test_feed_huge_repeat() {
+        let mut vt = create(80, 24, 0);
+        // avt's parameters wrap at 16 bits, so this repeats the `x` 16960 times
+        vt.feed_rows("\x1b[31mx\x1b[1000000b").unwrap();
+
+        let text = view_text(&vt.terminal);
+        assert_eq!(text.len(), 24);
+        assert!(text.iter().all(|row| row.chars().count() == 80));
+        assert_eq!(text[22], "x".repeat(80));
+        assert_eq!(text[23].trim_end(), "x");
+
+        let snapshot = create_snapshot(&vt);
+        let spans = &snapshot.lines[0].spans;
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, "x".repeat(80));
+    }
+
+    #[test]
+    fn test_panic_rollback() {
+        let mut vt = create(20, 4, 100);
+        vt.feed_rows("\x1b]2;title\x07history\r\n\r\n\x1b]8;;http://a\x07link\x1b]8;;\x07\r\n")
+            .unwrap();
+        vt.feed_rows("\x1b#6wide\r\n\x1b[1;31mkept\x1b[4 q\x1b[?25l").unwrap();
+        let view = create_snapshot(&vt);
+        let all = create_full_snapshot(&vt, true);
+        let cursor = vt.cursor_state();
+        assert_eq!(view.lines[1].spans[0].url.as_deref(), Some("http://a"));
+        assert_eq!(view.lines[2].line_attr, LineAttr::DoubleWidth);
+
+        // avt has no reliably panicking input, so the panic comes from the guarded closure
+        let boom = |vt: &mut Vt| {
+            let input = concat!(
+                "\x1b]2;other\x07\x1b]11;#000000\x07\x1b]4;1;#00FF00\x07",
+                "\x1b]52;c;aGk=\x07\x07\x1b[5n",
+                "\x1b[2;1H\x1b]8;;http://b\x07over\x1b#5\x1bc\x1b[0 qhalf\r\n\r\n\r\n\r\n",
+            );
+            for ch in input.chars() {
+                vt.feed_char(ch);
+            }
+            panic!("boom")
+        };
+
+        vt.set_panic_rollback(true);
+        assert_eq!(vt.guarded(boom), Err::<(), _>("boom".to_string()));
+        assert_eq!(create_snapshot(&vt), view);
+        assert_eq!(create_full_snapshot(&vt, true), all);
+        assert_eq!(vt.cursor_state(), cursor);
+        assert_eq!(vt.title.as_deref(), Some("title"));
+        assert_eq!(vt.default_colors, DefaultColors::default());
+        assert_eq!(vt.palette, Palette::default());
+        assert_eq!((vt.clipboard.clone(), vt.responses.as_str(), vt.bells), (None, "", 0));
+
+        // The restored pen and terminal keep working
+        vt.feed_rows(" more").unwrap();
+        assert_eq!(view_text(&vt.terminal)[3].trim_end(), "kept more");
+        assert_eq!(create_snapshot(&vt).lines[3].spans[0].fg, Some(ColorValue::Indexed(1)));
+
+        vt.set_panic_rollback(false);
+        assert!(vt.guarded(boom).is_err());
+        assert_ne!(create_snapshot(&vt), view);
+    }
+
+    #[test]
+    fn test_export_cast() {
+        let mut vt = create(20, 5, 0);
+        vt.feed_rows("before").unwrap();
+        assert_eq!(vt.export_cast(), "");
+
+        vt.start_recording();
+        vt.feed_rows("hello\r\n").unwrap();
+        vt.resize(30, 6);
+        vt.feed_rows("\x1b[1m\"quoted\"\\").unwrap();
+
+        let cast = vt.export_cast();
+        let lines: Vec<&str> = cast.lines().collect();
+        assert_eq!(lines[0], r#"{"version": 2, "width": 20, "height": 5}"#);
+
+        let events: Vec<(f64, &str)> = lines[1..]
+            .iter()
+            .map(|line| {
+                let body = line.strip_prefix('[').unwrap().strip_suffix(']').unwrap();
+                let (time, rest) = body.split_once(", ").unwrap();
+                (time.parse().unwrap(), rest)
+            })
+            .collect();
+
+        assert_eq!(
+            events.iter().map(|(_, rest)| *rest).collect::<Vec<_>>(),
+            [
+                r#""r", "20x5""#,
+                r#""o", "hello\r\n""#,
+                r#""r", "30x6""#,
+                r#""o", "\u001b[1m\"quoted\"\\""#,
+            ]
+        );
+        assert!(events.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    }
+
+    #[test]
+    fn test_input_log() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("before").unwrap();
+        assert_eq!(vt.take_input_log(), "");
+
+        vt.set_input_logging(true);
+        vt.feed_rows("\x1b[31mhi there\r\n\u{e9}").unwrap();
+        let dump = vt.take_input_log();
+        assert_eq!(
+            dump,
+            concat!(
+                "00000000  1b 5b 33 31 6d 68 69 20 74 68 65 72 65 0d 0a c3  ",
+                "ESC [ 3 1 m h i SP t h e r e CR LF .\n",
+                "00000010  a9                                               .\n",
+            )
+        );
+        assert_eq!(vt.take_input_log(), "");
+
+        // Offsets carry on, and only the newest bytes within the limit are kept
+        vt.set_input_log_limit(4);
+        vt.feed_rows("\x07abcdef").unwrap();
+        let padding = " ".repeat(36);
+        assert_eq!(vt.take_input_log(), format!("00000014  63 64 65 66{padding}  c d e f\n"));
+    }
+
+    #[test]
+    fn test_render_cast_at() {
+        let cast = concat!(
+            r#"{"version": 2, "width": 10, "height": 3, "env": {"TERM": "xterm"}}"#,
+            "\n",
+            r#"[0.5, "o", "first\r\n"]"#,
+            "\n",
+            r#"[1.0, "o", "\u001b[1msecond\u001b[0m \ud83d\ude00"]"#,
+            "\n",
+            "not json\n",
+            r#"[1.2, "r", "12x4"]"#,
+            "\n",
+            r#"[0.9, "o", "!"]"#,
+            "\n",
+            r#"[null, "o", "?"]"#,
+            "\n",
+            r#"[3.0, "o", "\r\nlater"]"#,
+            "\n",
+        );
+
+        let text = |at| view_text(&cast_at(cast, at).unwrap().terminal);
+
+        let vt = cast_at(cast, 0.0).unwrap();
+        assert_eq!(vt.terminal.size(), (10, 3));
+        assert!(view_text(&vt.terminal).iter().all(|line| line.trim().is_empty()));
+
+        assert_eq!(text(0.7)[0].trim_end(), "first");
+        assert_eq!(text(0.7)[1].trim_end(), "");
+
+        // The late "!" and the untimed "?" play right after the resize
+        let partial = cast_at(cast, 1.5).unwrap();
+        assert_eq!(partial.terminal.size(), (12, 4));
+        assert_eq!(view_text(&partial.terminal)[1].trim_end(), "second 😀!?");
+        assert!(create_snapshot(&partial).lines[1].spans[0].bold);
+
+        assert_eq!(text(10.0)[2].trim_end(), "later");
+        assert!(cast_at("[0.1, \"o\", \"x\"]", 1.0).is_none());
+    }
+
+    #[test]
+    fn test_osc52_clipboard() {
+        let mut vt = create(20, 3, 0);
+        vt.feed_rows("\x1b]52;c;aGVsbG8gd29ybGQ=\x07").unwrap();
+
+        let clipboard = vt.clipboard.take().unwrap();
+        assert_eq!(clipboard.selection, "c");
+        assert_eq!(clipboard.text, "hello world");
+        assert!(vt.clipboard.is_none());
+
+        vt.feed_rows("\x1b]52;p;w6lsw6h2ZQ\x1b\\").unwrap();
+        let clipboard = vt.clipboard.take().unwrap();
+        assert_eq!((clipboard.selection.as_str(), clipboard.text.as_str()), ("p", "élève"));
+
+        vt.feed_rows("\x1b]52;c;?\x07\x1b]52;c;not base64!\x07").unwrap();
+        assert!(vt.clipboard.is_none());
+        assert_eq!(view_text(&vt.terminal)[0].trim_end(), "");
+
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("YQ").unwrap(), b"a");
+        assert!(decode_base64("Y").is_none());
+    }
+
+    #[test]
+    fn test_query_responses() {
+        let mut vt = create(10, 5, 0);
+        vt.feed_rows("\x1b[3;4H\x1b[6nab\x1b[6n\x1b[1;10Hx\x1b[6n").unwrap();
+        assert_eq!(vt.take_responses(), "\x1b[3;4R\x1b[3;6R\x1b[1;10R");
+        assert_eq!(vt.take_responses(), "");
+
+        vt.feed_rows("\x1b[5n\x1b[c\x1b[0c\x1b[>c\x1b[?6n").unwrap();
+        assert_eq!(vt.take_responses(), "\x1b[0n\x1b[?1;2c\x1b[?1;2c");
+
+        let mut custom = create_with_device_attributes(10, 5, 0, "\x1b[?62;22c".to_string());
+        custom.feed_rows("\x1b[c").unwrap();
+        assert_eq!(custom.take_responses(), "\x1b[?62;22c");
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut vt = create(10, 3, 0);
+        let initial = vt.mode_state();
+        assert!(!initial.bracketed_paste && !initial.application_cursor);
+        assert_eq!(initial.mouse, MouseTracking::Off);
+
+        vt.feed_rows("\x1b[?2004h\x1b[?1000;1006h\x1b[?1h\x1b=\x1b[?1004h").unwrap();
+        let state = vt.mode_state();
+        assert!(state.bracketed_paste && state.application_cursor && state.application_keypad);
+        assert!(state.focus_reporting);
+        assert!(serde_json::to_string(&state).unwrap().contains("\"focus_reporting\":true"));
+        assert_eq!(state.mouse, MouseTracking::Normal);
+        assert_eq!(state.mouse_encoding, MouseEncoding::Sgr);
+
+        // The last tracking mode wins; resetting an inactive one changes nothing
+        vt.feed_rows("\x1b[?1002h\x1b[?1003h\x1b[?1002l").unwrap();
+        assert_eq!(vt.mode_state().mouse, MouseTracking::Any);
+
+        let copy = restore(10, 3, 0, &vt.dump());
+        assert_eq!(copy.mode_state(), vt.mode_state());
+
+        vt.feed_rows("\x1b[?1003l\x1b[?1006l\x1b[?2004l\x1b[?1l\x1b>").unwrap();
+        let state = vt.mode_state();
+        assert_eq!(state.mouse, MouseTracking::Off);
+        assert_eq!(state.mouse_encoding, MouseEncoding::Default);
+        assert!(!state.bracketed_paste && !state.application_cursor);
+        assert!(!state.application_keypad && state.focus_reporting);
+
+        vt.feed_rows("\x1b[?2004h\x1b[?9h\x1bc").unwrap();
+        assert_eq!(vt.mode_state(), initial);
+    }
+
+    #[test]
+    fn test_soft_reset() {
+        let mut vt = create(10, 4, 0);
+        vt.feed_rows("\x1b[2;3r\x1b[1m\x1b[6mbold\x1b[?1h\x1b=\x1b[?2004h").unwrap();
+        assert!(vt.scroll_region.is_some() && vt.mode_state().application_keypad);
+
+        vt.soft_reset_rows().unwrap();
+        assert_eq!(vt.scroll_region, None);
+        assert_eq!(vt.scroll_region().bottom, 3);
+        let state = vt.mode_state();
+        assert!(!state.application_cursor && !state.application_keypad);
+        // Modes outside DECSTR's reach stay
+        assert!(state.bracketed_paste);
+
+        vt.feed_rows("\r\nplain").unwrap();
+        let snapshot = create_snapshot(&vt);
+        let bold = &snapshot.lines[0].spans[0];
+        assert!(bold.text.starts_with("bold") && bold.bold && bold.rapid_blink);
+        let plain = &snapshot.lines[1].spans[0];
+        assert!(plain.text.starts_with("plain") && !plain.bold && !plain.rapid_blink);
+    }
+
+    #[test]
+    fn test_application_keypad() {
+        let mut vt = create(10, 3, 0);
+        vt.feed_rows("\x1b=").unwrap();
+        assert!(vt.mode_state().application_keypad);
+        assert!(restore(10, 3, 0, &vt.dump()).mode_state().application_keypad);
+
+        vt.feed_rows("\x1b>").unwrap();
+        assert!(!vt.mode_state().application_keypad);
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        let mut vt = create(10, 6, 0);
+        let region = |vt: &Vt| {
+            let ScrollRegion { top, bottom } = vt.scroll_region();
+            (top, bottom)
+        };
+        assert_eq!(region(&vt), (0, 5));
+
+        vt.feed_rows("\x1b[2;4r").unwrap();
+        assert_eq!(region(&vt), (1, 3));
+
+        // Invalid margins are ignored, omitted ones default to the screen edges
+        vt.feed_rows("\x1b[4;2r\x1b[3;9r").unwrap();
+        assert_eq!(region(&vt), (1, 3));
+        vt.feed_rows("\x1b[3r").unwrap();
+        assert_eq!(region(&vt), (2, 5));
+
+        // Scrolling happens inside the region only
+        vt.feed_rows("\x1b[2;4r\x1b[Htop\x1b[4;1Hone\r\ntwo").unwrap();
+        assert_eq!(view_text(&vt.terminal)[0].trim_end(), "top");
+        assert_eq!(view_text(&vt.terminal)[2].trim_end(), "one");
+        assert_eq!(view_text(&vt.terminal)[3].trim_end(), "two");
+
+        let copy = restore(10, 6, 0, &vt.dump());
+        assert_eq!(region(&copy), (1, 3));
+
+        vt.resize(12, 6);
+        assert_eq!(region(&vt), (1, 3));
+        vt.resize(12, 3);
+        assert_eq!(region(&vt), (0, 2));
+
+        vt.feed_rows("\x1b[1;2r\x1b[!p").unwrap();
+        assert_eq!(region(&vt), (0, 2));
+        vt.feed_rows("\x1b[2;3r\x1bc").unwrap();
+        assert_eq!(region(&vt), (0, 2));
+    }
+
+    fn positions(matches: &[SearchMatch]) -> Vec<(usize, usize, usize)> {
+        matches.iter().map(|m| (m.row, m.col, m.len)).collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let mut vt = create(10, 3, 10);
+        vt.feed_rows("Error one\r\nerror two\r\n界error\r\nwrapped errorerror").unwrap();
+        let search = |query, case_sensitive| {
+            positions(&search::search(&vt.terminal, query, case_sensitive))
+        };
+
+        // Rows count from the oldest scrollback line
+        assert_eq!(search("error", true), [(1, 0, 5), (2, 2, 5), (3, 8, 5), (4, 3, 5)]);
+        assert_eq!(search("ERROR", false)[..2], [(0, 0, 5), (1, 0, 5)]);
+        assert_eq!(search("界e", true), [(2, 0, 3)]);
+        assert_eq!(search("d er", true), [(3, 6, 4)]);
+        assert!(search("", true).is_empty());
+        assert!(search("missing", false).is_empty());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_search_regex() {
+        let mut vt = create(10, 3, 10);
+        vt.feed_rows("a1 界22 b333").unwrap();
+
+        let matches = search::search_regex(&vt.terminal, r"\d+", true).unwrap();
+        assert_eq!(positions(&matches), [(0, 1, 1), (0, 5, 2), (0, 9, 3)]);
+        let matches = search::search_regex(&vt.terminal, "A|B", false).unwrap();
+        assert_eq!(positions(&matches), [(0, 0, 1), (0, 8, 1)]);
+        assert!(search::search_regex(&vt.terminal, "(", true).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let content = "\x1b[1mone\x1b[0m\r\ntwo\r\nthree";
+        let mut a = create(10, 4, 0);
+        let mut b = create(10, 4, 0);
+        a.feed_rows(content).unwrap();
+        b.feed_rows(content).unwrap();
+
+        let diff = a.snapshot_diff(&b);
+        assert!(diff.changed_rows.is_empty() && !diff.resized);
+
+        b.feed_rows("\x1b[2;1H\x1b[31mTWO").unwrap();
+        let diff = a.snapshot_diff(&b);
+        assert_eq!(diff.changed_rows.len(), 1);
+        assert_eq!(diff.changed_rows[0].row, 1);
+        assert_eq!(diff.changed_rows[0].line, create_snapshot(&b).lines[1]);
+
+        b.resize(10, 5);
+        let diff = a.snapshot_diff(&b);
+        assert!(diff.resized);
+        assert_eq!(diff.changed_rows.len(), 5);
+    }
+
+    #[test]
+    fn test_full_snapshot_trim() {
+        let mut vt = create(10, 5, 10);
+        vt.feed_rows("text\r\n\x1b[44m\x1b[K\x1b[0m\r\n  ").unwrap();
+
+        // A blank line with a background is content
+        let trimmed = create_full_snapshot(&vt, true);
+        assert_eq!(trimmed.lines.len(), 2);
+        assert_eq!(trimmed.lines[1].spans[0].bg, Some(ColorValue::Indexed(4)));
+
+        let raw = create_full_snapshot(&vt, false);
+        assert_eq!(raw.lines.len(), 5);
+        assert_eq!(raw.lines[..2], trimmed.lines[..]);
+
+        vt.feed_rows("\x1b[?1049h").unwrap();
+        assert_eq!(create_full_snapshot(&vt, false), raw);
+        assert_eq!(create_full_snapshot(&vt, true), trimmed);
+    }
+
+    #[test]
+    fn test_blink_tick() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b[5mslow\x1b[6mrapid\x1b[38;5;6mred\x1b[25m \x1b[6;1mx\x1b[mend")
+            .unwrap();
+
+        let spans = &create_snapshot(&vt).lines[0].spans;
+        let flags: Vec<(&str, bool, bool)> = spans
+            .iter()
+            .map(|span| (span.text.trim_end(), span.blink, span.rapid_blink))
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                ("slow", true, false),
+                ("rapid", false, true),
+                ("red", false, true),
+                ("", false, false),
+                ("x", false, true),
+                ("end", false, false),
+            ]
+        );
+        assert!(vt.cell_info(4, 0).unwrap().rapid_blink);
+
+        let phases = |vt: &Vt| {
+            let snapshot = create_snapshot(vt);
+            (snapshot.blink_on, snapshot.rapid_blink_on)
+        };
+        assert_eq!(phases(&vt), (None, None));
+
+        vt.tick(1000.0);
+        assert_eq!(phases(&vt), (Some(true), Some(true)));
+        vt.tick(1300.0);
+        assert_eq!(phases(&vt), (Some(true), Some(false)));
+        vt.tick(1600.0);
+        assert_eq!(phases(&vt), (Some(false), Some(true)));
+        vt.tick(2000.0);
+        assert_eq!(phases(&vt), (Some(true), Some(true)));
+
+        // The round trip through ANSI keeps both kinds
+        let mut copy = create(20, 2, 0);
+        copy.feed_rows(&vt.to_ansi(false)).unwrap();
+        assert_eq!(copy.lines_range(0, 1), vt.lines_range(0, 1));
+    }
+
+    #[test]
+    fn test_clone_vt() {
+        let mut vt = create(20, 3, 50);
+        for i in 0..6 {
+            vt.feed_rows(&format!("\x1b[3{}mline {}\x1b[0m\r\n", i, i)).unwrap();
+        }
+        vt.feed_rows("\x1b]8;;https://example.com\x07link\x1b]2;title\x07\x1b[1;4").unwrap();
+
+        let mut copy = vt.clone_vt();
+        assert_eq!(create_full_snapshot(&copy, false), create_full_snapshot(&vt, false));
+        assert_eq!(copy.cursor_state(), vt.cursor_state());
+        assert_eq!(copy.title, vt.title);
+
+        // Both finish the pending SGR, then diverge
+        vt.feed_rows("m one\r\nmore").unwrap();
+        copy.feed_rows("m two").unwrap();
+
+        assert_eq!(view_text(&vt.terminal)[1].trim_end(), "link one");
+        assert_eq!(view_text(&copy.terminal)[2].trim_end(), "link two");
+        assert_eq!(copy.scrollback_len() + 1, vt.scrollback_len());
+        assert_eq!(
+            create_full_snapshot(&copy, false).lines[..6],
+            create_full_snapshot(&vt, false).lines[..6]
+        );
+
+        let spans = &create_snapshot(&copy).lines[2].spans;
+        assert_eq!(spans[0].url.as_deref(), Some("https://example.com"));
+        assert_eq!(spans[1].text.trim_end(), " two");
+        assert!(spans[1].bold && spans[1].underline);
+    }
+
+    #[test]
+    fn test_bell() {
+        let mut vt = create(10, 3, 0);
+
+        assert!(vt.feed_changes("ding\x07").unwrap().bell);
+        assert!(!vt.feed_changes("\x1b]0;title\x07").unwrap().bell);
+        assert_eq!(vt.title.as_deref(), Some("title"));
+        assert_eq!(vt.take_bell_count(), 1);
+
+        // BEL inside a CSI still rings; inside a DCS string it is data
+        vt.feed_rows("\x07\x1b[1\x07m\x1bPq\x07\x1b\\").unwrap();
+        assert_eq!(vt.take_bell_count(), 2);
+        assert_eq!(vt.take_bell_count(), 0);
+    }
+
+    #[test]
+    fn test_tab_stops() {
+        let mut vt = create(40, 3, 100);
+        assert_eq!(vt.tab_stops, vec![8, 16, 24, 32]);
+
+        // Clear all stops, set one at column 10, then tab from the line start
+        vt.feed_rows("\x1b[3g\x1b[11G\x1bH\r\t").unwrap();
+        assert_eq!(vt.tab_stops, vec![10]);
+        assert_eq!(vt.terminal.cursor().col, 10);
+
+        // The stops survive a restore from the dump
+        let restored = restore(40, 3, 100, &vt.dump());
+        assert_eq!(restored.tab_stops, vec![10]);
+
+        vt.feed_rows("\x1b[g").unwrap();
+        assert!(vt.tab_stops.is_empty());
+
+        // Resets restore stops at the configured width, in avt as well
+        vt.set_default_tab_width(4);
+        vt.reset_rows().unwrap();
+        assert_eq!(vt.tab_stops, vec![4, 8, 12, 16, 20, 24, 28, 32, 36]);
+        vt.feed_rows("\t\tx").unwrap();
+        assert_eq!(view_text(&vt.terminal)[0].trim_end(), "        x");
+
+        // Narrowing drops stops, widening adds them at the same width
+        vt.resize(10, 3);
+        assert_eq!(vt.tab_stops, vec![4, 8]);
+        vt.resize(20, 3);
+        assert_eq!(vt.tab_stops, vec![4, 8, 12, 16]);
+        vt.feed_rows("\r\t\t\t\ty").unwrap();
+        assert_eq!(vt.terminal.cursor().col, 17);
+    }
+
+    #[test]
+    fn test_line_attrs() {
+        use LineAttr::*;
+        let attrs = |vt: &Vt| -> Vec<LineAttr> {
+            create_snapshot(vt).lines.iter().map(|line| line.line_attr).collect()
+        };
+        let mut vt = create(10, 3, 10);
+
+        vt.feed_rows("\x1b#6wide\r\n\x1b#3top\r\n\x1b#4bottom").unwrap();
+        assert_eq!(attrs(&vt), vec![DoubleWidth, DoubleHeightTop, DoubleHeightBottom]);
+
+        // Attributes move up with their lines, into history too
+        vt.feed_rows("\r\nnext").unwrap();
+        assert_eq!(attrs(&vt), vec![DoubleHeightTop, DoubleHeightBottom, Normal]);
+        assert_eq!(vt.lines_range(0, 1)[0].line_attr, DoubleWidth);
+
+        // DECSWL, reverse index at the top and insert line
+        vt.feed_rows("\x1b[2;1H\x1b#5\x1b[H\x1bM").unwrap();
+        assert_eq!(attrs(&vt), vec![Normal, DoubleHeightTop, Normal]);
+        vt.feed_rows("\x1b[L").unwrap();
+        assert_eq!(attrs(&vt), vec![Normal, Normal, DoubleHeightTop]);
+
+        // Scrolling inside a region leaves the rows outside it alone
+        vt.feed_rows("\x1b[H\x1b#6\x1b[2;3r\x1b[3;1H\n").unwrap();
+        assert_eq!(attrs(&vt), vec![DoubleWidth, DoubleHeightTop, Normal]);
+
+        // The alt screen starts normal and the primary one gets its attributes back
+        vt.feed_rows("\x1b[r\x1b[?1049h").unwrap();
+        assert_eq!(attrs(&vt), vec![Normal; 3]);
+        vt.feed_rows("\x1b#6\x1b[?1049l").unwrap();
+        assert_eq!(attrs(&vt), vec![DoubleWidth, DoubleHeightTop, Normal]);
+
+        vt.feed_rows("\x1b[2J").unwrap();
+        assert_eq!(attrs(&vt), vec![Normal; 3]);
     }
 
     #[test]
-    fn test_get_size() {
-        let vt = create(80, 24, 0);
-        let size = vt.get_size();
-        assert!(!size.is_null());
+    fn test_create_from_options() {
+        let vt = create_from_options(CreateOptions::default()).unwrap();
+        assert_eq!(vt.terminal.size(), (80, 24));
+        assert_eq!(vt.scrollback_limit, DEFAULT_SCROLLBACK_LIMIT);
+        assert_eq!(vt.palette, Palette::default());
+        assert_eq!(vt.default_colors, DefaultColors::default());
+
+        let vt = create_from_options(CreateOptions {
+            cols: 20,
+            rows: 5,
+            scrollback_limit: 50,
+            palette: Some(vec!["#102030".to_string()]),
+            default_bg: Some("#000010".to_string()),
+            ..CreateOptions::default()
+        })
+        .unwrap();
+        assert_eq!(vt.terminal.size(), (20, 5));
+        assert_eq!(vt.scrollback_limit, 50);
+        assert_eq!(vt.palette.rgb(0), (0x10, 0x20, 0x30));
+        assert_eq!(vt.default_colors.bg, Some((0, 0, 0x10)));
+
+        let zero = CreateOptions {
+            cols: 0,
+            ..CreateOptions::default()
+        };
+        assert!(create_from_options(zero).is_err());
+        let bad_color = CreateOptions {
+            default_fg: Some("red".to_string()),
+            ..CreateOptions::default()
+        };
+        assert!(create_from_options(bad_color).is_err());
+        let no_reflow = CreateOptions {
+            resize_reflow: false,
+            ..CreateOptions::default()
+        };
+        assert!(create_from_options(no_reflow).is_err());
+    }
+
+    #[test]
+    fn test_view_options() {
+        let mut vt = create(10, 2, 10);
+        vt.feed_rows("\x1b[1mab\x1b[0m中").unwrap();
+
+        // Defaults reproduce get_view
+        let view = vt.view_with_options(&ViewOptions::default());
+        assert_eq!(view, vt.resolved(create_snapshot(&vt)));
+        assert!(view.cursor.is_none());
+
+        let view = vt.view_with_options(&ViewOptions {
+            include_cursor: true,
+            ..ViewOptions::default()
+        });
+        let cursor = view.cursor.unwrap();
+        assert_eq!((cursor.col, cursor.row), (4, 0));
+        assert!(cursor.visible);
+
+        // One span per cell, the wide character being one
+        let view = vt.view_with_options(&ViewOptions {
+            merge_spans: false,
+            ..ViewOptions::default()
+        });
+        let cells = &view.lines[0].spans;
+        assert_eq!(cells.len(), 9);
+        assert_eq!(cells[0].text, "a");
+        assert!(cells[0].bold && cells[1].bold && !cells[2].bold);
+        assert_eq!(cells[2].text, "中");
+        assert_eq!(cells[2].widths, Some(vec![2]));
+        assert_eq!(cells[3].widths, None);
+
+        let view = vt.view_with_options(&ViewOptions {
+            include_text_only: true,
+            ..ViewOptions::default()
+        });
+        let spans = &view.lines[0].spans;
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].text.starts_with("ab中"));
+        assert!(!spans[0].bold);
+    }
+
+    #[test]
+    fn test_used_colors() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b[31mred\x1b[38;2;1;2;3;44mrgb\x1b[0m plain\r\n\x1b[31magain").unwrap();
+
+        let colors = vt.used_colors();
+        assert_eq!(
+            colors.fg,
+            vec![ColorValue::Indexed(1), ColorValue::Rgb("#010203".to_string())]
+        );
+        assert_eq!(colors.bg, vec![ColorValue::Indexed(4)]);
+
+        vt.set_resolve_colors(true);
+        assert_eq!(vt.used_colors().bg, vec![ColorValue::Rgb("#0000EE".to_string())]);
+    }
+
+    #[test]
+    fn test_span_position() {
+        let mut vt = create(10, 2, 0);
+        vt.feed_rows("a中b\x1b[1m日x").unwrap();
+        let position = |col| {
+            vt.span_position(0, col)
+                .map(|p| (p.span, p.char_index, p.byte_offset))
+        };
+
+        assert_eq!(position(0), Some((0, 0, 0)));
+        // Both halves of the wide character
+        assert_eq!(position(1), Some((0, 1, 1)));
+        assert_eq!(position(2), Some((0, 1, 1)));
+        assert_eq!(position(3), Some((0, 2, 4)));
+        // The bold span starts over
+        assert_eq!(position(4), Some((1, 0, 0)));
+        assert_eq!(position(5), Some((1, 0, 0)));
+        assert_eq!(position(6), Some((1, 1, 3)));
+        // Trailing blanks are a span of their own
+        assert_eq!(position(9), Some((2, 2, 2)));
+        assert_eq!(position(10), None);
+        assert_eq!(vt.span_position(2, 0), None);
+    }
+
+    #[test]
+    fn test_generation() {
+        let mut vt = create(10, 2, 0);
+        let start = vt.generation();
+
+        vt.feed_rows("a").unwrap();
+        let fed = vt.generation();
+        assert!(fed > start);
+
+        // Reading leaves it alone, as does an empty feed
+        let _ = vt.terminal.size();
+        let _ = create_snapshot(&vt);
+        vt.feed_rows("").unwrap();
+        assert_eq!(vt.generation(), fed);
+
+        vt.resize(12, 2);
+        assert!(vt.generation() > fed);
+        let resized = vt.generation();
+        vt.reset_rows().unwrap();
+        assert!(vt.generation() > resized);
+
+        // Ticks only count when a blink phase flips
+        vt.tick(0.0);
+        let ticked = vt.generation();
+        vt.tick(100.0);
+        assert_eq!(vt.generation(), ticked);
+        vt.tick(300.0);
+        assert!(vt.generation() > ticked);
+    }
+
+    #[test]
+    fn test_feed_sync() {
+        let mut vt = create(20, 5, 0);
+        vt.set_feed_sync(true);
+        vt.feed_rows("start").unwrap();
+
+        let changes = vt.feed_changes("\x1b[?2026h\x1b[2;1Hone").unwrap();
+        assert!(vt.in_synchronized_update());
+        assert_eq!((changes.lines, changes.partial), (vec![], true));
+        let changes = vt.feed_changes("\x1b[4;1Htwo").unwrap();
+        assert_eq!((changes.lines, changes.partial), (vec![], true));
+
+        let changes = vt.feed_changes("\x1b[?2026l").unwrap();
+        assert!(!vt.in_synchronized_update());
+        assert_eq!((changes.lines, changes.partial), (vec![1, 3], false));
+
+        // Without feed sync the mode is still tracked, but rows are reported as they change
+        vt.set_feed_sync(false);
+        assert_eq!(vt.feed_rows("\x1b[?2026h\x1b[5;1Hthree").unwrap(), vec![4]);
+        assert!(vt.in_synchronized_update());
+    }
+
+    #[test]
+    fn test_cursor_after_narrowing_resize() {
+        let mut vt = create(80, 5, 0);
+        vt.feed_rows("\x1b[2;71H").unwrap();
+        assert_eq!(vt.cursor_position(), [70, 1]);
+
+        // avt clamps the cursor itself when columns go
+        vt.resize(40, 5);
+        assert_eq!(vt.cursor_position(), [39, 1]);
+        assert_eq!(vt.terminal.cursor().col, 39);
+
+        // Filling the last column leaves avt's cursor one past it until the next print
+        vt.feed_rows(&format!("\r{}", "x".repeat(40))).unwrap();
+        assert_eq!(vt.terminal.cursor().col, 40);
+        assert_eq!(vt.cursor_position(), [39, 1]);
+        assert_eq!(vt.cursor_state().col, 39);
+    }
+
+    #[test]
+    fn test_view_hash() {
+        let mut a = create(10, 3, 0);
+        let mut b = create(10, 3, 5);
+        assert_eq!(a.view_hash(), b.view_hash());
+        assert_ne!(a.view_hash(), create(15, 2, 0).view_hash());
+
+        // Same screen reached differently: the span split doesn't matter
+        a.feed_rows("\x1b[1;31mhello\x1b[0m world").unwrap();
+        b.feed_rows("xx\r\x1b[31;1mhe\x1b[1mllo\x1b[m world").unwrap();
+        assert_eq!(a.view_hash(), b.view_hash());
+
+        // One cell differing only in style, color or hyperlink changes it
+        let before = a.view_hash();
+        a.feed_rows("\x1b[1;7H\x1b[3mw").unwrap();
+        assert_ne!(a.view_hash(), before);
+        a.feed_rows("\x1b[1;7H\x1b[0mw").unwrap();
+        assert_eq!(a.view_hash(), before);
+        a.feed_rows("\x1b[1;7H\x1b[38;5;1mw").unwrap();
+        assert_ne!(a.view_hash(), before);
+        a.feed_rows("\x1b[1;7H\x1b[0m\x1b]8;;http://x\x07w\x1b]8;;\x07").unwrap();
+        assert_ne!(a.view_hash(), before);
+    }
+
+    #[test]
+    fn test_keyboard_flags() {
+        let mut vt = create(10, 3, 0);
+        vt.feed_rows("\x1b[>1u").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 1);
+        vt.feed_rows("\x1b[>5u").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 5);
+        assert_eq!(vt.mode_state().keyboard_flags, 5);
+
+        vt.feed_rows("\x1b[?u").unwrap();
+        assert_eq!(vt.take_responses(), "\x1b[?5u");
+
+        let restored = restore(10, 3, 0, &vt.dump());
+        assert_eq!(restored.modes.keyboard, vt.modes.keyboard);
+
+        vt.feed_rows("\x1b[<u").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 1);
+        vt.feed_rows("\x1b[=2;2u").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 3);
+        vt.feed_rows("\x1b[<u").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 0);
+        vt.feed_rows("\x1b[<u").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 0);
+
+        // The alt screen keeps a stack of its own
+        vt.feed_rows("\x1b[>1u\x1b[?1049h").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 0);
+        vt.feed_rows("\x1b[>8u\x1b[?1049l").unwrap();
+        assert_eq!(vt.get_keyboard_flags(), 1);
+    }
+
+    #[test]
+    fn test_feed_interactive() {
+        let mut vt = create(10, 3, 0);
+        vt.feed_rows("\x1b[c").unwrap();
+
+        let result = vt.feed_interactive_rows("ab\x1b[6n").unwrap();
+        assert_eq!(result.changes, vec![0]);
+        assert_eq!(result.responses, "\x1b[1;3R");
+        // Replies from earlier feeds stay queued
+        assert_eq!(vt.take_responses(), "\x1b[?1;2c");
+
+        let result = vt.feed_interactive_rows("c").unwrap();
+        assert_eq!(result.responses, "");
+    }
+
+    #[test]
+    fn test_str_width() {
+        assert_eq!(str_width("hello"), 5);
+        assert_eq!(str_width("日本語"), 6);
+        assert_eq!(str_width("e\u{301}"), 2);
+        assert_eq!(str_width("a\x1b[31mb\x07\x1b]0;title\x07"), 2);
+
+        for s in ["hello", "日本語", "e\u{301}", "a\x1b[31mb\x07"] {
+            let mut vt = create(20, 2, 0);
+            vt.feed_rows(s).unwrap();
+            assert_eq!(vt.cursor_position(), [str_width(s), 0], "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_view_masked() {
+        let mut vt = create(10, 2, 0);
+        vt.feed_rows("\x1b[5ma\x1b[25mb\x1b[6m中\x1b[0;2mc\x1b[0;3md").unwrap();
+
+        let strip_blink = MaskOptions {
+            strip_blink: true,
+            ..MaskOptions::default()
+        };
+        let spans = &vt.view_masked(&strip_blink).lines[0].spans;
+        assert_eq!(spans.len(), 4);
+        assert_eq!(spans[0].text, "ab中");
+        assert_eq!(spans[0].widths, Some(vec![1, 1, 2]));
+        assert!(!spans[0].blink && !spans[0].rapid_blink);
+        assert!(spans[1].faint && spans[2].italic);
+
+        let strip_all = MaskOptions {
+            strip_blink: true,
+            strip_faint: true,
+            strip_italic: true,
+        };
+        let spans = &vt.view_masked(&strip_all).lines[0].spans;
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, "ab中cd    ");
+
+        let spans = &vt.view_masked(&MaskOptions::default()).lines[0].spans;
+        assert_eq!(spans, &create_snapshot(&vt).lines[0].spans);
+    }
+
+    #[test]
+    fn test_text_tab_alignment() {
+        let mut vt = create(12, 3, 0);
+        vt.set_default_tab_width(4);
+        vt.reset_rows().unwrap();
+
+        // avt moves the cursor to the next stop and stores no tab, so text is laid out
+        vt.feed_rows("a\tb\r\n中\tb\r\nabcd\tb").unwrap();
+        let text = view_text(&vt.terminal);
+        assert_eq!(text[0].trim_end(), "a   b");
+        assert_eq!(text[1].trim_end(), "中  b");
+        assert_eq!(text[2].trim_end(), "abcd    b");
+    }
+
+    #[test]
+    fn test_all_lines_rle() {
+        let mut vt = create(8, 4, 100);
+        vt.feed_rows("top\r\n\n\n\n\n\nmiddle\r\n\n\n\nbottom").unwrap();
+
+        let rle = vt.all_lines_rle();
+        let runs: Vec<usize> = rle
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                RleLine::EmptyRun { empty_run } => Some(*empty_run),
+                RleLine::Line(_) => None,
+            })
+            .collect();
+        assert_eq!(runs, vec![5, 3]);
+        assert_eq!(rle.lines.len(), 5);
+
+        let expanded: Vec<SnapshotLine> = rle
+            .lines
+            .into_iter()
+            .flat_map(|line| match line {
+                RleLine::Line(line) => vec![line],
+                RleLine::EmptyRun { empty_run } => vec![rle.blank_line.clone(); empty_run],
+            })
+            .collect();
+        assert_eq!(expanded, vt.all_lines_with_options(&AllLinesOptions::default()).lines);
+
+        // Blank lines with a background aren't collapsed
+        vt.feed_rows("\r\n\x1b[44m\x1b[K\r\n\x1b[K").unwrap();
+        let rle = vt.all_lines_rle();
+        assert!(matches!(rle.lines[rle.lines.len() - 1], RleLine::Line(_)));
+    }
+
+    #[test]
+    fn test_count_replacement_chars() {
+        let mut vt = create(10, 2, 0);
+        let input = "ok \u{FFFD}\u{FFFD} bad";
+        assert_eq!(vt.feed_changes(input).unwrap().replacement_chars, None);
+
+        vt.set_count_replacement_chars(true);
+        assert_eq!(vt.feed_changes(input).unwrap().replacement_chars, Some(2));
+        assert_eq!(vt.feed_changes("fine").unwrap().replacement_chars, Some(0));
+
+        // Bytes that don't decode are fed as U+FFFD too
+        let decoded = decode_utf8_chunk(&mut Vec::new(), b"\xff!");
+        assert_eq!(vt.feed_changes(&decoded).unwrap().replacement_chars, Some(1));
+    }
+
+    #[test]
+    fn test_saved_cursor() {
+        let mut vt = create(10, 4, 0);
+        assert_eq!(vt.saved_cursor(), None);
+
+        vt.feed_rows("\x1b[2;4H\x1b[1;31m\x1b7\x1b[0m\x1b[4;1Hmoved").unwrap();
+        let saved = vt.saved_cursor().unwrap();
+        assert_eq!((saved.col, saved.row), (3, 1));
+        assert!(saved.pen.bold);
+        assert_eq!(saved.pen.fg, Some(ColorValue::Indexed(1)));
+        assert_eq!(vt.cursor_position(), [5, 3]);
+
+        // The alt screen saves its own, and the primary one is back after leaving it
+        vt.feed_rows("\x1b[?1047h").unwrap();
+        assert_eq!(vt.saved_cursor(), None);
+        vt.feed_rows("\x1b[3;2H\x1b[s").unwrap();
+        assert_eq!(vt.saved_cursor().map(|saved| (saved.col, saved.row)), Some((1, 2)));
+        vt.feed_rows("\x1b[?1047l").unwrap();
+        assert_eq!(vt.saved_cursor().map(|saved| (saved.col, saved.row)), Some((3, 1)));
+
+        let restored = restore(10, 4, 0, &vt.dump());
+        assert_eq!(restored.saved_cursor(), vt.saved_cursor());
+
+        vt.feed_rows("\x1b[!p").unwrap();
+        assert_eq!(vt.saved_cursor(), None);
+    }
+
+    #[test]
+    fn test_span_columns() {
+        let mut vt = create(10, 2, 0);
+        vt.feed_rows("a中\x1b[1mb\x1b[0;4m日c").unwrap();
+
+        let columns = ViewOptions {
+            include_columns: true,
+            ..ViewOptions::default()
+        };
+        let view = vt.view_with_options(&columns);
+        let cols: Vec<(&str, Option<usize>)> = view.lines[0]
+            .spans
+            .iter()
+            .map(|span| (span.text.as_str(), span.col))
+            .collect();
+        assert_eq!(
+            cols,
+            vec![("a中", Some(0)), ("b", Some(3)), ("日c", Some(4)), ("   ", Some(7))]
+        );
+
+        let cells = ViewOptions {
+            merge_spans: false,
+            ..columns
+        };
+        let view = vt.view_with_options(&cells);
+        let cols: Vec<usize> = view.lines[0].spans.iter().filter_map(|span| span.col).collect();
+        assert_eq!(cols, vec![0, 1, 3, 4, 6, 7, 8, 9]);
+
+        let view = vt.view_with_options(&ViewOptions::default());
+        assert!(view.lines[0].spans.iter().all(|span| span.col.is_none()));
+    }
+
+    #[test]
+    fn test_feed_without_changes() {
+        let mut vt = create(10, 2, 0);
+        vt.feed_rows("ab").unwrap();
+
+        assert_eq!(vt.feed_rows("\x1b[?25l"), Ok(vec![]));
+        assert_eq!(vt.feed_rows("\x1b[?25h\x1b[34m"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_origin_mode() {
+        let mut vt = create(10, 6, 0);
+        vt.feed_rows("\x1b[3;5r\x1b[?6h\x1b[H").unwrap();
+        assert!(vt.mode_state().origin_mode);
+        assert_eq!(vt.cursor_position(), [0, 2]);
+        vt.feed_rows("\x1b[2;3H").unwrap();
+        assert_eq!(vt.cursor_position(), [2, 3]);
+        assert!(restore(10, 6, 0, &vt.dump()).mode_state().origin_mode);
+
+        // Saved and restored with the cursor, per screen
+        vt.feed_rows("\x1b7\x1b[?6l").unwrap();
+        assert!(!vt.mode_state().origin_mode);
+        vt.feed_rows("\x1b[?6h\x1b[?1047h\x1b8").unwrap();
+        assert!(!vt.mode_state().origin_mode);
+        vt.feed_rows("\x1b[?1047l\x1b8").unwrap();
+        assert!(vt.mode_state().origin_mode);
+        // ?1049 saves on the way in and restores on the way out
+        vt.feed_rows("\x1b[?6l\x1b[?1049h\x1b[?6h\x1b[?1049l").unwrap();
+        assert!(!vt.mode_state().origin_mode);
+
+        vt.feed_rows("\x1b[!p").unwrap();
+        assert!(!vt.mode_state().origin_mode);
+        vt.feed_rows("\x1b[?6h\x1bc").unwrap();
+        assert!(!vt.mode_state().origin_mode);
+    }
+
+    #[test]
+    fn test_to_svg() {
+        let mut vt = create(6, 2, 0);
+        vt.feed_rows("\x1b[31;42mhi\x1b[0m 中\x1b[1m<b>").unwrap();
+
+        let svg = vt.to_svg(10.0, 20.0, "Fira \"Mono\"");
+        let expected = [
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"60\" height=\"40\" \
+             viewBox=\"0 0 60 40\" font-family=\"Fira &quot;Mono&quot;\" font-size=\"16\" \
+             xml:space=\"preserve\">",
+            "<rect width=\"60\" height=\"40\" fill=\"#000000\"/>",
+            "<rect x=\"0\" y=\"0\" width=\"20\" height=\"20\" fill=\"#00CD00\"/>",
+            "<text x=\"0 10\" y=\"16\" fill=\"#CD0000\">hi</text>",
+            // The wide character takes two columns
+            "<text x=\"20 30\" y=\"16\" fill=\"#E5E5E5\"> 中</text>",
+            "<text x=\"50\" y=\"16\" fill=\"#E5E5E5\" font-weight=\"bold\">&lt;</text>",
+            "<text x=\"0 10\" y=\"36\" fill=\"#E5E5E5\" font-weight=\"bold\">b&gt;</text>",
+            "</svg>",
+        ];
+        assert_eq!(svg, expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn test_focus_event() {
+        let mut vt = create(10, 2, 0);
+        assert_eq!(vt.focus_event(true), "");
+
+        vt.feed_rows("\x1b[?1004h").unwrap();
+        assert!(vt.mode_state().focus_reporting);
+        assert_eq!(vt.focus_event(true), "\x1b[I");
+        assert_eq!(vt.focus_event(false), "\x1b[O");
+
+        vt.feed_rows("\x1b[?1004l").unwrap();
+        assert_eq!(vt.focus_event(false), "");
+    }
+
+    #[test]
+    fn test_logical_lines() {
+        let mut vt = create(10, 3, 100);
+        vt.feed_rows("first\r\n").unwrap();
+        vt.feed_rows("a paragraph wider than the terminal\r\nlast").unwrap();
+
+        let lines = logical_lines(&vt.terminal);
+        let expected = [
+            ("first", 0, 0),
+            ("a paragraph wider than the terminal", 1, 4),
+            ("last", 5, 5),
+        ];
+        assert_eq!(lines.len(), expected.len());
+        for (line, (text, start_row, end_row)) in lines.iter().zip(expected) {
+            let found = (line.text.as_str(), line.start_row, line.end_row);
+            assert_eq!(found, (text, start_row, end_row));
+        }
+        // The paragraph starts in scrollback and ends in the viewport
+        assert_eq!(vt.scrollback_len(), 3);
+    }
+
+    #[test]
+    fn test_osc_dynamic_colors() {
+        let mut vt = create(20, 2, 0);
+        vt.feed_rows("\x1b]11;rgb:12/34/56\x07").unwrap();
+        assert_eq!(vt.default_color_state().bg, "#123456");
+        assert_eq!(vt.default_color_state().fg, "#E5E5E5");
+
+        vt.feed_rows("\x1b]11;?\x1b\\\x1b]10;#ABCDEF;?\x07").unwrap();
+        assert_eq!(vt.default_color_state().fg, "#ABCDEF");
+        assert_eq!(
+            vt.take_responses(),
+            "\x1b]11;rgb:1212/3434/5656\x1b\\\x1b]11;rgb:1212/3434/5656\x1b\\"
+        );
+
+        vt.feed_rows("\x1b]4;3;rgb:f/0/0;1;?\x07").unwrap();
+        let palette = vt.get_palette();
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[3], "#FF0000");
+        assert_eq!(palette[2], "#00CD00");
+        assert_eq!(vt.take_responses(), "\x1b]4;1;rgb:cdcd/0000/0000\x1b\\");
+
+        // Malformed specs are ignored
+        vt.feed_rows("\x1b]11;blue\x07\x1b]4;300;#FFFFFF\x07").unwrap();
+        assert_eq!(vt.default_color_state().bg, "#123456");
+
+        // A second spec goes to the next color: 10 carries on to 11, 11 to the cursor color
+        vt.feed_rows("\x1b]10;#111111;#222222\x07").unwrap();
+        assert_eq!(vt.default_color_state().fg, "#111111");
+        assert_eq!(vt.default_color_state().bg, "#222222");
+        vt.feed_rows("\x1b]11;#000000;#FF0000\x07").unwrap();
+        assert_eq!(vt.default_color_state().fg, "#111111");
+        assert_eq!(vt.default_color_state().bg, "#000000");
+        assert_eq!(palette::parse_color_spec("rgb:ffff/8000/0"), Some((0xFF, 0x7F, 0)));
+    }
+
+    #[test]
+    fn test_measure_wrap() {
+        let mut vt = create(10, 4, 0);
+        vt.feed_rows("abc").unwrap();
+
+        // The wide character would land in the last column, so it wraps early
+        let s = "123456\u{4e2d}\x1b[1m12345678\x1b[0m9";
+        let breaks = vt.wrap_breaks(s);
+        assert_eq!(breaks, vec![6, 23]);
+        assert_eq!(vt.wrap_breaks("1234567"), Vec::<usize>::new());
+
+        vt.feed_rows(s).unwrap();
+        let text: Vec<String> = vt.terminal.view().map(|line| line.text()).collect();
+        assert_eq!(text[0], "abc123456 ");
+        let chars: Vec<char> = s.chars().collect();
+        for (row, &index) in breaks.iter().enumerate() {
+            assert_eq!(text[row + 1].chars().next(), Some(chars[index]));
+        }
+        assert_eq!(text[2].trim_end(), "9");
+
+        // From the pending-wrap position the next character wraps right away
+        vt.feed_rows("\r\x1b[2B0123456789").unwrap();
+        assert_eq!(vt.wrap_breaks("x"), vec![0]);
+
+        // CR returns to the first column, LF keeps the column, BS and TAB move it
+        let s = "abcdefgh\r\nij\x08\tklm\r\n0123456789x";
+        assert_eq!(vt.wrap_breaks("\r\nab\x08\x08\x08\t12"), Vec::<usize>::new());
+        let mut vt = create(10, 5, 0);
+        assert_eq!(vt.wrap_breaks(s), vec![16, 29]);
+        vt.feed_rows(s).unwrap();
+        let text: Vec<String> = vt.terminal.view().map(|line| line.text()).collect();
+        assert_eq!(text, ["abcdefgh  ", "ij      kl", "m         ", "0123456789", "x         "]);
+    }
+
+    #[test]
+    fn test_row_timestamps() {
+        let mut vt = create(20, 3, 10);
+        let times = |vt: &Vt| {
+            let options = ViewOptions {
+                include_timestamps: true,
+                ..ViewOptions::default()
+            };
+            let snapshot = vt.view_with_options(&options);
+            snapshot.lines.iter().map(|line| line.changed_at).collect::<Vec<_>>()
+        };
+        // Only tracked once ticked
+        vt.feed_rows("zero").unwrap();
+        assert_eq!(times(&vt), vec![None; 3]);
+
+        vt.tick(1000.0);
+        vt.feed_rows("\rfirst\r\n").unwrap();
+        vt.tick(1100.0);
+        vt.feed_rows("second").unwrap();
+        assert_eq!(times(&vt), vec![Some(1000.0), Some(1100.0), Some(1000.0)]);
+        assert_eq!(vt.view_with_options(&ViewOptions::default()).lines[0].changed_at, None);
+
+        // Scrolled rows keep their time, rewriting the same content doesn't count
+        vt.tick(1200.0);
+        vt.feed_rows("\r\nthird\r\nfourth\x1b[1;1Hsecond").unwrap();
+        assert_eq!(times(&vt), vec![Some(1100.0), Some(1200.0), Some(1200.0)]);
+
+        vt.tick(1300.0);
+        vt.feed_rows("\x1b[H\x1bM\x1bM").unwrap();
+        assert_eq!(times(&vt), vec![Some(1300.0), Some(1300.0), Some(1100.0)]);
+    }
+
+    #[test]
+    fn test_decaln() {
+        let mut vt = create(80, 24, 0);
+        vt.feed_rows("\x1b]8;;https://example.com\x07\x1b[31;6mlink\r\n\x1b#8").unwrap();
+
+        let grid = char_grid(&vt.terminal);
+        assert_eq!(grid.len(), 24);
+        assert!(grid.iter().all(|row| row.len() == 80 && row.iter().all(|cell| cell == "E")));
+
+        // The fill uses the default pen, dropping what the overlay tracked too
+        let snapshot = create_snapshot(&vt);
+        for line in &snapshot.lines {
+            assert_eq!(line.spans.len(), 1);
+            assert_eq!(line.spans[0].text, "E".repeat(80));
+            assert_eq!(line.spans[0].fg, None);
+            assert_eq!(line.spans[0].url, None);
+            assert!(!line.spans[0].rapid_blink);
+        }
+    }
+
+    #[test]
+    fn test_selective_erase() {
+        let mut vt = create(12, 3, 0);
+        vt.feed_rows("\x1b[1\"qKEEP\x1b[0\"q drop\r\nab\x1b[1\"q\u{4e2d}\x1b[2\"qcdef\r\nline")
+            .unwrap();
+
+        let spans = &create_snapshot(&vt).lines[0].spans;
+        assert_eq!(spans[0].text, "KEEP");
+        assert!(spans[0].protected && !spans[1].protected);
+
+        // DECSEL from the start of the line through the cursor
+        vt.feed_rows("\x1b[2;6H\x1b[?1K").unwrap();
+        assert_eq!(vt.terminal.line(1).text(), "  \u{4e2d}  ef    ");
+        assert_eq!(vt.cursor_position(), [5, 1]);
+
+        // DECSED over the whole screen, and plain ED still clears protected cells
+        vt.feed_rows("\x1b[?2J").unwrap();
+        let text: Vec<String> = vt.terminal.view().map(|line| line.text()).collect();
+        assert_eq!(text[0].trim_end(), "KEEP");
+        assert_eq!(text[1].trim_end(), "  \u{4e2d}");
+        assert_eq!(text[2].trim_end(), "");
+        vt.feed_rows("\x1b[2J").unwrap();
+        assert!(vt.terminal.view().all(|line| line.text().trim_end().is_empty()));
+
+        // DECSTR ends protection
+        vt.feed_rows("\x1b[H\x1b[1\"q\x1b[!pX").unwrap();
+        let spans = &create_snapshot(&vt).lines[0].spans;
+        assert!(spans[0].text.starts_with('X') && !spans[0].protected);
+
+        // Cursor positions stay put under origin mode
+        vt.feed_rows("\x1b[2;3r\x1b[?6h\x1b[2;4H\x1b[?0J").unwrap();
+        assert_eq!(vt.cursor_position(), [3, 2]);
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let mut vt = create(10, 2, 0);
+        vt.feed_rows("hi").unwrap();
+
+        assert_eq!(supported_schema_version(), SNAPSHOT_SCHEMA_VERSION);
+        assert_eq!(create_snapshot(&vt).schema_version, SNAPSHOT_SCHEMA_VERSION);
+        assert_eq!(vt.all_lines_rle().schema_version, SNAPSHOT_SCHEMA_VERSION);
+        let json = serde_json::to_string(&vt.view_with_options(&ViewOptions::default())).unwrap();
+        assert!(json.starts_with(&format!("{{\"schema_version\":{},", SNAPSHOT_SCHEMA_VERSION)));
+        assert!(vt.all_lines_json().starts_with("{\"schema_version\":2,\"cols\":10,"));
+    }
+
+    #[test]
+    fn test_feed_damage() {
+        let mut vt = create(30, 3, 0);
+        vt.feed_rows("0123456789abcdefghij\r\nsecond").unwrap();
+
+        let damage = vt.feed_damage("\x1b[1;11HABCDEF").unwrap();
+        assert_eq!(
+            damage,
+            vec![Damage {
+                row: 0,
+                min_col: 10,
+                max_col: 15
+            }]
+        );
+
+        // Same content, a new pen or hyperlink, and a wide character's right half
+        assert_eq!(vt.feed_damage("\x1b[2;1Hsecond").unwrap(), vec![]);
+        let damage = vt.feed_damage("\x1b[2;3H\x1b[1mc\x1b]8;;http://x\x07o\x1b]8;;\x07").unwrap();
+        assert_eq!((damage[0].min_col, damage[0].max_col), (2, 3));
+        vt.feed_rows("\x1b[3;5H\u{4e2d}").unwrap();
+        let damage = vt.feed_damage("\x1b[3;5H\u{56fd}").unwrap();
+        assert_eq!((damage[0].row, damage[0].min_col, damage[0].max_col), (2, 4, 5));
+
+        // Rows held back by feed sync come out whole once released
+        vt.set_feed_sync(true);
+        vt.feed_rows("\x1b[?2026h\x1b[1;1Hx").unwrap();
+        let damage = vt.feed_damage("\x1b[?2026l").unwrap();
+        assert_eq!((damage[0].row, damage[0].min_col, damage[0].max_col), (0, 0, 29));
+    }
+
+    #[test]
+    fn test_paused_feed() {
+        let mut vt = create(10, 3, 0);
+        vt.pause(true).unwrap();
+        assert_eq!(vt.feed_rows("one\r\n").unwrap(), Vec::<usize>::new());
+        assert_eq!(vt.feed_changes("\x1b[1mtwo").unwrap(), FeedChanges::default());
+        assert!(vt.terminal.line(0).text().trim_end().is_empty());
+
+        // Flushing applies the queue in order and stays paused
+        let flushed = vt.flush_paused().unwrap();
+        assert_eq!(flushed.changes, vec![0, 1, 2]);
+        assert_eq!(flushed.dropped_bytes, 0);
+        assert_eq!(vt.terminal.line(0).text().trim_end(), "one");
+        assert_eq!(vt.terminal.line(1).text().trim_end(), "two");
+        assert!(vt.paused);
+
+        // Past the limit the oldest input goes, never splitting a character
+        vt.set_pause_queue_limit(4);
+        vt.feed_rows("\r\n\u{e9}\u{e9}xyz").unwrap();
+        assert_eq!(vt.paused_input, "xyz");
+        assert_eq!(vt.paused_dropped, 6);
+
+        // Resuming applies the rest
+        let flushed = vt.pause(false).unwrap();
+        assert_eq!((flushed.changes, flushed.dropped_bytes), (vec![1], 6));
+        assert_eq!(vt.terminal.line(1).text().trim_end(), "twoxyz");
+        assert_eq!(vt.feed_rows("!").unwrap(), vec![1]);
+
+        // A resize while paused ends up as it would live: the queue goes in first
+        let mut live = create(10, 3, 0);
+        let mut paused = create(10, 3, 0);
+        paused.pause(true).unwrap();
+        for vt in [&mut live, &mut paused] {
+            vt.feed_rows("0123456789abc").unwrap();
+            vt.resize(5, 3);
+        }
+        assert!(paused.paused && paused.paused_input.is_empty());
+        assert_eq!(view_text(&paused.terminal), view_text(&live.terminal));
+
+        // Resets take effect right away: RIS drops the queue, DECSTR applies it first
+        paused.feed_rows("lost").unwrap();
+        paused.reset_rows().unwrap();
+        assert!(paused.paused_input.is_empty());
+        assert!(view_text(&paused.terminal).iter().all(|line| line.trim().is_empty()));
+        paused.feed_rows("\x1b[1mkept").unwrap();
+        paused.soft_reset_rows().unwrap();
+        paused.pause(false).unwrap();
+        paused.feed_rows("!").unwrap();
+        let spans = &create_snapshot(&paused).lines[0].spans;
+        assert_eq!((spans[0].text.as_str(), spans[0].bold), ("kept", true));
+        assert_eq!((spans[1].text.as_str(), spans[1].bold), ("!", false));
+    }
+
+    #[test]
+    fn test_cursor_line() {
+        let mut vt = create(20, 8, 0);
+        vt.feed_rows("top\x1b[6;1Hfifth \x1b[1mrow\x1b[0m\x1b[6;4H\r\nbelow\x1b[6;4H").unwrap();
+
+        let line = vt.cursor_line().unwrap();
+        assert_eq!((line.row, line.cursor_col), (5, 3));
+        assert_eq!(line.line, create_snapshot(&vt).lines[5]);
+        let text: String = line.line.spans.iter().map(|span| span.text.as_str()).collect();
+        assert_eq!(text.trim_end(), "fifth row");
+
+        vt.feed_rows("\x1b[?25l").unwrap();
+        assert!(vt.cursor_line().is_none());
+    }
+
+    #[test]
+    fn test_feed_bytes_in_place() {
+        let input = "\x1b[1;32mgr\u{fc}n\x1b[0m \u{4e2d}\u{6587} line\r\n".repeat(5000);
+        let mut from_bytes = create(40, 10, 100);
+        let mut from_str = create(40, 10, 100);
+
+        assert_eq!(
+            from_bytes.feed_bytes_rows(input.as_bytes()).unwrap(),
+            from_str.feed_rows(&input).unwrap()
+        );
+        assert_eq!(create_snapshot(&from_bytes), create_snapshot(&from_str));
+
+        // A chunk ending mid-character still goes through the buffer
+        let bytes = "\u{fc}ber".as_bytes();
+        assert!(from_bytes.feed_bytes_rows(&bytes[..1]).unwrap().is_empty());
+        assert_eq!(from_bytes.utf8_tail, vec![0xC3]);
+        from_bytes.feed_bytes_rows(&bytes[1..]).unwrap();
+        assert!(from_bytes.utf8_tail.is_empty());
+        assert_eq!(from_bytes.terminal.line(9).text().trim_end(), "\u{fc}ber");
+    }
+
+    #[test]
+    fn test_feed_u8_large_buffer_chunks() {
+        // A PTY hands over fixed-size chunks that split characters and sequences anywhere
+        let input = "\x1b[38;5;208m\u{4e2d}\u{6587}\u{1F600} caf\u{e9}\x1b[0m\r\n".repeat(5000);
+        let mut from_bytes = create(80, 24, 1000);
+        let mut from_str = create(80, 24, 1000);
+
+        let mut rows = Vec::new();
+        for chunk in input.as_bytes().chunks(4093) {
+            rows.extend(from_bytes.feed_bytes_rows(chunk).unwrap());
+        }
+        assert!(from_bytes.utf8_tail.is_empty());
+        rows.sort_unstable();
+        rows.dedup();
+        assert_eq!(rows, from_str.feed_rows(&input).unwrap());
+        assert_eq!(create_full_snapshot(&from_bytes, true), create_full_snapshot(&from_str, true));
+    }
+
+    #[test]
+    fn test_dec_graphics_charset() {
+        let mut vt = create(10, 3, 0);
+        vt.feed_rows("\x1b(0qqqjk").unwrap();
+        assert_eq!(vt.active_charset(), Charset::DecGraphics);
+        vt.feed_rows("\x1b(Bqj").unwrap();
+        assert_eq!(vt.active_charset(), Charset::Ascii);
+        let text = view_text(&vt.terminal);
+        assert_eq!(text[0].trim_end(), "\u{2500}\u{2500}\u{2500}\u{2518}\u{2510}qj");
+
+        // G1 is shifted in with SO and out with SI; DECSTR designates ASCII again
+        vt.feed_rows("\r\n\x1b)0\x0eq").unwrap();
+        assert_eq!(vt.active_charset(), Charset::DecGraphics);
+        vt.feed_rows("\x0fq").unwrap();
+        assert_eq!(vt.active_charset(), Charset::Ascii);
+        assert_eq!(view_text(&vt.terminal)[1].trim_end(), "\u{2500}q");
+        vt.feed_rows("\x0e\x1b[!p").unwrap();
+        assert_eq!(vt.active_charset(), Charset::Ascii);
+
+        // The designation survives a round trip through the dump
+        vt.feed_rows("\x1b(0").unwrap();
+        let restored = restore(10, 3, 0, &vt.dump());
+        assert_eq!(restored.active_charset(), Charset::DecGraphics);
+    }
+
+    #[test]
+    fn test_scrollback_page() {
+        let mut vt = create(10, 3, 100);
+        let input: Vec<String> = (0..50).map(|i| format!("line {i}")).collect();
+        vt.feed_rows(&input.join("\r\n")).unwrap();
+        let text = |page: &ScrollbackPage| -> Vec<String> {
+            page.lines
+                .iter()
+                .map(|line| line.spans.iter().map(|span| span.text.as_str()).collect::<String>())
+                .map(|text| text.trim_end().to_string())
+                .collect()
+        };
+
+        let page = vt.scrollback_page(20, 3);
+        assert_eq!(page.total, 47);
+        assert_eq!(text(&page), vec!["line 20", "line 21", "line 22"]);
+
+        // Pages stop at the end of scrollback, before the viewport
+        let page = vt.scrollback_page(45, 10);
+        assert_eq!(text(&page), vec!["line 45", "line 46"]);
+        assert!(vt.scrollback_page(100, 10).lines.is_empty());
+        assert!(vt.scrollback_page(10, 0).lines.is_empty());
+    }
+
+    #[test]
+    fn test_capture_unsupported() {
+        let mut vt = create(10, 3, 100);
+        let raw = |vt: &Vt| -> Vec<(String, &str)> {
+            let unsupported = vt.unsupported.as_ref().unwrap();
+            unsupported.iter().map(|entry| (entry.raw.clone(), entry.kind)).collect()
+        };
+
+        // Nothing is captured until asked for
+        vt.feed_rows("\x1b[>4;1m").unwrap();
+        assert!(vt.unsupported.is_none());
+
+        vt.set_capture_unsupported(true);
+        // modifyOtherKeys, an unknown DEC mode, DECID, and OSC 7 (current directory)
+        vt.feed_rows("\x1b[>4;1m\x1b[?1;9999h\x1bZ\x1b]7;file:///tmp\x1b\\").unwrap();
+        // Sequences avt or the wrapper act on aren't captured
+        vt.feed_rows("\x1b[1;31mx\x1b[?2004h\x1b]2;title\x07\x1b#6\x1b[?1u\x1b[4h").unwrap();
+
+        assert_eq!(
+            raw(&vt),
+            vec![
+                ("\x1b[>4;1m".to_string(), "csi"),
+                ("\x1b[?1;9999h".to_string(), "csi"),
+                ("\x1bZ".to_string(), "esc"),
+                ("\x1b]7;file:///tmp".to_string(), "osc"),
+            ]
+        );
+
+        vt.set_capture_unsupported(false);
+        assert!(vt.unsupported.is_none());
+    }
+
+    #[test]
+    fn test_erase_scrollback() {
+        let mut vt = create(10, 3, 100);
+        vt.feed_rows("one\r\ntwo\r\nthree\r\nfour\r\nfive").unwrap();
+        assert_eq!(vt.scrollback_len(), 2);
+
+        let changes = vt.feed_changes("\x1b[3J").unwrap();
+        assert!(changes.scrollback_cleared);
+        assert_eq!(vt.scrollback_len(), 0);
+        assert_eq!(view_text(&vt.terminal), vec!["three     ", "four      ", "five      "]);
+        assert_eq!(vt.cursor_position(), [4, 2]);
+
+        // The limit still holds, and other feeds don't report a clear
+        let changes = vt.feed_changes("\r\nsix").unwrap();
+        assert!(!changes.scrollback_cleared);
+        assert_eq!(vt.scrollback_len(), 1);
+        assert_eq!(vt.scrollback_limit, 100);
+    }
+
+    #[test]
+    fn test_to_raw_capture() {
+        let mut vt = create(10, 3, 100);
+        let input: Vec<String> = (0..8).map(|i| format!("\x1b[3{i}mline {i}\x1b[0m")).collect();
+        vt.feed_rows(&input.join("\r\n")).unwrap();
+        vt.feed_rows("\x1b[2;4H").unwrap();
+
+        let capture = vt.to_raw_capture(true);
+        assert!(!capture.contains('\u{9b}'));
+        let mut copy = create(10, 3, 100);
+        copy.feed_rows("previous\r\noutput").unwrap();
+        copy.feed_rows(&capture).unwrap();
+
+        // The earlier screen is cleared, not scrolled into history
+        assert_eq!(all_text(&copy.terminal), all_text(&vt.terminal));
+        assert_eq!(create_snapshot(&copy), create_snapshot(&vt));
+        assert_eq!(copy.cursor_position(), [3, 1]);
+
+        let mut copy = create(10, 3, 100);
+        copy.feed_rows(&vt.to_raw_capture(false)).unwrap();
+        assert_eq!(copy.scrollback_len(), 0);
+        assert_eq!(view_text(&copy.terminal), view_text(&vt.terminal));
+        assert_eq!(copy.cursor_position(), [3, 1]);
+    }
+
+    #[test]
+    fn test_underline_style_and_color() {
+        let mut vt = create(30, 1, 0);
+        vt.feed_rows("\x1b[4:3;58:2::255:0:0mlint\x1b[4:2mdbl\x1b[59m \x1b[4:0mno\x1b[4mplain")
+            .unwrap();
+        let spans = &create_snapshot(&vt).lines[0].spans;
+        let style = |i: usize| {
+            let span: &SnapshotSpan = &spans[i];
+            (span.text.as_str(), span.underline, span.underline_style, span.underline_color.clone())
+        };
+        let red = Some(ColorValue::Rgb("#FF0000".to_string()));
+
+        // The colon form underlines in avt too; the color only shows while underlined
+        assert_eq!(style(0), ("lint", true, Some(UnderlineStyle::Curly), red.clone()));
+        assert_eq!(style(1), ("dbl", true, Some(UnderlineStyle::Double), red));
+        assert_eq!(style(2), (" ", true, Some(UnderlineStyle::Double), None));
+        assert_eq!(style(3), ("no", false, None, None));
+        assert_eq!(style(4), ("plain", true, None, None));
+
+        // Both survive a round trip through the ANSI export
+        let ansi = vt.to_ansi(false);
+        assert!(ansi.contains("\x1b[0;4:3;58:2::255:0:0mlint"));
+        let mut copy = create(30, 1, 0);
+        copy.feed_rows(&ansi).unwrap();
+        assert_eq!(create_snapshot(&copy), create_snapshot(&vt));
+    }
+
+    #[test]
+    fn test_underline_style_exports() {
+        let mut vt = create(20, 1, 0);
+        vt.feed_rows("\x1b[4:3;58;5;1mlint\x1b[4:2;59mdbl\x1b[24m off").unwrap();
+        let red = palette::rgb_hex(palette::xterm_rgb(1));
+
+        vt.set_resolve_colors(true);
+        let spans = &vt.view_with_options(&ViewOptions::default()).lines[0].spans;
+        assert_eq!(spans[0].underline_color, Some(ColorValue::Rgb(red.clone())));
+
+        let html = vt.to_html();
+        let lint = format!("text-decoration-style:wavy;text-decoration-color:{}", red);
+        assert!(html.contains(&format!("{}\">lint", lint)));
+        assert!(html.contains("text-decoration:underline;text-decoration-style:double\">dbl"));
+
+        let svg = vt.to_svg(8.0, 16.0, "monospace");
+        assert!(svg.contains(&format!("style=\"{}\">lint", lint)));
+
+        let rtf = vt.to_rtf();
+        assert!(rtf.contains("\\ulwave\\ulc1 lint\\uldb\\ulc0 dbl\\ulnone  off"));
+    }
+
+    #[test]
+    fn test_view_runs() {
+        let mut vt = create(12, 2, 0);
+        vt.feed_rows("a\x1b[1m日本\x1b[0;31mxy\x1b[0m!").unwrap();
+        let view = vt.view_runs();
+        let line = &view.lines[0];
+
+        // The runs cover the text end to end, by character, in the spans' styles
+        let spans = &create_snapshot(&vt).lines[0].spans;
+        assert_eq!(line.text, spans.iter().map(|span| span.text.as_str()).collect::<String>());
+        let chars: Vec<char> = line.text.chars().collect();
+        let mut end = 0;
+        for (run, span) in line.runs.iter().zip(spans) {
+            assert_eq!(run.start, end);
+            assert_eq!(chars[run.start..run.start + run.len].iter().collect::<String>(), span.text);
+            assert!(same_style(&run.attrs, span) && run.attrs.text.is_empty());
+            end += run.len;
+        }
+        assert_eq!(line.runs.len(), spans.len());
+        assert_eq!(end, chars.len());
+
+        let runs: Vec<(usize, usize, bool)> =
+            line.runs.iter().map(|run| (run.start, run.len, run.attrs.bold)).collect();
+        assert_eq!(runs[..3], [(0, 1, false), (1, 2, true), (3, 2, false)]);
+        assert!(!serde_json::to_string(&line.runs[0]).unwrap().contains("\"text\""));
+    }
+
+    #[test]
+    fn test_feed_chunked() {
+        let line = "\x1b[1;3%dmcolumns é 日本\x1b[0m \x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\";
+        let input: String = (0..2000)
+            .map(|i| line.replace("%d", &(i % 8).to_string()) + &format!(" {i}\r\n"))
+            .collect();
+
+        let mut whole = create(40, 10, 5000);
+        whole.feed_rows(&input).unwrap();
+
+        // Slices stretch to the end of the sequence or character they would cut
+        let mut chunked = create(40, 10, 5000);
+        let mut result = chunked.feed_chunk(&input, 1000).unwrap();
+        let mut calls = 1;
+        while result.remaining > 0 {
+            result = chunked.feed_chunk("", 1000).unwrap();
+            calls += 1;
+        }
+        assert!(calls <= input.len().div_ceil(1000));
+        assert_eq!(create_snapshot(&chunked), create_snapshot(&whole));
+        assert_eq!(all_text(&chunked.terminal), all_text(&whole.terminal));
+
+        // A feed between slices doesn't land inside a sequence
+        let mut vt = create(10, 2, 0);
+        assert_eq!(vt.feed_chunk("\x1b[31mred", 3).unwrap().remaining, 3);
+        vt.feed_rows("X").unwrap();
+        vt.feed_chunk("", 3).unwrap();
+        assert_eq!(view_text(&vt.terminal)[0].trim_end(), "Xred");
+        assert_eq!(create_snapshot(&vt).lines[0].spans[0].fg, Some(ColorValue::Indexed(1)));
+
+        // An unterminated sequence at the end of the queue goes in whole
+        let mut vt = create(10, 2, 0);
+        assert_eq!(vt.feed_chunk("ab\x1b]2;title", 3).unwrap().remaining, 0);
+        assert_eq!(vt.scanner.pending(), "\x1b]2;title");
+
+        // Resets drop the queued input; only RIS clears what was already applied
+        for (soft, kept) in [(false, ""), (true, "ab")] {
+            let mut vt = create(10, 2, 0);
+            assert_eq!(vt.feed_chunk("abcdefgh", 2).unwrap().remaining, 6);
+            if soft {
+                vt.soft_reset_rows().unwrap();
+            } else {
+                vt.reset_rows().unwrap();
+            }
+            assert_eq!(vt.feed_chunk("", 2).unwrap().remaining, 0);
+            assert_eq!(view_text(&vt.terminal)[0].trim_end(), kept);
+        }
+
+        // A slice too short for a character still takes the whole character
+        let mut vt = create(10, 2, 0);
+        assert_eq!(vt.feed_chunk("日本", 1).unwrap().remaining, 3);
+        assert_eq!(vt.feed_chunk("", 0).unwrap().remaining, 0);
+        assert_eq!(vt.feed_chunk("", 10).unwrap(), ChunkedFeed { changes: vec![], remaining: 0 });
+        assert_eq!(view_text(&vt.terminal)[0], "日本      ");
+    }
+
+    #[test]
+    fn test_decode_utf8_chunk_invalid_bytes() {
+        let mut tail = Vec::new();
+        assert_eq!(decode_utf8_chunk(&mut tail, b"a\xFFb"), "a\u{FFFD}b");
+        assert!(tail.is_empty());
     }
 }
@@ -0,0 +1,199 @@
+//! DEC line attributes (DECDWL, DECDHL, DECSWL), which avt's parser drops.
+//!
+//! Every line of the active buffer, history included, has an entry, oldest first, so an
+//! attribute moves along with its line. avt doesn't report how lines moved, so the moves
+//! are worked out from each function and the cursor before it runs, plus the change in
+//! the line count after it: a scroll region starting at the top row pushes its top lines
+//! into history (on either screen), anything else rotates rows within the viewport.
+//!
+//! Tracking only starts once a line gets a non-normal attribute, so plain sessions pay
+//! nothing.
+
+use avt::parser::{EdScope, Function};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// How a line is rendered
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LineAttr {
+    #[default]
+    Normal,
+    DoubleWidth,
+    DoubleHeightTop,
+    DoubleHeightBottom,
+}
+
+impl LineAttr {
+    /// The attribute selected by `ESC # final_char`
+    pub fn from_esc(final_char: char) -> Option<Self> {
+        match final_char {
+            '3' => Some(LineAttr::DoubleHeightTop),
+            '4' => Some(LineAttr::DoubleHeightBottom),
+            '5' => Some(LineAttr::Normal),
+            '6' => Some(LineAttr::DoubleWidth),
+            _ => None,
+        }
+    }
+
+    pub fn is_normal(&self) -> bool {
+        *self == LineAttr::Normal
+    }
+}
+
+/// How a function is about to move viewport rows, unless it grows the buffer instead
+pub(crate) enum Shift {
+    /// Rows move up by `n` within the range, normal rows entering at its bottom
+    Up(Range<usize>, usize),
+    /// Rows move down by `n` within the range, normal rows entering at its top
+    Down(Range<usize>, usize),
+    /// Every viewport row returns to normal
+    Reset,
+    None,
+}
+
+impl Shift {
+    /// Work out the shift for `function`, given the cursor (`col`, `row`), the width and
+    /// height, and the scroll region as an inclusive row range
+    pub fn of(
+        function: &Function,
+        (col, row): (usize, usize),
+        (cols, rows): (usize, usize),
+        (top, bottom): (usize, usize),
+    ) -> Shift {
+        let region = top..bottom + 1;
+        // IL and DL work from the cursor down to the bottom margin, or the screen's bottom
+        // below it
+        let from_cursor = row..if row <= bottom { bottom + 1 } else { rows };
+
+        match *function {
+            Function::Lf | Function::Nel if row == bottom => Shift::Up(region, 1),
+            // Printing in the pending-wrap column wraps first
+            Function::Print(_) if col >= cols && row == bottom => Shift::Up(region, 1),
+            Function::Su(n) => Shift::Up(region, count(n)),
+            Function::Ri if row == top => Shift::Down(region, 1),
+            Function::Sd(n) => Shift::Down(region, count(n)),
+            Function::Il(n) => Shift::Down(from_cursor, count(n)),
+            Function::Dl(n) => Shift::Up(from_cursor, count(n)),
+            // Erasing the whole screen and the alignment pattern make every line single-width
+            Function::Ed(EdScope::All) | Function::Decaln => Shift::Reset,
+            _ => Shift::None,
+        }
+    }
+}
+
+/// A count parameter, where 0 means 1
+fn count(n: u16) -> usize {
+    (n as usize).max(1)
+}
+
+#[derive(Clone)]
+pub(crate) struct LineAttrs {
+    /// One entry per line of the active buffer, oldest first
+    lines: VecDeque<LineAttr>,
+    /// The primary buffer's entries while the alt screen is active
+    primary: Option<VecDeque<LineAttr>>,
+}
+
+impl LineAttrs {
+    /// Start tracking a buffer of `len` lines, all normal
+    pub fn new(len: usize) -> Self {
+        LineAttrs {
+            lines: VecDeque::from(vec![LineAttr::Normal; len]),
+            primary: None,
+        }
+    }
+
+    /// Set the attribute of viewport row `row`
+    pub fn set(&mut self, row: usize, rows: usize, attr: LineAttr) {
+        let index = self.lines.len() - rows + row;
+
+        if let Some(line) = self.lines.get_mut(index) {
+            *line = attr;
+        }
+    }
+
+    /// Attribute of the line `from_bottom` lines above the last viewport row
+    pub fn line(&self, from_bottom: usize) -> LineAttr {
+        self.lines
+            .len()
+            .checked_sub(from_bottom + 1)
+            .and_then(|index| self.lines.get(index).copied())
+            .unwrap_or_default()
+    }
+
+    /// Follow the line moves of a function that ran: `shift` as worked out before it ran,
+    /// `margin_end` the row below the scroll region and `len` the buffer's new line count
+    pub fn follow(&mut self, shift: Shift, rows: usize, margin_end: usize, len: usize) {
+        let offset = self.lines.len() - rows;
+
+        if len > self.lines.len() {
+            // The region scrolled up from the top row; avt inserts the new blank lines right
+            // below it, pushing everything above into history
+            for _ in self.lines.len()..len {
+                self.lines.insert(offset + margin_end, LineAttr::Normal);
+            }
+            return;
+        }
+
+        let view = &mut self.lines.make_contiguous()[offset..];
+
+        match shift {
+            Shift::Up(range, n) => {
+                let rows = &mut view[range];
+                let n = n.min(rows.len());
+                rows.rotate_left(n);
+                let len = rows.len();
+                rows[len - n..].fill(LineAttr::Normal);
+            }
+            Shift::Down(range, n) => {
+                let rows = &mut view[range];
+                let n = n.min(rows.len());
+                rows.rotate_right(n);
+                rows[..n].fill(LineAttr::Normal);
+            }
+            Shift::Reset => view.fill(LineAttr::Normal),
+            Shift::None => {}
+        }
+
+        self.sync(len);
+    }
+
+    /// Switch to the other buffer, now `len` lines long. The alt screen starts out normal;
+    /// the primary one gets its entries back.
+    pub fn switch_buffer(&mut self, alternate: bool, len: usize) {
+        if alternate {
+            let blank = VecDeque::from(vec![LineAttr::Normal; len]);
+            self.primary = Some(std::mem::replace(&mut self.lines, blank));
+            return;
+        }
+        self.lines = self.primary.take().unwrap_or_default();
+
+        self.fit_bottom(len);
+    }
+
+    /// Follow a resize to `len` lines. Without a width change the lines stay in order and
+    /// only the bottom is dropped or padded; a width change re-wraps every line, so the
+    /// attributes are dropped.
+    pub fn resize(&mut self, width_changed: bool, len: usize) {
+        if width_changed {
+            self.lines = VecDeque::from(vec![LineAttr::Normal; len]);
+            self.primary = None;
+        } else {
+            self.fit_bottom(len);
+        }
+    }
+
+    /// Drop the entries of lines evicted from the top of history, keeping `len`
+    pub fn sync(&mut self, len: usize) {
+        while self.lines.len() > len {
+            self.lines.pop_front();
+        }
+        self.fit_bottom(len);
+    }
+
+    fn fit_bottom(&mut self, len: usize) {
+        self.lines.resize(len, LineAttr::Normal);
+    }
+}
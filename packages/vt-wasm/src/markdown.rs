@@ -0,0 +1,28 @@
+//! Plain text wrapped in a Markdown code fence, for pasting into chats and issues.
+
+/// Wrap `lines` in a fenced code block. Each line loses its trailing whitespace and
+/// trailing empty lines are dropped; the fence is one backtick longer than the longest
+/// run of backticks in the text, and at least three.
+pub(crate) fn text_to_markdown(lines: &[String]) -> String {
+    let mut lines: Vec<&str> = lines.iter().map(|line| line.trim_end()).collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let longest_run = lines
+        .iter()
+        .flat_map(|line| line.split(|c| c != '`'))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat((longest_run + 1).max(3));
+
+    let mut markdown = format!("{}\n", fence);
+    for line in lines {
+        markdown.push_str(line);
+        markdown.push('\n');
+    }
+    markdown.push_str(&fence);
+    markdown.push('\n');
+    markdown
+}
@@ -0,0 +1,285 @@
+//! Per-cell attributes that avt's pen doesn't model (e.g. OSC 8 hyperlinks, rapid blink).
+//!
+//! A shadow avt `Terminal` receives the same input as the real one, except that every
+//! printed character is replaced by a marker codepoint of the same display width that
+//! encodes an index into an attribute table. avt therefore handles all cursor movement,
+//! scrolling, erasing and wrapping for the overlay too, and reading a shadow cell yields
+//! the attributes of the matching real cell. The shadow keeps no scrollback of its own:
+//! lines leaving its viewport are reduced to attribute runs in a compact history.
+//!
+//! The overlay is only created once a sequence needs it, so plain sessions pay nothing.
+
+use crate::{ColorValue, UnderlineStyle};
+use avt::parser::{Function, Parser};
+use avt::terminal::{BufferType, Terminal};
+use std::collections::{HashMap, VecDeque};
+use unicode_width::UnicodeWidthChar;
+
+/// Markers for narrow characters: Supplementary Private Use Area-A (display width 1)
+const NARROW_BASE: u32 = 0xF0000;
+/// Markers for wide characters: CJK Unified Ideographs Extension B (display width 2)
+const WIDE_BASE: u32 = 0x20000;
+/// Number of attribute sets that fit in both marker ranges
+const MAX_IDS: u32 = 0xA6E0;
+
+/// Attribute set attached to a cell; the default set means "nothing to add"
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct CellAttrs {
+    pub url: Option<String>,
+    /// SGR 6, which avt reads as nothing
+    pub rapid_blink: bool,
+    /// DECSCA protection, which keeps the cell through selective erases
+    pub protected: bool,
+    /// SGR `4:n`, which avt skips; None for a plain underline
+    pub underline_style: Option<UnderlineStyle>,
+    /// SGR 58
+    pub underline_color: Option<ColorValue>,
+}
+
+/// Columns `start..end` of a line carry attribute set `id`
+#[derive(Clone, Debug)]
+pub(crate) struct Run {
+    start: usize,
+    end: usize,
+    id: u32,
+}
+
+pub(crate) struct Overlay {
+    parser: Parser,
+    terminal: Terminal,
+    /// Interned attribute sets; id 0 is the default set
+    table: Vec<CellAttrs>,
+    ids: HashMap<CellAttrs, u32>,
+    /// Id applied to newly printed characters
+    current: u32,
+    /// Attribute runs of lines that scrolled into the primary buffer's history, oldest first
+    history: VecDeque<Vec<Run>>,
+}
+
+impl Overlay {
+    /// Create an overlay mirroring `terminal`, whose parser is in the middle of `pending`
+    pub fn new(terminal: &Terminal, pending: &str) -> Self {
+        let mut overlay = Overlay {
+            parser: Parser::new(),
+            terminal: Terminal::new(terminal.size(), Some(0)),
+            table: vec![CellAttrs::default()],
+            ids: HashMap::from([(CellAttrs::default(), 0)]),
+            current: 0,
+            history: VecDeque::new(),
+        };
+
+        for ch in terminal.dump().chars().chain(pending.chars()) {
+            overlay.feed(ch);
+        }
+
+        drop(overlay.terminal.gc());
+        overlay
+    }
+
+    /// An independent copy, given the raw unfinished sequence the parser is in the middle of.
+    /// The shadow screen is rebuilt from its own dump, which holds markers rather than text.
+    pub fn duplicate(&self, pending: &str) -> Self {
+        let mut parser = Parser::new();
+        let mut terminal = Terminal::new(self.terminal.size(), Some(0));
+
+        for ch in self.terminal.dump().chars() {
+            if let Some(function) = parser.feed(ch) {
+                terminal.execute(function);
+            }
+        }
+
+        let mut overlay = Overlay {
+            parser: Parser::new(),
+            terminal,
+            table: self.table.clone(),
+            ids: self.ids.clone(),
+            current: self.current,
+            history: self.history.clone(),
+        };
+
+        for ch in pending.chars() {
+            overlay.feed(ch);
+        }
+
+        drop(overlay.terminal.gc());
+        overlay
+    }
+
+    /// Attributes applied to newly printed characters
+    pub fn current(&self) -> &CellAttrs {
+        &self.table[self.current as usize]
+    }
+
+    pub fn set_current(&mut self, attrs: CellAttrs) {
+        self.current = match self.ids.get(&attrs) {
+            Some(&id) => id,
+            None if (self.table.len() as u32) < MAX_IDS => {
+                let id = self.table.len() as u32;
+                self.ids.insert(attrs.clone(), id);
+                self.table.push(attrs);
+                id
+            }
+            // Table exhausted: new attribute sets are dropped rather than misattributed
+            None => 0,
+        };
+    }
+
+    pub fn feed(&mut self, ch: char) {
+        let function = match self.parser.feed(ch) {
+            Some(Function::Print(ch)) => Function::Print(self.marker(ch)),
+            Some(function) => function,
+            None => return,
+        };
+
+        self.terminal.execute(function);
+    }
+
+    /// Execute `seq` (which must not print) through a parser of its own, so a sequence the
+    /// overlay's parser is in the middle of carries on unaffected
+    pub fn inject(&mut self, seq: &str) {
+        let mut parser = Parser::new();
+
+        for ch in seq.chars() {
+            if let Some(function) = parser.feed(ch) {
+                self.terminal.execute(function);
+            }
+        }
+    }
+
+    /// Follow a resize of the real terminal, which moved `pulled` lines of its history
+    /// back into the top of its grown viewport. The shadow keeps no scrollback, so it makes
+    /// room at the top itself and prints those lines back from their runs.
+    pub fn resize(&mut self, cols: usize, rows: usize, pulled: usize) {
+        self.terminal.resize(cols, rows);
+
+        if pulled == 0 {
+            return;
+        }
+
+        // avt reset the margins, so the whole screen scrolls and rows are absolute
+        let cursor = self.terminal.cursor();
+        self.terminal.execute(Function::Sd(pulled as u16));
+
+        for row in (0..pulled).rev() {
+            let Some(runs) = self.history.pop_back() else {
+                break;
+            };
+
+            for run in runs {
+                self.terminal
+                    .execute(Function::Cup(row as u16 + 1, run.start as u16 + 1));
+                let marker = char::from_u32(NARROW_BASE + run.id).unwrap_or(' ');
+                for _ in run.start..run.end {
+                    self.terminal.execute(Function::Print(marker));
+                }
+            }
+        }
+
+        let col = cursor.col.min(cols - 1);
+        self.terminal.execute(Function::Cup(
+            (cursor.row + pulled) as u16 + 1,
+            col as u16 + 1,
+        ));
+    }
+
+    /// Move lines that left the viewport into history, keeping at most `scrollback_len`
+    pub fn sync(&mut self, scrollback_len: usize) {
+        let evicted: Vec<Vec<Run>> = self.terminal.gc().map(|line| runs(&line)).collect();
+        self.history.extend(evicted);
+
+        // The alt buffer hides the primary history rather than clearing it
+        if self.terminal.active_buffer_type() == BufferType::Primary {
+            while self.history.len() > scrollback_len {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    /// Overlay for the line `from_bottom` lines above the last viewport row
+    pub fn line(&self, from_bottom: usize) -> Option<LineOverlay<'_>> {
+        let (_, rows) = self.terminal.size();
+
+        if from_bottom < rows {
+            let line = self.terminal.line(rows - 1 - from_bottom);
+            Some(LineOverlay::Live(self, line))
+        } else {
+            let index = self.history.len().checked_sub(from_bottom - rows + 1)?;
+            Some(LineOverlay::History(self, &self.history[index]))
+        }
+    }
+
+    /// Replacement for `ch` in the shadow terminal. Default-attribute spaces stay spaces
+    /// so avt's trailing-blank trimming on resize treats both terminals alike.
+    fn marker(&self, ch: char) -> char {
+        if ch == ' ' && self.current == 0 {
+            return ch;
+        }
+
+        let base = if ch.width().unwrap_or(1) == 2 {
+            WIDE_BASE
+        } else {
+            NARROW_BASE
+        };
+
+        char::from_u32(base + self.current).unwrap_or(ch)
+    }
+
+    fn attrs(&self, id: u32) -> Option<&CellAttrs> {
+        (id != 0).then(|| &self.table[id as usize])
+    }
+}
+
+/// Attribute lookup for a single line
+pub(crate) enum LineOverlay<'a> {
+    Live(&'a Overlay, &'a avt::Line),
+    History(&'a Overlay, &'a [Run]),
+}
+
+impl LineOverlay<'_> {
+    /// Attributes of the cell at `col`, or None when it has nothing to add
+    pub fn get(&self, col: usize) -> Option<&CellAttrs> {
+        match self {
+            LineOverlay::Live(overlay, line) => line
+                .cells()
+                .get(col)
+                .and_then(|cell| overlay.attrs(marker_id(cell.char()))),
+            LineOverlay::History(overlay, runs) => runs
+                .iter()
+                .find(|run| (run.start..run.end).contains(&col))
+                .and_then(|run| overlay.attrs(run.id)),
+        }
+    }
+}
+
+fn marker_id(ch: char) -> u32 {
+    let code = ch as u32;
+
+    if (NARROW_BASE..NARROW_BASE + MAX_IDS).contains(&code) {
+        code - NARROW_BASE
+    } else if (WIDE_BASE..WIDE_BASE + MAX_IDS).contains(&code) {
+        code - WIDE_BASE
+    } else {
+        0
+    }
+}
+
+/// Collapse a shadow line into runs of non-default attribute ids
+fn runs(line: &avt::Line) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+
+    for (col, cell) in line.cells().iter().enumerate() {
+        let id = marker_id(cell.char());
+
+        match runs.last_mut() {
+            Some(run) if run.id == id && run.end == col => run.end = col + 1,
+            _ if id != 0 => runs.push(Run {
+                start: col,
+                end: col + 1,
+                id,
+            }),
+            _ => {}
+        }
+    }
+
+    runs
+}
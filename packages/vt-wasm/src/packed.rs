@@ -0,0 +1,113 @@
+//! Compact binary encoding of a snapshot's lines for the fast render path.
+//!
+//! All integers are little-endian, so JS can read them with a `DataView`:
+//!
+//! ```text
+//! u32 line_count
+//! line_count x {
+//!     u32 span_count
+//!     u8  line_flags        bit 0: wrapped
+//!     span_count x {
+//!         u16 attrs         bit 0 bold, 1 faint, 2 italic, 3 underline,
+//!                           4 strikethrough, 5 blink, 6 inverse, 7 rapid blink
+//!         u32 fg            tagged color, see below
+//!         u32 bg
+//!         u32 text_len      byte length of the UTF-8 text that follows
+//!         u8  text[text_len]
+//!     }
+//! }
+//! ```
+//!
+//! A tagged color keeps its kind in the top byte: `0` is the default color, `1` an
+//! indexed color (index in the low byte) and `2` an RGB color (`0xRRGGBB` in the low
+//! three bytes). Hyperlinks, per-character widths and the underline style and color
+//! (SGR `4:n` and 58) are not part of the layout; an underline of any style sets bit 3.
+//!
+//! The changes of a feed (`feed_packed`) put each line after the viewport row it replaces:
+//!
+//! ```text
+//! u32 change_count
+//! change_count x {
+//!     u32 row
+//!     line              as above, from span_count on
+//! }
+//! ```
+
+use crate::palette::parse_hex;
+use crate::{ColorValue, SnapshotLine, SnapshotSpan};
+
+pub(crate) const WRAPPED: u8 = 1;
+
+pub(crate) const BOLD: u16 = 1 << 0;
+pub(crate) const FAINT: u16 = 1 << 1;
+pub(crate) const ITALIC: u16 = 1 << 2;
+pub(crate) const UNDERLINE: u16 = 1 << 3;
+pub(crate) const STRIKETHROUGH: u16 = 1 << 4;
+pub(crate) const BLINK: u16 = 1 << 5;
+pub(crate) const INVERSE: u16 = 1 << 6;
+pub(crate) const RAPID_BLINK: u16 = 1 << 7;
+
+pub(crate) const TAG_INDEXED: u32 = 1 << 24;
+pub(crate) const TAG_RGB: u32 = 2 << 24;
+
+/// Encode lines in the layout described in the module docs
+pub(crate) fn pack(lines: &[SnapshotLine]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend((lines.len() as u32).to_le_bytes());
+
+    for line in lines {
+        push_line(&mut out, line);
+    }
+
+    out
+}
+
+/// Append the changed `rows` and their new `lines` to `out` in the change layout
+pub(crate) fn pack_changes(out: &mut Vec<u8>, rows: &[usize], lines: &[SnapshotLine]) {
+    out.extend((rows.len() as u32).to_le_bytes());
+
+    for (row, line) in rows.iter().zip(lines) {
+        out.extend((*row as u32).to_le_bytes());
+        push_line(out, line);
+    }
+}
+
+fn push_line(out: &mut Vec<u8>, line: &SnapshotLine) {
+    out.extend((line.spans.len() as u32).to_le_bytes());
+    out.push(if line.wrapped { WRAPPED } else { 0 });
+
+    for span in &line.spans {
+        out.extend(attrs(span).to_le_bytes());
+        out.extend(color(span.fg.as_ref()).to_le_bytes());
+        out.extend(color(span.bg.as_ref()).to_le_bytes());
+        out.extend((span.text.len() as u32).to_le_bytes());
+        out.extend(span.text.as_bytes());
+    }
+}
+
+fn attrs(span: &SnapshotSpan) -> u16 {
+    [
+        (span.bold, BOLD),
+        (span.faint, FAINT),
+        (span.italic, ITALIC),
+        (span.underline, UNDERLINE),
+        (span.strikethrough, STRIKETHROUGH),
+        (span.blink, BLINK),
+        (span.inverse, INVERSE),
+        (span.rapid_blink, RAPID_BLINK),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .fold(0, |flags, (_, bit)| flags | bit)
+}
+
+fn color(color: Option<&ColorValue>) -> u32 {
+    match color {
+        None => 0,
+        Some(ColorValue::Indexed(n)) => TAG_INDEXED | *n as u32,
+        Some(ColorValue::Rgb(hex)) => match parse_hex(hex) {
+            Some((r, g, b)) => TAG_RGB | (r as u32) << 16 | (g as u32) << 8 | b as u32,
+            None => 0,
+        },
+    }
+}
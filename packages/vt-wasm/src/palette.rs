@@ -0,0 +1,189 @@
+//! Indexed-color palettes used to resolve palette indices to concrete RGB.
+//! The standard xterm 256-color table is the fallback for any entry a theme leaves out.
+
+/// The 16 base ANSI colors as rendered by xterm
+const XTERM_BASE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xCD, 0x00, 0x00),
+    (0x00, 0xCD, 0x00),
+    (0xCD, 0xCD, 0x00),
+    (0x00, 0x00, 0xEE),
+    (0xCD, 0x00, 0xCD),
+    (0x00, 0xCD, 0xCD),
+    (0xE5, 0xE5, 0xE5),
+    (0x7F, 0x7F, 0x7F),
+    (0xFF, 0x00, 0x00),
+    (0x00, 0xFF, 0x00),
+    (0xFF, 0xFF, 0x00),
+    (0x5C, 0x5C, 0xFF),
+    (0xFF, 0x00, 0xFF),
+    (0x00, 0xFF, 0xFF),
+    (0xFF, 0xFF, 0xFF),
+];
+
+/// Channel levels of the 6x6x6 color cube (indices 16-231)
+const CUBE_LEVELS: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+
+/// Resolve a palette index to RGB using the standard xterm table
+pub(crate) fn xterm_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => XTERM_BASE[index as usize],
+        16..=231 => {
+            let i = (index - 16) as usize;
+            (
+                CUBE_LEVELS[i / 36],
+                CUBE_LEVELS[(i / 6) % 6],
+                CUBE_LEVELS[i % 6],
+            )
+        }
+        232..=255 => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        }
+    }
+}
+
+/// Index of the color among the first `count` xterm base colors (8 or 16) nearest to
+/// `rgb`, by squared RGB distance
+pub(crate) fn nearest_base((r, g, b): (u8, u8, u8), count: usize) -> u8 {
+    let distance = |&(br, bg, bb): &(u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, br) + d(g, bg) + d(b, bb)
+    };
+
+    let base = &XTERM_BASE[..count.min(XTERM_BASE.len())];
+    let nearest = (0..base.len()).min_by_key(|&i| distance(&base[i]));
+    nearest.unwrap_or(0) as u8
+}
+
+/// Format RGB as an uppercase `#RRGGBB` hex string
+pub(crate) fn rgb_hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
+
+/// Parse a `#RRGGBB` (or `RRGGBB`) hex string into RGB
+pub(crate) fn parse_hex(hex: &str) -> Option<(u8, u8, u8)> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 || !digits.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Parse an X11 color spec as used by OSC 4/10/11: `#RRGGBB` or `rgb:R/G/B` with 1-4
+/// hex digits per channel, scaled to 8 bits
+pub(crate) fn parse_color_spec(spec: &str) -> Option<(u8, u8, u8)> {
+    let Some(channels) = spec.strip_prefix("rgb:") else {
+        return spec.starts_with('#').then(|| parse_hex(spec)).flatten();
+    };
+
+    let channel = |digits: &str| {
+        if digits.is_empty() || digits.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(digits, 16).ok()?;
+        let max = (1u32 << (4 * digits.len())) - 1;
+        Some((value * 0xFF / max) as u8)
+    };
+
+    let mut parts = channels.split('/');
+    let rgb = (
+        channel(parts.next()?)?,
+        channel(parts.next()?)?,
+        channel(parts.next()?)?,
+    );
+    parts.next().is_none().then_some(rgb)
+}
+
+/// Format RGB as the `rgb:RRRR/GGGG/BBBB` spec xterm answers color queries with
+pub(crate) fn rgb_spec((r, g, b): (u8, u8, u8)) -> String {
+    let wide = |channel: u8| channel as u16 * 0x101;
+    format!("rgb:{:04x}/{:04x}/{:04x}", wide(r), wide(g), wide(b))
+}
+
+/// Maximum number of entries in an indexed-color palette
+const PALETTE_SIZE: usize = 256;
+
+/// A themed palette: custom entries override the first N xterm colors
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Palette {
+    custom: Vec<(u8, u8, u8)>,
+}
+
+impl Palette {
+    /// Build a palette from up to 256 `#RRGGBB` strings
+    pub fn parse(hex_colors: &[String]) -> Result<Self, String> {
+        if hex_colors.len() > PALETTE_SIZE {
+            return Err(format!(
+                "palette has {} entries, at most {PALETTE_SIZE} allowed",
+                hex_colors.len()
+            ));
+        }
+
+        let custom = hex_colors
+            .iter()
+            .enumerate()
+            .map(|(i, hex)| {
+                parse_hex(hex).ok_or_else(|| format!("invalid palette color at index {i}: {hex:?}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Palette { custom })
+    }
+
+    /// Resolve a palette index to RGB, falling back to xterm for unset entries
+    pub fn rgb(&self, index: u8) -> (u8, u8, u8) {
+        self.custom
+            .get(index as usize)
+            .copied()
+            .unwrap_or_else(|| xterm_rgb(index))
+    }
+
+    /// Override one entry; entries before it that weren't set keep their xterm color
+    pub fn set(&mut self, index: u8, rgb: (u8, u8, u8)) {
+        let index = index as usize;
+        while self.custom.len() <= index {
+            self.custom.push(xterm_rgb(self.custom.len() as u8));
+        }
+        self.custom[index] = rgb;
+    }
+
+    /// All 256 entries as `#RRGGBB`
+    pub fn to_hex(&self) -> Vec<String> {
+        (0..=255).map(|index| rgb_hex(self.rgb(index))).collect()
+    }
+}
+
+/// Colors of cells that don't set their own; unset entries fall back to the palette's
+/// white (7) foreground and black (0) background
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct DefaultColors {
+    pub fg: Option<(u8, u8, u8)>,
+    pub bg: Option<(u8, u8, u8)>,
+}
+
+impl DefaultColors {
+    pub fn parse(fg: Option<&str>, bg: Option<&str>) -> Result<Self, String> {
+        let parse = |hex: Option<&str>, which: &str| {
+            hex.map(|hex| {
+                parse_hex(hex).ok_or_else(|| format!("invalid default {which} color: {hex:?}"))
+            })
+            .transpose()
+        };
+
+        Ok(DefaultColors {
+            fg: parse(fg, "foreground")?,
+            bg: parse(bg, "background")?,
+        })
+    }
+
+    pub fn fg(&self, palette: &Palette) -> (u8, u8, u8) {
+        self.fg.unwrap_or_else(|| palette.rgb(7))
+    }
+
+    pub fn bg(&self, palette: &Palette) -> (u8, u8, u8) {
+        self.bg.unwrap_or_else(|| palette.rgb(0))
+    }
+}
@@ -0,0 +1,102 @@
+//! When each viewport row last changed, for fading out old output.
+//!
+//! A feed compares every row with the row it held before, so redrawing a row with the
+//! same content doesn't count and a row keeps its time when it scrolls. The rows are
+//! followed through a feed the same way line attributes are, see `line_attrs`.
+
+use crate::line_attrs::Shift;
+use avt::Line;
+
+#[derive(Clone)]
+pub(crate) struct RowTimes {
+    /// Per viewport row, top first, in milliseconds of the `tick` clock
+    times: Vec<f64>,
+    /// While a feed runs: the viewport before it
+    before: Vec<Line>,
+    /// While a feed runs: the row of `before` each row holds now, None for a new one
+    origins: Vec<Option<usize>>,
+    /// Line count of the buffer after the last followed function
+    len: usize,
+}
+
+impl RowTimes {
+    /// Start tracking `rows` rows, all changed at `now`
+    pub fn new(rows: usize, now: f64) -> Self {
+        RowTimes {
+            times: vec![now; rows],
+            before: Vec::new(),
+            origins: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Time the viewport row `row` last changed
+    pub fn get(&self, row: usize) -> Option<f64> {
+        self.times.get(row).copied()
+    }
+
+    /// Remember the viewport before a feed into a buffer of `len` lines
+    pub fn begin<'a>(&mut self, view: impl Iterator<Item = &'a Line>, len: usize) {
+        self.before = view.cloned().collect();
+        self.origins = (0..self.before.len()).map(Some).collect();
+        self.len = len;
+    }
+
+    /// Follow the row moves of a function that ran, as for `LineAttrs::follow`; after a
+    /// screen switch every row counts as new
+    pub fn follow(&mut self, shift: &Shift, margin_end: usize, len: usize, switched: bool) {
+        let grown = len.saturating_sub(self.len);
+        self.len = len;
+
+        if switched {
+            self.origins.fill(None);
+            return;
+        }
+
+        let (range, n, up) = match shift {
+            // Lines pushed into history move the rows above the margin up
+            _ if grown > 0 => (0..margin_end, grown, true),
+            Shift::Up(range, n) => (range.clone(), *n, true),
+            Shift::Down(range, n) => (range.clone(), *n, false),
+            Shift::Reset | Shift::None => return,
+        };
+        let Some(rows) = self.origins.get_mut(range) else {
+            return;
+        };
+        let n = n.min(rows.len());
+
+        if up {
+            rows.rotate_left(n);
+            let len = rows.len();
+            rows[len - n..].fill(None);
+        } else {
+            rows.rotate_right(n);
+            rows[..n].fill(None);
+        }
+    }
+
+    /// Finish a feed: rows that differ from the row they came from changed at `now`
+    pub fn finish<'a>(&mut self, view: impl Iterator<Item = &'a Line>, now: f64) {
+        let times: Vec<f64> = view
+            .enumerate()
+            .map(|(row, line)| {
+                let origin = self.origins.get(row).copied().flatten();
+                match origin {
+                    Some(origin) if self.before.get(origin) == Some(line) => {
+                        self.times.get(origin).copied().unwrap_or(now)
+                    }
+                    _ => now,
+                }
+            })
+            .collect();
+
+        self.times = times;
+        self.before.clear();
+        self.origins.clear();
+    }
+
+    /// Follow a resize to `rows` rows, which counts as a change of every row
+    pub fn resize(&mut self, rows: usize, now: f64) {
+        self.times = vec![now; rows];
+    }
+}
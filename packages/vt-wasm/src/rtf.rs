@@ -0,0 +1,137 @@
+//! Rich Text Format rendering of a terminal snapshot, for pasting into word processors.
+
+use crate::palette::{parse_hex, xterm_rgb};
+use crate::{ColorValue, SnapshotSpan, TerminalSnapshot, UnderlineStyle};
+
+/// Render a snapshot as an RTF document in a monospace font. The color table holds every
+/// distinct color the spans use; each span switches colors and toggles bold, italic,
+/// underline (in its style and color) and strikethrough as needed, and every line ends a
+/// paragraph.
+pub(crate) fn snapshot_to_rtf(snapshot: &TerminalSnapshot) -> String {
+    let mut colors: Vec<(u8, u8, u8)> = Vec::new();
+    let mut body = String::new();
+    let mut state = Format::default();
+
+    for line in &snapshot.lines {
+        for span in &line.spans {
+            let mut index = |color: &Option<ColorValue>| {
+                color
+                    .as_ref()
+                    .map_or(0, |color| color_index(&mut colors, color))
+            };
+            let format = Format {
+                fg: index(&span.fg),
+                bg: index(&span.bg),
+                bold: span.bold,
+                italic: span.italic,
+                underline: span
+                    .underline
+                    .then(|| span.underline_style.map_or("\\ul", UnderlineStyle::rtf)),
+                underline_color: index(&span.underline_color),
+                strikethrough: span.strikethrough,
+            };
+
+            push_format_changes(&mut body, &state, &format);
+            state = format;
+            push_escaped(&mut body, span);
+        }
+
+        body.push_str("\\par\n");
+    }
+
+    let mut rtf = String::from("{\\rtf1\\ansi\\deff0{\\fonttbl{\\f0\\fmodern Courier New;}}\n");
+    rtf.push_str("{\\colortbl;");
+    for (r, g, b) in &colors {
+        rtf.push_str(&format!("\\red{}\\green{}\\blue{};", r, g, b));
+    }
+    rtf.push_str("}\n\\f0\n");
+    rtf.push_str(&body);
+    rtf.push_str("}\n");
+    rtf
+}
+
+/// Character formatting in effect; colors are color table indices, 0 for the default
+#[derive(Default, PartialEq)]
+struct Format {
+    fg: usize,
+    bg: usize,
+    bold: bool,
+    italic: bool,
+    /// Control word of the underline style, None when not underlined
+    underline: Option<&'static str>,
+    underline_color: usize,
+    strikethrough: bool,
+}
+
+/// Color table index of `color`, adding it on first use (entry 0 is the default color)
+fn color_index(colors: &mut Vec<(u8, u8, u8)>, color: &ColorValue) -> usize {
+    let rgb = match color {
+        ColorValue::Indexed(n) => xterm_rgb(*n),
+        ColorValue::Rgb(hex) => parse_hex(hex).unwrap_or_default(),
+    };
+
+    match colors.iter().position(|&known| known == rgb) {
+        Some(i) => i + 1,
+        None => {
+            colors.push(rgb);
+            colors.len()
+        }
+    }
+}
+
+/// Append the control words that turn formatting `from` into `to`
+fn push_format_changes(out: &mut String, from: &Format, to: &Format) {
+    if from == to {
+        return;
+    }
+
+    if from.fg != to.fg {
+        out.push_str(&format!("\\cf{}", to.fg));
+    }
+    if from.bg != to.bg {
+        out.push_str(&format!("\\highlight{}", to.bg));
+    }
+
+    let mut toggle = |was: bool, is: bool, on: &str, off: &str| {
+        if was != is {
+            out.push_str(if is { on } else { off });
+        }
+    };
+    toggle(from.bold, to.bold, "\\b", "\\b0");
+    toggle(from.italic, to.italic, "\\i", "\\i0");
+    toggle(
+        from.strikethrough,
+        to.strikethrough,
+        "\\strike",
+        "\\strike0",
+    );
+
+    if from.underline != to.underline {
+        out.push_str(to.underline.unwrap_or("\\ulnone"));
+    }
+    if from.underline_color != to.underline_color {
+        out.push_str(&format!("\\ulc{}", to.underline_color));
+    }
+
+    // Ends the last control word, so text starting with a letter or digit isn't read into it
+    out.push(' ');
+}
+
+/// Append a span's text with RTF special characters escaped and non-ASCII characters as
+/// `\uN?` escapes (UTF-16 code units, written signed)
+fn push_escaped(out: &mut String, span: &SnapshotSpan) {
+    for ch in span.text.chars() {
+        match ch {
+            '\\' | '{' | '}' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            ' '..='~' => out.push(ch),
+            _ => {
+                for unit in ch.encode_utf16(&mut [0; 2]) {
+                    out.push_str(&format!("\\u{}?", *unit as i16));
+                }
+            }
+        }
+    }
+}
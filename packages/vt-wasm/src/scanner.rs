@@ -0,0 +1,244 @@
+//! Sequence scanner in front of avt's terminal.
+//!
+//! avt's `Vt` parses and executes in one step and silently drops everything its terminal
+//! doesn't model (OSC strings, bells, unknown modes, queries). The wrapper drives avt's
+//! public `Parser` through this scanner instead: each character yields the function for
+//! the terminal to execute, plus any sequence the character completed. Sequence boundaries
+//! come from the parser's own state machine, so they always agree with what avt executed.
+
+use avt::parser::{Function, Parser, State};
+
+/// Longest OSC payload kept, in bytes. Like xterm, a longer string is consumed but
+/// dropped, so an unterminated OSC can't grow the buffer `pending` copies without bound.
+pub(crate) const MAX_OSC_LEN: usize = 1 << 20;
+
+/// A complete sequence observed while scanning
+#[derive(Debug, PartialEq)]
+pub(crate) enum Event {
+    /// BEL outside of a string sequence
+    Bell,
+    /// OSC payload: everything between `ESC ]` and the BEL/ST terminator
+    Osc(String),
+    /// Control sequence, whether or not avt acted on it
+    Csi(Csi),
+    /// Escape sequence that doesn't introduce a CSI/OSC/DCS string
+    Esc(Esc),
+}
+
+/// A parsed control sequence (`CSI [prefix] params [intermediates] final`)
+#[derive(Debug, PartialEq)]
+pub(crate) struct Csi {
+    /// Private marker (`?`, `>`, `<` or `=`) preceding the parameters
+    pub prefix: Option<char>,
+    /// Parameters, each split into its colon-separated parts; empty parts read as 0
+    pub params: Vec<Vec<u16>>,
+    pub intermediates: String,
+    pub final_char: char,
+}
+
+impl Csi {
+    fn parse(body: &str, final_char: char) -> Self {
+        let prefix = body.chars().next().filter(|c| ('<'..='?').contains(c));
+        let body = &body[prefix.map_or(0, char::len_utf8)..];
+        let split = body
+            .find(|c: char| !(c.is_ascii_digit() || c == ';' || c == ':'))
+            .unwrap_or(body.len());
+        let (params, intermediates) = body.split_at(split);
+
+        let params = if params.is_empty() {
+            Vec::new()
+        } else {
+            params
+                .split(';')
+                .map(|param| {
+                    param
+                        .split(':')
+                        .map(|part| part.parse().unwrap_or(0))
+                        .collect()
+                })
+                .collect()
+        };
+
+        Csi {
+            prefix,
+            params,
+            intermediates: intermediates.to_string(),
+            final_char,
+        }
+    }
+
+    /// First part of parameter `i`, or `default` when it is missing or zero
+    pub fn param(&self, i: usize, default: u16) -> u16 {
+        match self.params.get(i).and_then(|parts| parts.first()) {
+            Some(0) | None => default,
+            Some(&value) => value,
+        }
+    }
+}
+
+/// A parsed escape sequence (`ESC [intermediates] final`)
+#[derive(Debug, PartialEq)]
+pub(crate) struct Esc {
+    pub intermediates: String,
+    pub final_char: char,
+}
+
+pub(crate) struct Scanner {
+    parser: Parser,
+    /// Raw text of the sequence in progress, including its introducer
+    raw: String,
+    /// Raw text of the sequence the last event completed, without a string's terminator
+    last: String,
+    /// The OSC in progress exceeded `MAX_OSC_LEN`; `raw` holds only its introducer
+    overflow: bool,
+}
+
+impl Scanner {
+    pub fn new() -> Self {
+        Scanner {
+            parser: Parser::new(),
+            raw: String::new(),
+            last: String::new(),
+            overflow: false,
+        }
+    }
+
+    /// Advance by one character, returning the function for the terminal to execute
+    /// and the sequence this character completed, if any
+    pub fn feed(&mut self, ch: char) -> (Option<Function>, Option<Event>) {
+        let before = self.parser.state;
+        let function = self.parser.feed(ch);
+        let after = self.parser.state;
+
+        (function, self.observe(before, after, ch))
+    }
+
+    /// Raw text of an unfinished sequence, so a dump can resume it after restore
+    pub fn pending(&self) -> &str {
+        &self.raw
+    }
+
+    /// Byte length of the shortest prefix of `s`, at least `min` bytes long, after which the
+    /// parser is back in ground state; None when no such prefix ends outside a sequence.
+    /// Nothing is fed: the scan runs on a scratch parser in the current state.
+    pub fn ground_boundary(&self, s: &str, min: usize) -> Option<usize> {
+        // Transitions depend on the state alone, so the pending parameters aren't needed
+        let mut parser = Parser::new();
+        parser.state = self.parser.state;
+
+        s.char_indices().find_map(|(i, ch)| {
+            parser.feed(ch);
+            let end = i + ch.len_utf8();
+            (end >= min && parser.state == State::Ground).then_some(end)
+        })
+    }
+
+    /// Raw text of the sequence the last returned event completed
+    pub fn last_sequence(&self) -> &str {
+        &self.last
+    }
+
+    fn observe(&mut self, before: State, after: State, ch: char) -> Option<Event> {
+        use State::*;
+
+        if before == OscString && after != OscString {
+            let terminated = matches!(ch, '\x07' | '\x1b' | '\u{9c}') && !self.overflow;
+            let event = terminated.then(|| Event::Osc(self.osc()));
+            if event.is_some() {
+                std::mem::swap(&mut self.raw, &mut self.last);
+            }
+            self.restart(after, ch);
+            return event;
+        }
+
+        if after == Ground {
+            let event = match before {
+                Ground if ch == '\x07' => Some(Event::Bell),
+                CsiEntry | CsiParam | CsiIntermediate if ('@'..='~').contains(&ch) => {
+                    let body = strip_introducer(&self.raw, "\x1b[", '\u{9b}');
+                    Some(Event::Csi(Csi::parse(body, ch)))
+                }
+                Escape | EscapeIntermediate if ('0'..='~').contains(&ch) => Some(Event::Esc(Esc {
+                    intermediates: self.raw.trim_start_matches('\x1b').to_string(),
+                    final_char: ch,
+                })),
+                _ => None,
+            };
+            if event.is_some() {
+                self.raw.push(ch);
+                // Swapping keeps both buffers' allocations
+                std::mem::swap(&mut self.raw, &mut self.last);
+            }
+            self.raw.clear();
+            return event;
+        }
+
+        let starts_sequence = before == Ground
+            || ch == '\x1b'
+            || matches!(
+                ch,
+                '\u{90}' | '\u{98}' | '\u{9b}' | '\u{9d}' | '\u{9e}' | '\u{9f}'
+            );
+
+        if starts_sequence {
+            self.restart(after, ch);
+        } else if ch == '\x07' && before == after && !is_string(before) {
+            // BEL is executed mid-sequence without aborting it
+            return Some(Event::Bell);
+        } else if !ch.is_control() && !is_passthrough(before) && !self.overflow {
+            self.raw.push(ch);
+            if before == State::OscString && self.raw.len() > MAX_OSC_LEN {
+                let payload = strip_introducer(&self.raw, "\x1b]", '\u{9d}').len();
+                self.raw.truncate(self.raw.len() - payload);
+                self.overflow = true;
+            }
+        }
+
+        None
+    }
+
+    /// Begin collecting a new sequence introduced by `ch`, or stop collecting in ground
+    fn restart(&mut self, state: State, ch: char) {
+        self.raw.clear();
+        self.overflow = false;
+
+        if state != State::Ground {
+            self.raw.push(ch);
+        }
+    }
+
+    fn osc(&self) -> String {
+        strip_introducer(&self.raw, "\x1b]", '\u{9d}').to_string()
+    }
+}
+
+/// Whether the parser is inside a string sequence, where BEL is data or a terminator
+fn is_string(state: State) -> bool {
+    use State::*;
+
+    matches!(
+        state,
+        DcsEntry
+            | DcsParam
+            | DcsIntermediate
+            | DcsPassthrough
+            | DcsIgnore
+            | OscString
+            | SosPmApcString
+    )
+}
+
+/// Whether the parser is consuming string data that no event reports (DCS/SOS/PM/APC),
+/// which could be arbitrarily large (e.g. sixel images)
+fn is_passthrough(state: State) -> bool {
+    matches!(
+        state,
+        State::DcsPassthrough | State::DcsIgnore | State::SosPmApcString
+    )
+}
+
+fn strip_introducer<'a>(raw: &'a str, seven_bit: &str, eight_bit: char) -> &'a str {
+    raw.strip_prefix(seven_bit)
+        .or_else(|| raw.strip_prefix(eight_bit))
+        .unwrap_or(raw)
+}
@@ -0,0 +1,123 @@
+//! Find-in-terminal over scrollback and viewport.
+//!
+//! Soft-wrapped rows are joined into logical lines before matching, so a match may run
+//! across a wrap. Every character remembers the cell it came from, which maps matches on
+//! the text back to cell positions regardless of wide characters.
+
+use crate::{is_wrapped, SearchMatch};
+use avt::terminal::Terminal;
+
+/// A character of a logical line and the cell it occupies
+struct Located {
+    ch: char,
+    row: usize,
+    col: usize,
+    width: usize,
+}
+
+/// Logical lines of the terminal, oldest first, without trailing blanks
+fn logical_lines(terminal: &Terminal) -> Vec<Vec<Located>> {
+    let mut lines = Vec::new();
+    let mut current = Vec::new();
+
+    for (row, line) in terminal.lines().enumerate() {
+        for (col, cell) in line.cells().iter().enumerate() {
+            // The right half of a wide character belongs to the cell before it
+            if cell.width() > 0 {
+                current.push(Located {
+                    ch: cell.char(),
+                    row,
+                    col,
+                    width: cell.width(),
+                });
+            }
+        }
+
+        if !is_wrapped(line) {
+            while current.last().is_some_and(|located| located.ch == ' ') {
+                current.pop();
+            }
+            lines.push(std::mem::take(&mut current));
+        }
+    }
+
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+/// Non-overlapping occurrences of `query`, in reading order
+pub(crate) fn search(terminal: &Terminal, query: &str, case_sensitive: bool) -> Vec<SearchMatch> {
+    let fold = |ch: char| {
+        if case_sensitive {
+            ch
+        } else {
+            ch.to_lowercase().next().unwrap_or(ch)
+        }
+    };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let mut matches = Vec::new();
+
+    if query.is_empty() {
+        return matches;
+    }
+
+    for line in logical_lines(terminal) {
+        let mut start = 0;
+
+        while start + query.len() <= line.len() {
+            let found = line[start..start + query.len()]
+                .iter()
+                .zip(&query)
+                .all(|(located, &ch)| fold(located.ch) == ch);
+
+            if found {
+                matches.push(located_match(&line[start..start + query.len()]));
+                start += query.len();
+            } else {
+                start += 1;
+            }
+        }
+    }
+
+    matches
+}
+
+/// Non-overlapping, non-empty matches of `pattern`, in reading order
+#[cfg(feature = "regex")]
+pub(crate) fn search_regex(
+    terminal: &Terminal,
+    pattern: &str,
+    case_sensitive: bool,
+) -> Result<Vec<SearchMatch>, String> {
+    let regex = regex::RegexBuilder::new(pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut matches = Vec::new();
+
+    for line in logical_lines(terminal) {
+        let text: String = line.iter().map(|located| located.ch).collect();
+        let offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+        let index = |byte: usize| offsets.partition_point(|&offset| offset < byte);
+
+        for found in regex.find_iter(&text).filter(|found| !found.is_empty()) {
+            matches.push(located_match(
+                &line[index(found.start())..index(found.end())],
+            ));
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Position of a match covering `chars`, with its length in cells
+fn located_match(chars: &[Located]) -> SearchMatch {
+    SearchMatch {
+        row: chars[0].row,
+        col: chars[0].col,
+        len: chars.iter().map(|located| located.width).sum(),
+    }
+}
@@ -0,0 +1,161 @@
+//! SVG rendering of a terminal snapshot, for thumbnails that scale without blurring.
+
+use crate::palette::{rgb_hex, xterm_rgb};
+use crate::{span_widths, ColorValue, SnapshotSpan, TerminalSnapshot};
+
+/// Cell size and font of the rendering
+pub(crate) struct SvgLayout<'a> {
+    pub cell_width: f64,
+    pub cell_height: f64,
+    pub font_family: &'a str,
+    /// Colors of cells without an explicit fg/bg
+    pub fg: ColorValue,
+    pub bg: ColorValue,
+}
+
+/// Render a snapshot as a standalone SVG document: a background `<rect>` for the whole
+/// grid and for each span with a background, then a `<text>` per span that shows
+/// something, each character placed on its column
+pub(crate) fn snapshot_to_svg(snapshot: &TerminalSnapshot, layout: &SvgLayout) -> String {
+    let width = snapshot.cols as f64 * layout.cell_width;
+    let height = snapshot.rows as f64 * layout.cell_height;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         viewBox=\"0 0 {} {}\" font-family=\"",
+        width, height, width, height
+    );
+    push_escaped(&mut svg, layout.font_family);
+    svg.push_str(&format!(
+        "\" font-size=\"{}\" xml:space=\"preserve\">\n",
+        layout.cell_height * 0.8
+    ));
+    svg.push_str(&format!(
+        "<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+        width,
+        height,
+        svg_color(&layout.bg)
+    ));
+
+    let mut texts = String::new();
+
+    for (row, line) in snapshot.lines.iter().enumerate() {
+        let y = row as f64 * layout.cell_height;
+        let mut col = 0;
+
+        for span in &line.spans {
+            let widths = span_widths(span);
+            let span_cols: usize = widths.iter().map(|&width| width as usize).sum();
+            let x = col as f64 * layout.cell_width;
+
+            if let Some(bg) = &span.bg {
+                svg.push_str(&format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+                    x,
+                    y,
+                    span_cols as f64 * layout.cell_width,
+                    layout.cell_height,
+                    svg_color(bg)
+                ));
+            }
+
+            if shows_text(span) {
+                push_text(&mut texts, span, &widths, col, y, layout);
+            }
+            col += span_cols;
+        }
+    }
+
+    svg.push_str(&texts);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Whether a span draws anything past its background
+fn shows_text(span: &SnapshotSpan) -> bool {
+    !span.text.trim().is_empty() || span.underline || span.strikethrough
+}
+
+/// Append a `<text>` for a span starting at column `col` of the row at `y`
+fn push_text(
+    out: &mut String,
+    span: &SnapshotSpan,
+    widths: &[u8],
+    col: usize,
+    y: f64,
+    layout: &SvgLayout,
+) {
+    // One x per character keeps wide characters and proportional fallbacks on the grid
+    let mut xs = Vec::with_capacity(widths.len());
+    let mut char_col = col;
+    for &width in widths {
+        xs.push((char_col as f64 * layout.cell_width).to_string());
+        char_col += width as usize;
+    }
+
+    let fill = svg_color(span.fg.as_ref().unwrap_or(&layout.fg));
+    out.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" fill=\"{}\"",
+        xs.join(" "),
+        y + layout.cell_height * 0.8,
+        fill
+    ));
+    if span.bold {
+        out.push_str(" font-weight=\"bold\"");
+    }
+    if span.italic {
+        out.push_str(" font-style=\"italic\"");
+    }
+    if span.faint {
+        out.push_str(" opacity=\"0.5\"");
+    }
+
+    let decorations: Vec<&str> = [
+        (span.underline, "underline"),
+        (span.strikethrough, "line-through"),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, name)| *name)
+    .collect();
+
+    if !decorations.is_empty() {
+        out.push_str(&format!(" text-decoration=\"{}\"", decorations.join(" ")));
+    }
+
+    // SVG has no presentation attributes for these, but renderers take the CSS properties
+    let mut underline = Vec::new();
+    if let (true, Some(style)) = (span.underline, span.underline_style) {
+        underline.push(format!("text-decoration-style:{}", style.css()));
+    }
+    if let (true, Some(color)) = (span.underline, &span.underline_color) {
+        underline.push(format!("text-decoration-color:{}", svg_color(color)));
+    }
+    if !underline.is_empty() {
+        out.push_str(&format!(" style=\"{}\"", underline.join(";")));
+    }
+
+    out.push('>');
+    push_escaped(out, &span.text);
+    out.push_str("</text>\n");
+}
+
+/// Resolve a snapshot color to a concrete hex color
+fn svg_color(color: &ColorValue) -> String {
+    match color {
+        ColorValue::Indexed(n) => rgb_hex(xterm_rgb(*n)),
+        ColorValue::Rgb(hex) => hex.clone(),
+    }
+}
+
+/// Append text with XML special characters escaped, quotes included for attributes
+fn push_escaped(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(ch),
+        }
+    }
+}
@@ -8,9 +8,18 @@
  * May represent the visible viewport (from getView) or the full scrollback + viewport (from getAllLines).
  */
 export interface TerminalSnapshot {
+  schema_version: number; // shape version, equal to supportedSchemaVersion() of the build that made it
   cols: number;
   rows: number;
   lines: SnapshotLine[];
+  blink_on?: boolean; // shared slow blink phase, present once tick() was called
+  rapid_blink_on?: boolean; // shared rapid blink phase, present once tick() was called
+  cursor?: CursorPosition & {
+    visible: boolean;
+    shape: 'block' | 'underline' | 'bar';
+    blink: boolean;
+    under: CellInfo | null; // cell under the cursor, null past the end of its line's content
+  }; // get_view_opts with include_cursor
 }
 
 /**
@@ -18,8 +27,9 @@ export interface TerminalSnapshot {
  */
 export interface SnapshotLine {
   spans: SnapshotSpan[];
-  // Note: Line.wrapped is pub(crate) in avt, not accessible from WASM.
-  // Wrapped line merging can be done heuristically if needed later.
+  wrapped?: boolean; // true when the line soft-wraps into the next (omitted when false)
+  line_attr?: 'double-width' | 'double-height-top' | 'double-height-bottom'; // DECDWL/DECDHL (omitted when normal)
+  changed_at?: number; // tick() time the row last changed, from getViewOpts with include_timestamps
 }
 
 /**
@@ -34,11 +44,78 @@ export interface SnapshotSpan {
   faint?: boolean; // mapped from Pen.intensity (Faint)
   italic?: boolean; // from Pen.attrs bit 0 (avt layout)
   underline?: boolean; // from Pen.attrs bit 1 (avt layout)
+  underline_style?: 'single' | 'double' | 'curly' | 'dotted' | 'dashed'; // SGR 4:n, omitted for a plain SGR 4 underline
+  underline_color?: string | number; // SGR 58, same encoding as fg; omitted for the text color
   strikethrough?: boolean; // from Pen.attrs bit 2 (avt layout)
   blink?: boolean; // from Pen.attrs bit 3 (avt layout)
+  rapid_blink?: boolean; // SGR 6; blink is the slow kind (SGR 5)
   inverse?: boolean; // from Pen.attrs bit 4 (avt layout)
+  url?: string; // OSC 8 hyperlink target covering the span
+  protected?: boolean; // DECSCA protection, kept through selective erases (CSI ? J / CSI ? K)
+  widths?: number[]; // column width (1 or 2) per character, only when the span has a wide one
+  col?: number; // grid column the span starts at, from get_view_opts with include_columns
 }
 
+/**
+ * All lines with runs of blank lines collapsed, from getAllLinesRle.
+ * Expand each EmptyRun into `empty_run` copies of `blank_line` to get the getAllLines lines.
+ */
+export interface RleSnapshot {
+  schema_version: number;
+  cols: number;
+  rows: number;
+  blank_line: SnapshotLine; // what an untouched row gives: cols spaces in the default colors
+  lines: (SnapshotLine | EmptyRun)[];
+  blink_on?: boolean;
+  rapid_blink_on?: boolean;
+}
+
+export interface EmptyRun {
+  empty_run: number; // number of consecutive blank_line copies
+}
+
+/**
+ * The viewport as one string per line plus styled runs, from getViewRuns.
+ */
+export interface ViewRuns {
+  schema_version: number;
+  cols: number;
+  rows: number;
+  lines: RunLine[];
+  blink_on?: boolean;
+  rapid_blink_on?: boolean;
+}
+
+export interface RunLine {
+  text: string;
+  runs: TextRun[]; // partition text in order
+}
+
+export interface TextRun {
+  start: number; // character index into the line's text (not bytes or columns)
+  len: number; // characters covered
+  attrs: Omit<SnapshotSpan, 'text' | 'widths' | 'col'>;
+}
+
+/**
+ * Options object of `create_with_options`. Missing fields take the defaults shown.
+ */
+export interface CreateOptions {
+  cols?: number; // default 80
+  rows?: number; // default 24
+  scrollback_limit?: number; // lines kept above the viewport; default 0, no scrollback (as createVt)
+  palette?: string[]; // up to 256 "#RRGGBB" entries, as for set_palette
+  default_fg?: string; // "#RRGGBB"
+  default_bg?: string; // "#RRGGBB"
+  resize_reflow?: true; // false is not supported yet and throws: avt reflows on every width change
+}
+
+/**
+ * Result of feed and the other calls that can fail: `ok` with the value, or the error
+ * message. `rolled_back` is set when panic rollback restored the state before the call.
+ */
+export type FeedResult<T> = { ok: T } | { error: string; rolled_back?: boolean };
+
 /**
  * Cursor position within the terminal viewport.
  */
@@ -54,3 +131,11 @@ export interface TerminalSize {
   cols: number;
   rows: number;
 }
+
+/**
+ * A single viewport cell, as returned by getCell.
+ */
+export type CellInfo = Omit<SnapshotSpan, 'text' | 'widths'> & {
+  char: string;
+  width: number; // 1 or 2 for the cell holding a character, 0 for the right half of a wide one
+};
@@ -89,7 +89,7 @@ describe('vt-wasm error path branches', () => {
     it('createVt with undefined scrollbackLimit (exercises ?? 0 right-side branch)', async () => {
       const { initVt, createVt } = await import('./index.js');
       await initVt();
-      // scrollbackLimit = undefined → uses 0 (unlimited)
+      // scrollbackLimit = undefined → uses 0 (no scrollback)
       const vt = createVt(80, 24, undefined);
       expect(vt).toBeDefined();
       vt.free();